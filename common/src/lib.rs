@@ -8,6 +8,9 @@ use std::{
 
 pub static mut GLOBAL_BUFFER_SIZE: usize = 100000;
 
+mod time;
+pub use time::{format_rfc3339, parse_rfc3339};
+
 use serde_json::Value;
 
 pub fn new_arc_rwlock<T>(t: T) -> Arc<RwLock<T>> {
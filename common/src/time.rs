@@ -0,0 +1,147 @@
+const NANOS_PER_SECOND: i64 = 1_000_000_000;
+
+// days_from_civil returns the days since 1970-01-01 of a proleptic gregorian date
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let yoe = year - era * 400;
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+// civil_from_days returns the proleptic gregorian date of the days since 1970-01-01
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = if days >= 0 { days } else { days - 146096 } / 146097;
+    let doe = days - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn number(s: &str, from: usize, to: usize) -> Option<i64> {
+    let digits = s.get(from..to)?;
+    if !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    digits.parse::<i64>().ok()
+}
+
+/// parse_rfc3339 parses a RFC 3339 timestamp such as the docker json log
+/// `time` field, `2021-02-03T04:05:06.123456789Z`, into unix nanoseconds.
+pub fn parse_rfc3339(s: &str) -> Option<i64> {
+    let s = s.trim();
+    let b = s.as_bytes();
+    if b.len() < 20 || b[4] != b'-' || b[7] != b'-' || b[13] != b':' || b[16] != b':' {
+        return None;
+    }
+    if !matches!(b[10], b'T' | b't' | b' ') {
+        return None;
+    }
+    let (year, month, day) = (number(s, 0, 4)?, number(s, 5, 7)?, number(s, 8, 10)?);
+    let (hour, minute, second) = (number(s, 11, 13)?, number(s, 14, 16)?, number(s, 17, 19)?);
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 60
+    {
+        return None;
+    }
+
+    let mut index = 19;
+    let mut nanos = 0;
+    if b[index] == b'.' {
+        let start = index + 1;
+        index = start;
+        while index < b.len() && b[index].is_ascii_digit() {
+            index += 1;
+        }
+        if index == start {
+            return None;
+        }
+        // digits past nanoseconds are dropped
+        let digits = &s[start..std::cmp::min(index, start + 9)];
+        nanos = digits.parse::<i64>().ok()? * 10i64.pow(9 - digits.len() as u32);
+    }
+
+    let offset = match b.get(index)? {
+        b'Z' | b'z' if index + 1 == b.len() => 0,
+        sign @ (b'+' | b'-') if index + 6 == b.len() && b[index + 3] == b':' => {
+            let offset =
+                number(s, index + 1, index + 3)? * 3600 + number(s, index + 4, index + 6)? * 60;
+            if *sign == b'+' {
+                offset
+            } else {
+                -offset
+            }
+        }
+        _ => return None,
+    };
+
+    let seconds =
+        days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second - offset;
+    Some(seconds * NANOS_PER_SECOND + nanos)
+}
+
+/// format_rfc3339 formats unix nanoseconds as a UTC RFC 3339 timestamp with
+/// nanoseconds, `2021-02-03T04:05:06.123456789Z`.
+pub fn format_rfc3339(nanos: i64) -> String {
+    let seconds = nanos.div_euclid(NANOS_PER_SECOND);
+    let (year, month, day) = civil_from_days(seconds.div_euclid(86400));
+    let second_of_day = seconds.rem_euclid(86400);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:09}Z",
+        year,
+        month,
+        day,
+        second_of_day / 3600,
+        second_of_day / 60 % 60,
+        second_of_day % 60,
+        nanos.rem_euclid(NANOS_PER_SECOND)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_rfc3339, parse_rfc3339};
+
+    #[test]
+    fn it_works() {
+        assert_eq!(parse_rfc3339("1970-01-01T00:00:00Z"), Some(0));
+        assert_eq!(
+            parse_rfc3339("2021-02-03T04:05:06.123456789Z"),
+            Some(1_612_325_106_123_456_789)
+        );
+        assert_eq!(
+            parse_rfc3339("2021-02-03T12:05:06.5+08:00"),
+            Some(1_612_325_106_500_000_000)
+        );
+        assert_eq!(parse_rfc3339("1969-12-31T23:59:59Z"), Some(-1_000_000_000));
+        assert_eq!(
+            parse_rfc3339("2021-02-03 04:05:06Z"),
+            Some(1_612_325_106_000_000_000)
+        );
+        assert!(parse_rfc3339("2021-02-03T04:05:06").is_none());
+        assert!(parse_rfc3339("2021-13-03T04:05:06Z").is_none());
+        assert!(parse_rfc3339("2021-02-03T04:05:06.Z").is_none());
+        assert!(parse_rfc3339("not a time").is_none());
+
+        assert_eq!(format_rfc3339(0), "1970-01-01T00:00:00.000000000Z");
+        assert_eq!(
+            format_rfc3339(1_612_325_106_123_456_789),
+            "2021-02-03T04:05:06.123456789Z"
+        );
+        assert_eq!(
+            format_rfc3339(-1_000_000_000),
+            "1969-12-31T23:59:59.000000000Z"
+        );
+        let nanos = 951_782_400_000_000_000; // a leap day
+        assert_eq!(parse_rfc3339(&format_rfc3339(nanos)), Some(nanos));
+    }
+}
@@ -1,5 +1,8 @@
-use super::Filter;
+use super::{
+    Dedup, Enrich, Envelope, Filter, Limit, Metrics, Parse, Plugin, Redact, Script, StartPosition,
+};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
 pub enum State {
@@ -7,6 +10,13 @@ pub enum State {
     Running,
     Stopped,
 }
+// Mount is a volume of the container, `destination` is the in-container path
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+pub struct Mount {
+    pub source: String,
+    pub destination: String,
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
 pub struct Container {
     pub ns: String,
@@ -22,6 +32,53 @@ pub struct Container {
     pub ips: Vec<String>,
     pub last_offset: i64,
     pub node_name: String,
+    #[serde(default)]
+    pub labels: HashMap<String, String>,
+    #[serde(default)]
+    pub image: String,
+    // the local image id of the container runtime
+    #[serde(default)]
+    pub image_id: String,
+    // the registry digest of the image reported by the kubelet
+    #[serde(default)]
+    pub image_digest: String,
+    #[serde(default)]
+    pub container_id: String,
+    #[serde(default)]
+    pub pod_uid: String,
+    #[serde(default)]
+    pub annotations: HashMap<String, String>,
+    #[serde(default)]
+    pub owner: String,
+    #[serde(default)]
+    pub enrich: Enrich,
+    // in-container glob paths of log files collected besides stdout
+    #[serde(default)]
+    pub paths: Vec<String>,
+    #[serde(default)]
+    pub mounts: Vec<Mount>,
+    // in-container path of the file, empty for the container stdout log
+    #[serde(default)]
+    pub source: String,
+    #[serde(default)]
+    pub start: StartPosition,
+    #[serde(default)]
+    pub redact: Redact,
+    #[serde(default)]
+    pub parse: Parse,
+    #[serde(default)]
+    pub limit: Limit,
+    #[serde(default)]
+    pub dedup: Dedup,
+    #[serde(default)]
+    pub script: Script,
+    // webassembly plugins run in order after the script
+    #[serde(default)]
+    pub plugins: Vec<Plugin>,
+    #[serde(default)]
+    pub metrics: Metrics,
+    #[serde(default)]
+    pub envelope: Envelope,
 }
 
 impl Container {
@@ -61,6 +118,17 @@ impl Container {
         self.offset = other.offset.clone();
         self.node_name = other.node_name.clone();
         self.service_name = other.service_name.clone();
+        self.enrich = other.enrich.clone();
+        self.paths = other.paths.clone();
+        self.start = other.start.clone();
+        self.redact = other.redact.clone();
+        self.parse = other.parse.clone();
+        self.limit = other.limit.clone();
+        self.dedup = other.dedup.clone();
+        self.script = other.script.clone();
+        self.plugins = other.plugins.clone();
+        self.metrics = other.metrics.clone();
+        self.envelope = other.envelope.clone();
         if other.ips.len() > 0 {
             self.ips.clone_from(&other.ips)
         }
@@ -68,6 +136,33 @@ impl Container {
         self
     }
 
+    /// merge_pod_meta takes the pod metadata of the kubelet from other, it
+    /// returns true when the pod is annotated with an output and is now
+    /// collected without a task.
+    pub fn merge_pod_meta(&mut self, other: &Container) -> bool {
+        if !other.ips.is_empty() {
+            self.ips.clone_from(&other.ips);
+        }
+        if self.service_name.is_empty() {
+            self.service_name = other.service_name.clone();
+        }
+        for (k, v) in other.labels.iter() {
+            self.labels.entry(k.clone()).or_insert_with(|| v.clone());
+        }
+        self.annotations = other.annotations.clone();
+        self.owner = other.owner.clone();
+        if !other.image_digest.is_empty() {
+            self.image_digest = other.image_digest.clone();
+        }
+        if !other.is_upload() || self.is_upload() {
+            return false;
+        }
+        self.output = other.output.clone();
+        self.filter.expr = other.filter.expr.clone();
+        self.upload().state_running();
+        true
+    }
+
     pub fn is_running(&self) -> bool {
         self.state == State::Running
     }
@@ -109,11 +204,33 @@ impl Default for Container {
             filter: Filter {
                 max_length: 0,
                 expr: "".to_string(),
+                min_level: "".to_string(),
             },
             output: "".to_string(),
             ips: Vec::new(),
             last_offset: 0,
             node_name: "".to_string(),
+            labels: HashMap::new(),
+            image: "".to_string(),
+            image_id: "".to_string(),
+            image_digest: "".to_string(),
+            container_id: "".to_string(),
+            pod_uid: "".to_string(),
+            annotations: HashMap::new(),
+            owner: "".to_string(),
+            enrich: Enrich::default(),
+            paths: Vec::new(),
+            mounts: Vec::new(),
+            source: "".to_string(),
+            start: StartPosition::Resume,
+            redact: Redact::default(),
+            parse: Parse::default(),
+            limit: Limit::default(),
+            dedup: Dedup::default(),
+            script: Script::default(),
+            plugins: Vec::new(),
+            metrics: Metrics::default(),
+            envelope: Envelope::default(),
         }
     }
 }
@@ -16,6 +16,8 @@ pub enum Event {
     Delete,
     #[strum(serialize = "offset")]
     IncrOffset,
+    #[strum(serialize = "pod_meta")]
+    PodMeta,
     #[strum(serialize = "close")]
     Close,
 }
@@ -135,6 +137,18 @@ impl MemDatabase {
                         }
                         m.remove(&container.path);
                     }
+                    Event::PodMeta => {
+                        if let Some(inner) = m.get_mut(&container.path) {
+                            if inner.merge_pod_meta(&container) {
+                                match t_dispatchers.write() {
+                                    Ok(mut dispatch) => dispatch.dispatch_open_event(inner),
+                                    Err(e) => {
+                                        eprintln!("MemDatabase thread dispath open event failed, error:{:?}",e)
+                                    }
+                                }
+                            }
+                        }
+                    }
                     Event::IncrOffset => {
                         if let Some(inner) = m.get_mut(&container.path) {
                             inner.last_offset = container.last_offset;
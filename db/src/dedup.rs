@@ -0,0 +1,32 @@
+use serde::{Deserialize, Serialize};
+
+/// Dedup collapses consecutive identical lines of a container into the first
+/// one with a `repeat_count`, a run is shipped when a different line is read
+/// or `window` seconds after its first line. `normalize_numbers` compares the
+/// lines with their numbers masked, so retries with changing ids collapse.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct Dedup {
+    pub window: u64,
+    pub normalize_numbers: bool,
+}
+
+impl Dedup {
+    pub fn is_enabled(&self) -> bool {
+        self.window > 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Dedup;
+
+    #[test]
+    fn it_works() {
+        assert!(!Dedup::default().is_enabled());
+        let dedup =
+            serde_json::from_str::<Dedup>(r#"{"window":5,"normalize_numbers":true}"#).unwrap();
+        assert!(dedup.is_enabled());
+        assert!(dedup.normalize_numbers);
+    }
+}
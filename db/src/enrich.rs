@@ -0,0 +1,133 @@
+use super::{glob_match, Container};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// Enrich selects the container metadata added to every record of a task.
+/// Label names are matched with `label_allow`/`label_deny` glob patterns,
+/// an empty allow list keeps every label that is not denied.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct Enrich {
+    pub labels: bool,
+    pub label_allow: Vec<String>,
+    pub label_deny: Vec<String>,
+    pub image: bool,
+    pub container_id: bool,
+    pub pod_uid: bool,
+    pub node_name: bool,
+    pub annotations: bool,
+    pub owner: bool,
+}
+
+impl Enrich {
+    fn label_pass(&self, name: &str) -> bool {
+        if self.label_deny.iter().any(|p| glob_match(p, name)) {
+            return false;
+        }
+        self.label_allow.is_empty() || self.label_allow.iter().any(|p| glob_match(p, name))
+    }
+
+    /// fields returns the enrichment fields of the container, it is meant to be
+    /// computed once per container and reused for every line.
+    pub fn fields(&self, container: &Container) -> Map<String, Value> {
+        let mut fields = Map::new();
+        if self.labels {
+            let labels = container
+                .labels
+                .iter()
+                .filter(|(k, _)| self.label_pass(k))
+                .map(|(k, v)| (k.clone(), Value::String(v.clone())))
+                .collect::<Map<String, Value>>();
+            fields.insert("labels".to_string(), Value::Object(labels));
+        }
+        if self.image {
+            fields.insert("image".to_string(), Value::String(container.image.clone()));
+            fields.insert(
+                "imageId".to_string(),
+                Value::String(container.image_id.clone()),
+            );
+            // the digest is known once the kubelet reported the pod
+            if !container.image_digest.is_empty() {
+                fields.insert(
+                    "imageDigest".to_string(),
+                    Value::String(container.image_digest.clone()),
+                );
+            }
+        }
+        if self.container_id {
+            fields.insert(
+                "containerId".to_string(),
+                Value::String(container.container_id.clone()),
+            );
+        }
+        if self.pod_uid {
+            fields.insert(
+                "podUid".to_string(),
+                Value::String(container.pod_uid.clone()),
+            );
+        }
+        if self.node_name {
+            fields.insert(
+                "nodeName".to_string(),
+                Value::String(container.node_name.clone()),
+            );
+        }
+        if self.annotations {
+            let annotations = container
+                .annotations
+                .iter()
+                .map(|(k, v)| (k.clone(), Value::String(v.clone())))
+                .collect::<Map<String, Value>>();
+            fields.insert("annotations".to_string(), Value::Object(annotations));
+        }
+        if self.owner {
+            fields.insert("owner".to_string(), Value::String(container.owner.clone()));
+        }
+        fields
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Enrich;
+    use crate::Container;
+
+    #[test]
+    fn it_works() {
+        let mut container = Container {
+            image: "nginx:1.19".to_string(),
+            image_id: "sha256:80d28bedfe5d".to_string(),
+            container_id: "58044a726890".to_string(),
+            ..Default::default()
+        };
+        container
+            .labels
+            .insert("app".to_string(), "web".to_string());
+        container
+            .labels
+            .insert("io.kubernetes.pod.uid".to_string(), "c7621e69".to_string());
+
+        let enrich = Enrich {
+            labels: true,
+            label_deny: vec!["io.kubernetes.*".to_string()],
+            image: true,
+            ..Default::default()
+        };
+        let fields = enrich.fields(&container);
+        assert_eq!(fields["labels"]["app"], "web");
+        assert!(fields["labels"].get("io.kubernetes.pod.uid").is_none());
+        assert_eq!(fields["image"], "nginx:1.19");
+        assert_eq!(fields["imageId"], "sha256:80d28bedfe5d");
+        assert!(fields.get("imageDigest").is_none());
+        assert!(fields.get("containerId").is_none());
+
+        assert!(Enrich::default().fields(&container).is_empty());
+
+        container.image_digest =
+            "sha256:4cf620a5c81390ee209398ecc18e5fb9dd0f5155cd82adcbae532fec94006fb9".to_string();
+        assert_eq!(
+            enrich.fields(&container)["imageDigest"],
+            container.image_digest.as_str()
+        );
+    }
+}
@@ -0,0 +1,66 @@
+use serde::{Deserialize, Serialize};
+
+/// EnvelopeSchema is the shape of the records written to an output.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum EnvelopeSchema {
+    // the message with the `custom` block of the container, `version` v1.0.0
+    Legacy,
+    // the message, its fields and the container metadata at the top level
+    Flat,
+    // Elastic Common Schema
+    Ecs,
+    // the OpenTelemetry log data model
+    Otel,
+    // a CloudEvents 1.0 structured event
+    CloudEvents,
+    // the `template` of the envelope
+    Template,
+}
+
+impl EnvelopeSchema {
+    /// parse returns the schema of a name, the template is only set by tasks.
+    pub fn parse(name: &str) -> Option<EnvelopeSchema> {
+        match name.trim().to_lowercase().as_str() {
+            "legacy" => Some(EnvelopeSchema::Legacy),
+            "flat" => Some(EnvelopeSchema::Flat),
+            "ecs" => Some(EnvelopeSchema::Ecs),
+            "otel" => Some(EnvelopeSchema::Otel),
+            "cloudevents" => Some(EnvelopeSchema::CloudEvents),
+            _ => None,
+        }
+    }
+}
+
+/// Envelope selects the schema of the records of a task, without a `schema`
+/// the schema of the output is used, legacy unless configured. `template` is
+/// a json document whose `${path}` strings are replaced by the record values.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct Envelope {
+    pub schema: Option<EnvelopeSchema>,
+    pub template: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Envelope, EnvelopeSchema};
+
+    #[test]
+    fn it_works() {
+        let envelope = serde_json::from_str::<Envelope>(
+            r#"{"schema":"template","template":"{\"msg\":\"${message}\"}"}"#,
+        )
+        .unwrap();
+        assert_eq!(envelope.schema, Some(EnvelopeSchema::Template));
+        assert_eq!(Envelope::default().schema, None);
+        assert_eq!(
+            serde_json::from_str::<Envelope>(r#"{"schema":"cloudevents"}"#)
+                .unwrap()
+                .schema,
+            Some(EnvelopeSchema::CloudEvents)
+        );
+        assert_eq!(EnvelopeSchema::parse(" ECS"), Some(EnvelopeSchema::Ecs));
+        assert_eq!(EnvelopeSchema::parse("template"), None);
+    }
+}
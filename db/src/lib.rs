@@ -3,9 +3,33 @@ extern crate lazy_static;
 mod database;
 
 mod container;
-pub use container::{Container, ContainerList, ContainerListMarshaller, GetContainer, State};
+mod dedup;
+mod enrich;
+mod envelope;
+mod limit;
+mod metric;
+mod parse;
+mod plugin;
+mod position;
+mod redact;
+mod script;
+mod selector;
+pub use container::{
+    Container, ContainerList, ContainerListMarshaller, GetContainer, Mount, State,
+};
 use database::Message;
+pub use dedup::Dedup;
+pub use enrich::Enrich;
+pub use envelope::{Envelope, EnvelopeSchema};
 use event::Listener;
+pub use limit::{Limit, SampleBy};
+pub use metric::{MetricKind, MetricRule, Metrics};
+pub use parse::{Parse, ParseFailure};
+pub use plugin::{Plugin, PluginFailure};
+pub use position::StartPosition;
+pub use redact::{Redact, RedactRule};
+pub use script::Script;
+pub use selector::{evict_pod_regex, glob_match, Selector};
 
 pub use common::new_arc_rwlock;
 pub use database::Event;
@@ -42,6 +66,17 @@ pub fn update(container: &Container) {
         .unwrap();
 }
 
+/// update_pod_meta sends the pod metadata of a stored container, see
+/// `Container::merge_pod_meta`.
+pub fn update_pod_meta(container: &Container) {
+    MEM.tx
+        .send(Message {
+            event: Event::PodMeta,
+            container: container.clone(),
+        })
+        .unwrap();
+}
+
 pub fn insert(container: &Container) {
     MEM.tx
         .send(Message {
@@ -74,6 +109,31 @@ pub fn all_to_json() -> ContainerListMarshaller {
     )
 }
 
+// pods_to_json lists the containers of pods, without the files collected inside them
+pub fn pods_to_json() -> ContainerListMarshaller {
+    ContainerListMarshaller(
+        MEM.containers
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(_, v)| v.source.is_empty())
+            .map(|(_, v)| v.clone())
+            .collect::<Vec<Container>>(),
+    )
+}
+
+pub fn ns_to_json(ns: &str) -> ContainerListMarshaller {
+    ContainerListMarshaller(
+        MEM.containers
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(_, v)| v.ns == ns && v.source.is_empty())
+            .map(|(_, v)| v.clone())
+            .collect::<Vec<Container>>(),
+    )
+}
+
 pub fn close() {
     MEM.tx
         .send(Message {
@@ -115,7 +175,43 @@ pub fn get_container_slice_by_pod(ns: &str, pod_name: &str) -> Vec<(String, Cont
         .read()
         .unwrap()
         .iter()
-        .filter(|(_, v)| v.ns == ns && v.pod_name == pod_name)
+        .filter(|(_, v)| v.ns == ns && v.pod_name == pod_name && v.source.is_empty())
+        .map(|(uuid, container)| (uuid.clone(), container.clone()))
+        .collect::<Vec<(String, Container)>>();
+    result
+}
+
+pub fn get_container_slice_by_pod_uid(pod_uid: &str) -> Vec<(String, Container)> {
+    let result = MEM
+        .containers
+        .read()
+        .unwrap()
+        .iter()
+        .filter(|(_, v)| v.pod_uid == pod_uid)
+        .map(|(uuid, container)| (uuid.clone(), container.clone()))
+        .collect::<Vec<(String, Container)>>();
+    result
+}
+
+pub fn get_container_slice_by_ip(ip: &str) -> Vec<(String, Container)> {
+    let result = MEM
+        .containers
+        .read()
+        .unwrap()
+        .iter()
+        .filter(|(_, v)| v.source.is_empty() && v.ips.iter().any(|v_ip| v_ip == ip))
+        .map(|(uuid, container)| (uuid.clone(), container.clone()))
+        .collect::<Vec<(String, Container)>>();
+    result
+}
+
+pub fn get_container_slice_by_selector(selector: &Selector) -> Vec<(String, Container)> {
+    let result = MEM
+        .containers
+        .read()
+        .unwrap()
+        .iter()
+        .filter(|(_, v)| v.source.is_empty() && selector.matches(v))
         .map(|(uuid, container)| (uuid.clone(), container.clone()))
         .collect::<Vec<(String, Container)>>();
     result
@@ -186,4 +282,8 @@ where
 pub struct Filter {
     pub max_length: i64,
     pub expr: String,
+    // messages below this level, such as "warn", are dropped, messages
+    // without a detected level are kept
+    #[serde(default)]
+    pub min_level: String,
 }
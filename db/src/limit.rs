@@ -0,0 +1,53 @@
+use serde::{Deserialize, Serialize};
+
+/// SampleBy selects how `sample_rate` keeps lines, `hash` keeps or drops the
+/// same message every time.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SampleBy {
+    #[default]
+    Random,
+    Hash,
+}
+
+/// Limit caps the volume shipped for every container of a task, 0 disables a
+/// limit. `sample_rate` is the kept fraction of the lines in (0, 1), error
+/// lines are never sampled out. The suppressed lines are reported every
+/// `report_interval` seconds.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct Limit {
+    pub lines_per_sec: u64,
+    pub bytes_per_sec: u64,
+    pub sample_rate: f64,
+    pub sample_by: SampleBy,
+    pub report_interval: u64,
+}
+
+impl Limit {
+    pub fn is_enabled(&self) -> bool {
+        self.lines_per_sec > 0 || self.bytes_per_sec > 0 || self.is_sampled()
+    }
+
+    pub fn is_sampled(&self) -> bool {
+        self.sample_rate > 0.0 && self.sample_rate < 1.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Limit, SampleBy};
+
+    #[test]
+    fn it_works() {
+        assert!(!Limit::default().is_enabled());
+
+        let limit =
+            serde_json::from_str::<Limit>(r#"{"sample_rate":0.1,"sample_by":"hash"}"#).unwrap();
+        assert!(limit.is_enabled());
+        assert_eq!(limit.sample_by, SampleBy::Hash);
+
+        let limit = serde_json::from_str::<Limit>(r#"{"sample_rate":1.0}"#).unwrap();
+        assert!(!limit.is_enabled());
+    }
+}
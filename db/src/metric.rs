@@ -0,0 +1,60 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum MetricKind {
+    #[default]
+    Counter,
+    Histogram,
+}
+
+/// MetricRule derives a metric from the records matching `expr`, an empty
+/// `expr` matches every record. `labels` maps label names to record fields,
+/// a counter adds 1 or the `value` field and a histogram observes the `value`
+/// field in `buckets`.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct MetricRule {
+    pub name: String,
+    pub help: String,
+    pub kind: MetricKind,
+    pub expr: String,
+    pub labels: BTreeMap<String, String>,
+    pub value: String,
+    pub buckets: Vec<f64>,
+}
+
+/// Metrics are the metric rules of a task, aggregated in the agent and
+/// exposed on `/metrics`. With an `output` the metrics of the rules are also
+/// written to it every `interval` seconds.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct Metrics {
+    pub rules: Vec<MetricRule>,
+    pub output: String,
+    pub interval: u64,
+}
+
+impl Metrics {
+    pub fn is_enabled(&self) -> bool {
+        !self.rules.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MetricKind, Metrics};
+
+    #[test]
+    fn it_works() {
+        assert!(!Metrics::default().is_enabled());
+        let metrics = serde_json::from_str::<Metrics>(
+            r#"{"rules":[{"name":"http_request_seconds","kind":"histogram","labels":{"service":"container.service_name"},"value":"duration"}],"output":"metrics_topic"}"#,
+        )
+        .unwrap();
+        assert!(metrics.is_enabled());
+        assert_eq!(metrics.rules[0].kind, MetricKind::Histogram);
+        assert_eq!(metrics.rules[0].labels["service"], "container.service_name");
+    }
+}
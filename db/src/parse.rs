@@ -0,0 +1,46 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// ParseFailure is what happens to a line matching none of the patterns.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ParseFailure {
+    // ship the raw message
+    #[default]
+    Keep,
+    // ship the raw message tagged `_grokparsefailure`
+    Tag,
+    Drop,
+}
+
+/// Parse extracts fields from plain-text messages, `patterns` are grok
+/// patterns such as `%{NGINX_ACCESS}` or regexes with named captures, tried
+/// in order. `definitions` adds or overrides named grok patterns.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct Parse {
+    pub patterns: Vec<String>,
+    pub definitions: HashMap<String, String>,
+    pub on_failure: ParseFailure,
+}
+
+impl Parse {
+    pub fn is_enabled(&self) -> bool {
+        !self.patterns.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Parse, ParseFailure};
+
+    #[test]
+    fn it_works() {
+        let parse =
+            serde_json::from_str::<Parse>(r#"{"patterns":["%{NGINX_ACCESS}"],"on_failure":"tag"}"#)
+                .unwrap();
+        assert!(parse.is_enabled());
+        assert_eq!(parse.on_failure, ParseFailure::Tag);
+        assert_eq!(Parse::default().on_failure, ParseFailure::Keep);
+    }
+}
@@ -0,0 +1,40 @@
+use serde::{Deserialize, Serialize};
+
+/// PluginFailure is what happens to a record when its plugin fails, traps or
+/// runs out of fuel.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum PluginFailure {
+    // ship the record as the plugin got it
+    #[default]
+    Bypass,
+    Drop,
+}
+
+/// Plugin is a WebAssembly filter or transform run on every message of a
+/// task, `name` loads `<name>.wasm` from the plugin directory of the agent and
+/// `path` loads a module file in that directory. A call gets `fuel` units and the module memory
+/// is capped to `max_memory` bytes, 0 keeps the default.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct Plugin {
+    pub name: String,
+    pub path: String,
+    pub fuel: u64,
+    pub max_memory: usize,
+    pub on_failure: PluginFailure,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Plugin, PluginFailure};
+
+    #[test]
+    fn it_works() {
+        let plugin =
+            serde_json::from_str::<Plugin>(r#"{"name":"mask","on_failure":"drop"}"#).unwrap();
+        assert_eq!(plugin.name, "mask");
+        assert_eq!(plugin.on_failure, PluginFailure::Drop);
+        assert_eq!(Plugin::default().on_failure, PluginFailure::Bypass);
+    }
+}
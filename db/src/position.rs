@@ -0,0 +1,90 @@
+use common::{format_rfc3339, parse_rfc3339};
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+
+/// StartPosition is where the reader of a task starts in a file it opens for
+/// the first time, it is written as `beginning`, `end`, `offset:N`,
+/// `last_lines:N` or `since:<RFC 3339 timestamp>`. `Resume`, the empty
+/// string, starts at the offset of the task.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(try_from = "String", into = "String")]
+pub enum StartPosition {
+    #[default]
+    Resume,
+    Beginning,
+    End,
+    Offset(u64),
+    LastLines(u64),
+    // unix nanoseconds, compared with the docker `time` field
+    Since(i64),
+}
+
+impl TryFrom<String> for StartPosition {
+    type Error = String;
+
+    fn try_from(position: String) -> Result<Self, Self::Error> {
+        let (kind, value) = match position.find(':') {
+            Some(index) => (&position[..index], &position[index + 1..]),
+            None => (position.as_str(), ""),
+        };
+        let number = || {
+            value
+                .parse::<u64>()
+                .map_err(|e| format!("invalid start position {:?}: {}", position, e))
+        };
+        match kind {
+            "" => Ok(StartPosition::Resume),
+            "beginning" => Ok(StartPosition::Beginning),
+            "end" => Ok(StartPosition::End),
+            "offset" => Ok(StartPosition::Offset(number()?)),
+            "last_lines" => Ok(StartPosition::LastLines(number()?)),
+            "since" => match parse_rfc3339(value) {
+                Some(nanos) => Ok(StartPosition::Since(nanos)),
+                None => Err(format!("invalid start position timestamp {:?}", value)),
+            },
+            _ => Err(format!("unknown start position {:?}", position)),
+        }
+    }
+}
+
+impl From<StartPosition> for String {
+    fn from(position: StartPosition) -> Self {
+        match position {
+            StartPosition::Resume => "".to_string(),
+            StartPosition::Beginning => "beginning".to_string(),
+            StartPosition::End => "end".to_string(),
+            StartPosition::Offset(offset) => format!("offset:{}", offset),
+            StartPosition::LastLines(lines) => format!("last_lines:{}", lines),
+            StartPosition::Since(nanos) => format!("since:{}", format_rfc3339(nanos)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StartPosition;
+
+    #[test]
+    fn it_works() {
+        let parse = |s: &str| serde_json::from_str::<StartPosition>(&format!("{:?}", s));
+        assert_eq!(parse("").unwrap(), StartPosition::Resume);
+        assert_eq!(parse("end").unwrap(), StartPosition::End);
+        assert_eq!(parse("offset:1024").unwrap(), StartPosition::Offset(1024));
+        assert_eq!(
+            parse("last_lines:10000").unwrap(),
+            StartPosition::LastLines(10000)
+        );
+        assert_eq!(
+            parse("since:2021-02-03T04:05:06Z").unwrap(),
+            StartPosition::Since(1_612_325_106_000_000_000)
+        );
+        assert!(parse("last_lines:ten").is_err());
+        assert!(parse("since:yesterday").is_err());
+        assert!(parse("middle").is_err());
+
+        let since = StartPosition::Since(1_612_325_106_123_456_789);
+        let json = serde_json::to_string(&since).unwrap();
+        assert_eq!(json, r#""since:2021-02-03T04:05:06.123456789Z""#);
+        assert_eq!(serde_json::from_str::<StartPosition>(&json).unwrap(), since);
+    }
+}
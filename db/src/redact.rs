@@ -0,0 +1,47 @@
+use serde::{Deserialize, Serialize};
+
+/// RedactRule masks the matches of `pattern`, `replacement` may refer to the
+/// groups of the pattern as `$1` or `${name}`.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct RedactRule {
+    pub name: String,
+    pub pattern: String,
+    pub replacement: String,
+}
+
+/// Redact selects the masking applied to the messages of a task before they
+/// leave the node. `builtins` names detectors of the filter crate such as
+/// `email` or `credit_card`, `fields` masks the values of these keys of json
+/// messages.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct Redact {
+    pub builtins: Vec<String>,
+    pub rules: Vec<RedactRule>,
+    pub fields: Vec<String>,
+}
+
+impl Redact {
+    pub fn is_enabled(&self) -> bool {
+        !self.builtins.is_empty() || !self.rules.is_empty() || !self.fields.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Redact;
+
+    #[test]
+    fn it_works() {
+        assert!(!Redact::default().is_enabled());
+
+        let redact = serde_json::from_str::<Redact>(
+            r#"{"builtins":["email"],"rules":[{"name":"order","pattern":"order-(\\d+)","replacement":"order-***"}]}"#,
+        )
+        .unwrap();
+        assert!(redact.is_enabled());
+        assert_eq!(redact.rules[0].name, "order");
+        assert!(redact.fields.is_empty());
+    }
+}
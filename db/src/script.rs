@@ -0,0 +1,38 @@
+use serde::{Deserialize, Serialize};
+
+/// Script is a Rhai script run on every record of a task, it gets the record
+/// as `record` and the container metadata as `container`. The limits bound the
+/// operations, the time in milliseconds and the size of the strings and
+/// collections of one run, 0 keeps the default.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct Script {
+    pub source: String,
+    pub max_operations: u64,
+    pub timeout_ms: u64,
+    pub max_string_size: usize,
+    pub max_collection_size: usize,
+}
+
+impl Script {
+    pub fn is_enabled(&self) -> bool {
+        !self.source.trim().is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Script;
+
+    #[test]
+    fn it_works() {
+        assert!(!Script::default().is_enabled());
+        let script = serde_json::from_str::<Script>(
+            r#"{"source":"record.fields.team = \"a\";","timeout_ms":5}"#,
+        )
+        .unwrap();
+        assert!(script.is_enabled());
+        assert_eq!(script.timeout_ms, 5);
+        assert_eq!(script.max_operations, 0);
+    }
+}
@@ -0,0 +1,218 @@
+use super::Container;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::RwLock;
+
+lazy_static! {
+    // the compiled pod regexes of the selectors, by pattern, an invalid
+    // pattern is kept as none so it is compiled and logged once
+    static ref POD_REGEXES: RwLock<HashMap<String, Option<Regex>>> =
+        RwLock::new(HashMap::new());
+}
+
+// pod_regex returns the compiled pod regex of a pattern, compiled once
+fn pod_regex(pattern: &str) -> Option<Regex> {
+    if let Ok(regexes) = POD_REGEXES.read() {
+        if let Some(re) = regexes.get(pattern) {
+            return re.clone();
+        }
+    }
+    let re = match Regex::new(pattern) {
+        Ok(re) => Some(re),
+        Err(e) => {
+            eprintln!("[ERROR] selector pod regex {:?} invalid: {:?}", pattern, e);
+            None
+        }
+    };
+    if let Ok(mut regexes) = POD_REGEXES.write() {
+        regexes.insert(pattern.to_string(), re.clone());
+    }
+    re
+}
+
+/// evict_pod_regex drops the compiled pod regex of a pattern once no
+/// selector task uses it anymore.
+pub fn evict_pod_regex(pattern: &str) {
+    if let Ok(mut regexes) = POD_REGEXES.write() {
+        regexes.remove(pattern);
+    }
+}
+
+/// Selector describes a set of containers a task collects from. An empty
+/// field matches everything, so `Selector::default()` selects every container.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+#[serde(default)]
+pub struct Selector {
+    pub namespaces: Vec<String>,
+    pub exclude_namespaces: Vec<String>,
+    // glob pattern, supports `*` and `?`
+    pub pod_name: String,
+    pub pod_regex: String,
+    pub container: String,
+    pub labels: BTreeMap<String, String>,
+}
+
+impl Selector {
+    /// key returns a stable identifier used to store the selector task.
+    pub fn key(&self) -> String {
+        format!(
+            "selector:ns={};exclude={};pod={};regex={};container={};labels={}",
+            self.namespaces.join(","),
+            self.exclude_namespaces.join(","),
+            self.pod_name,
+            self.pod_regex,
+            self.container,
+            self.labels
+                .iter()
+                .map(|(k, v)| format!("{}={}", k, v))
+                .collect::<Vec<String>>()
+                .join(",")
+        )
+    }
+
+    /// validate compiles the pod regex of the selector.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.pod_regex.is_empty() {
+            return Ok(());
+        }
+        Regex::new(&self.pod_regex)
+            .map(|_| ())
+            .map_err(|e| format!("selector pod regex {:?} invalid: {}", self.pod_regex, e))
+    }
+
+    /// matches_namespace tells whether the selector collects from the
+    /// namespace, an empty `namespaces` collects from all of them.
+    pub fn matches_namespace(&self, ns: &str) -> bool {
+        if self.exclude_namespaces.iter().any(|item| item == ns) {
+            return false;
+        }
+        self.namespaces.is_empty() || self.namespaces.iter().any(|item| item == "*" || item == ns)
+    }
+
+    pub fn matches(&self, container: &Container) -> bool {
+        // host files and journal records do not belong to a pod
+        if container.pod_name.is_empty() {
+            return false;
+        }
+
+        if !self.matches_namespace(&container.ns) {
+            return false;
+        }
+
+        if !self.pod_name.is_empty() && !glob_match(&self.pod_name, &container.pod_name) {
+            return false;
+        }
+
+        if !self.pod_regex.is_empty() {
+            match pod_regex(&self.pod_regex) {
+                Some(re) if re.is_match(&container.pod_name) => {}
+                _ => return false,
+            }
+        }
+
+        if !self.container.is_empty() && !glob_match(&self.container, &container.container) {
+            return false;
+        }
+
+        self.labels
+            .iter()
+            .all(|(k, v)| container.labels.get(k) == Some(v))
+    }
+}
+
+/// glob_match matches `s` against a shell style pattern with `*` and `?`.
+pub fn glob_match(pattern: &str, s: &str) -> bool {
+    let p = pattern.chars().collect::<Vec<char>>();
+    let s = s.chars().collect::<Vec<char>>();
+    let (mut pi, mut si) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+
+    while si < s.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == s[si]) {
+            pi += 1;
+            si += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            star = Some((pi, si));
+            pi += 1;
+        } else if let Some((star_pi, star_si)) = star {
+            pi = star_pi + 1;
+            si = star_si + 1;
+            star = Some((star_pi, star_si + 1));
+        } else {
+            return false;
+        }
+    }
+
+    while pi < p.len() && p[pi] == '*' {
+        pi += 1;
+    }
+    pi == p.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{evict_pod_regex, glob_match, Selector, POD_REGEXES};
+    use crate::Container;
+
+    #[test]
+    fn glob_it_works() {
+        assert!(glob_match("web-*", "web-7d9f-abc"));
+        assert!(glob_match("web-?", "web-1"));
+        assert!(glob_match("*", ""));
+        assert!(!glob_match("web-?", "web-12"));
+        assert!(!glob_match("api-*", "web-1"));
+    }
+
+    #[test]
+    fn selector_it_works() {
+        let mut container = Container {
+            ns: "default".to_string(),
+            pod_name: "web-7d9f-abc".to_string(),
+            container: "nginx".to_string(),
+            ..Default::default()
+        };
+        container
+            .labels
+            .insert("app".to_string(), "web".to_string());
+
+        let mut selector = Selector {
+            namespaces: vec!["default".to_string(), "prod".to_string()],
+            pod_name: "web-*".to_string(),
+            ..Default::default()
+        };
+        selector.labels.insert("app".to_string(), "web".to_string());
+        assert!(selector.matches(&container));
+
+        selector.pod_regex = "^api-".to_string();
+        assert!(!selector.matches(&container));
+
+        selector.pod_regex = "^web-[0-9a-f]+-".to_string();
+        assert!(selector.matches(&container));
+        assert!(selector.validate().is_ok());
+        assert!(POD_REGEXES.read().unwrap().contains_key("^web-[0-9a-f]+-"));
+        evict_pod_regex("^web-[0-9a-f]+-");
+        assert!(!POD_REGEXES.read().unwrap().contains_key("^web-[0-9a-f]+-"));
+
+        selector.pod_regex = "web-(".to_string();
+        assert!(!selector.matches(&container));
+        assert!(selector.validate().is_err());
+
+        selector.pod_regex = "".to_string();
+        selector.namespaces = vec!["kube-system".to_string()];
+        assert!(!selector.matches(&container));
+
+        selector.namespaces = vec!["*".to_string()];
+        assert!(selector.matches(&container));
+        selector.exclude_namespaces = vec!["default".to_string()];
+        assert!(!selector.matches(&container));
+
+        assert!(Selector::default().matches(&container));
+
+        let host_file = Container {
+            source: "/var/log/kubelet.log".to_string(),
+            ..Default::default()
+        };
+        assert!(!Selector::default().matches(&host_file));
+    }
+}
@@ -0,0 +1,617 @@
+use super::pipeline::Entry;
+use super::{custom_fields, encode_entry, encode_record};
+use common::format_rfc3339;
+use db::{Container, EnvelopeSchema};
+use filter::{container_metadata, resolve};
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::RwLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const ECS_VERSION: &str = "8.11.0";
+const CLOUDEVENTS_LOG_TYPE: &str = "harvest.log";
+const CLOUDEVENTS_RECORD_TYPE: &str = "harvest.record";
+
+lazy_static! {
+    // the schema of the records written to an output, by output
+    static ref OUTPUT_ENVELOPES: RwLock<HashMap<String, EnvelopeSchema>> =
+        RwLock::new(HashMap::new());
+}
+
+// the sequence of the cloudevents ids of the agent
+static EVENT_SEQ: AtomicU64 = AtomicU64::new(0);
+
+/// set_output_envelopes sets the schema of the records of outputs from a comma
+/// separated list of `<output>=<schema>`, tasks without a schema use it.
+pub fn set_output_envelopes(spec: &str) -> Result<usize, String> {
+    let mut envelopes = HashMap::new();
+    for pair in spec
+        .split(',')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+    {
+        let (output, schema) = pair
+            .rsplit_once('=')
+            .ok_or_else(|| format!("envelope {:?} is not <output>=<schema>", pair))?;
+        let schema = EnvelopeSchema::parse(schema)
+            .ok_or_else(|| format!("unknown envelope schema {:?} of {:?}", schema, output))?;
+        envelopes.insert(output.trim().to_string(), schema);
+    }
+    let count = envelopes.len();
+    if let Ok(mut output_envelopes) = OUTPUT_ENVELOPES.write() {
+        output_envelopes.extend(envelopes);
+    }
+    Ok(count)
+}
+
+fn output_envelope(output: &str) -> EnvelopeSchema {
+    match OUTPUT_ENVELOPES.read() {
+        Ok(envelopes) => envelopes
+            .get(output)
+            .copied()
+            .unwrap_or(EnvelopeSchema::Legacy),
+        Err(_) => EnvelopeSchema::Legacy,
+    }
+}
+
+/// parse_template parses an envelope template, a json document.
+pub fn parse_template(template: &str) -> Result<Value, String> {
+    serde_json::from_str::<Value>(template).map_err(|e| format!("envelope template error: {}", e))
+}
+
+fn now_nanos() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as i64)
+        .unwrap_or(0)
+}
+
+fn object(value: Value) -> Map<String, Value> {
+    match value {
+        Value::Object(map) => map,
+        _ => Map::new(),
+    }
+}
+
+// lookup returns the value of a record at a dotted path
+fn lookup<'a>(record: &'a Value, path: &str) -> Option<&'a Value> {
+    let path = path
+        .trim()
+        .split('.')
+        .map(|key| key.to_string())
+        .collect::<Vec<String>>();
+    resolve(record, &path)
+}
+
+// render replaces the `${path}` strings of a template by the record values, a
+// string of a single placeholder keeps the type of its value
+fn render(template: &Value, record: &Value) -> Value {
+    match template {
+        Value::String(text) => render_text(text, record),
+        Value::Array(list) => Value::Array(list.iter().map(|v| render(v, record)).collect()),
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .map(|(key, value)| (key.clone(), render(value, record)))
+                .collect(),
+        ),
+        other => other.clone(),
+    }
+}
+
+fn render_text(text: &str, record: &Value) -> Value {
+    if let Some(path) = text.strip_prefix("${").and_then(|t| t.strip_suffix('}')) {
+        if !path.contains('}') {
+            return lookup(record, path).cloned().unwrap_or(Value::Null);
+        }
+    }
+    let mut rendered = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("${") {
+        rendered.push_str(&rest[..start]);
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => break,
+        };
+        match lookup(record, &rest[start + 2..end]) {
+            Some(Value::String(value)) => rendered.push_str(value),
+            Some(Value::Null) | None => {}
+            Some(value) => rendered.push_str(&value.to_string()),
+        }
+        rest = &rest[end + 1..];
+    }
+    rendered.push_str(rest);
+    Value::String(rendered)
+}
+
+// severity_number returns the opentelemetry severity number of a level
+fn severity_number(level: filter::Level) -> u64 {
+    match level {
+        filter::Level::Trace => 1,
+        filter::Level::Debug => 5,
+        filter::Level::Info => 9,
+        filter::Level::Warn => 13,
+        filter::Level::Error => 17,
+        filter::Level::Fatal => 21,
+    }
+}
+
+/// Encoder encodes the entries of a container in the envelope schema of its
+/// task, or of its output when the task sets none.
+pub(crate) struct Encoder {
+    schema: EnvelopeSchema,
+    template: Value,
+    custom: Map<String, Value>,
+    // the serialized `custom` block of the legacy schema
+    legacy_custom: String,
+    container: Container,
+    metadata: Value,
+}
+
+impl Encoder {
+    pub(crate) fn new(container: &Container, output: &str) -> Self {
+        let mut schema = container
+            .envelope
+            .schema
+            .unwrap_or_else(|| output_envelope(output));
+        let mut template = Value::Null;
+        if schema == EnvelopeSchema::Template {
+            match parse_template(&container.envelope.template) {
+                Ok(it) => template = it,
+                Err(e) => {
+                    eprintln!(
+                        "[ERROR] envelope of {:?} invalid, use legacy: {}",
+                        container.path, e
+                    );
+                    schema = EnvelopeSchema::Legacy;
+                }
+            }
+        }
+        let custom = custom_fields(container);
+        Self {
+            schema,
+            template,
+            legacy_custom: Value::Object(custom.clone()).to_string(),
+            custom,
+            container: container.clone(),
+            metadata: container_metadata(container),
+        }
+    }
+
+    /// with_custom adds a field to the container metadata of the records.
+    pub(crate) fn with_custom(mut self, key: &str, value: Value) -> Self {
+        self.custom.insert(key.to_string(), value);
+        self.legacy_custom = Value::Object(self.custom.clone()).to_string();
+        self
+    }
+
+    /// entry encodes a processed message.
+    pub(crate) fn entry(&self, entry: Entry) -> String {
+        match self.schema {
+            EnvelopeSchema::Legacy => encode_entry(&self.legacy_custom, entry),
+            _ => self.encode(&self.custom, entry, CLOUDEVENTS_LOG_TYPE),
+        }
+    }
+
+    /// record encodes a synthetic record, its `message` is the message of the
+    /// record and its other keys are the fields.
+    pub(crate) fn record(&self, record: &Value) -> String {
+        if self.schema == EnvelopeSchema::Legacy {
+            return encode_record(&self.legacy_custom, record);
+        }
+        let mut fields = match record {
+            Value::Object(fields) => fields.clone(),
+            _ => Map::new(),
+        };
+        let message = match fields.remove("message") {
+            Some(Value::String(message)) => message,
+            _ => "".to_string(),
+        };
+        let entry = Entry {
+            message,
+            fields,
+            ..Default::default()
+        };
+        self.encode(&self.custom, entry, CLOUDEVENTS_RECORD_TYPE)
+    }
+
+    fn encode(&self, custom: &Map<String, Value>, entry: Entry, event_type: &str) -> String {
+        let now = now_nanos();
+        match self.schema {
+            EnvelopeSchema::Flat => self.flat(custom, entry),
+            EnvelopeSchema::Ecs => self.ecs(custom, entry, now),
+            EnvelopeSchema::Otel => self.otel(custom, entry, now),
+            EnvelopeSchema::CloudEvents => self.cloudevents(custom, entry, now, event_type),
+            _ => self.template(custom, entry, now),
+        }
+        .to_string()
+    }
+
+    // flat puts the container metadata and the fields next to the message, the
+    // metadata wins over fields of the same name
+    fn flat(&self, custom: &Map<String, Value>, entry: Entry) -> Value {
+        let mut record = custom.clone();
+        record.remove("version");
+        for (key, value) in entry.fields {
+            record.entry(key).or_insert(value);
+        }
+        record.insert("message".to_string(), Value::String(entry.message));
+        if let Some(level) = entry.level {
+            record.insert("level".to_string(), json!(level.as_str()));
+        }
+        if !entry.tags.is_empty() {
+            record.insert("tags".to_string(), json!(entry.tags));
+        }
+        if entry.repeat_count > 1 {
+            record.insert("repeat_count".to_string(), json!(entry.repeat_count));
+        }
+        Value::Object(record)
+    }
+
+    // ecs maps the container to the ecs fields, the fields are added at the
+    // top level unless they are ecs fields already set
+    fn ecs(&self, custom: &Map<String, Value>, entry: Entry, now: i64) -> Value {
+        let c = &self.container;
+        let mut record = object(json!({
+            "@timestamp": format_rfc3339(now),
+            "ecs": {"version": ECS_VERSION},
+            "message": entry.message,
+            "host": {"name": c.node_name},
+        }));
+        let mut log = Map::new();
+        if let Some(level) = entry.level {
+            log.insert("level".to_string(), json!(level.as_str()));
+        }
+        if !c.source.is_empty() {
+            log.insert("file".to_string(), json!({ "path": c.source }));
+        }
+        if !log.is_empty() {
+            record.insert("log".to_string(), Value::Object(log));
+        }
+        if !c.service_name.is_empty() {
+            record.insert("service".to_string(), json!({ "name": c.service_name }));
+        }
+        if !c.pod_name.is_empty() {
+            let mut container = object(json!({ "name": c.container }));
+            if let Some(id) = custom.get("containerId") {
+                container.insert("id".to_string(), id.clone());
+            }
+            if let Some(image) = custom.get("image") {
+                container.insert("image".to_string(), json!({ "name": image }));
+            }
+            record.insert("container".to_string(), Value::Object(container));
+            record.insert(
+                "orchestrator".to_string(),
+                json!({
+                    "type": "kubernetes",
+                    "namespace": c.ns,
+                    "resource": {"type": "pod", "name": c.pod_name},
+                }),
+            );
+        }
+        if let Some(labels) = custom.get("labels") {
+            record.insert("labels".to_string(), labels.clone());
+        }
+        if !entry.tags.is_empty() {
+            record.insert("tags".to_string(), json!(entry.tags));
+        }
+        if entry.repeat_count > 1 {
+            record.insert("repeat_count".to_string(), json!(entry.repeat_count));
+        }
+        for (key, value) in entry.fields {
+            record.entry(key).or_insert(value);
+        }
+        Value::Object(record)
+    }
+
+    // otel is a log record of the opentelemetry log data model, the read time
+    // is the observed timestamp as the log time is not known
+    fn otel(&self, custom: &Map<String, Value>, entry: Entry, now: i64) -> Value {
+        let c = &self.container;
+        let mut resource = Map::new();
+        let mut attribute = |key: &str, value: &str| {
+            if !value.is_empty() {
+                resource.insert(key.to_string(), json!(value));
+            }
+        };
+        attribute("service.name", &c.service_name);
+        attribute("k8s.namespace.name", &c.ns);
+        attribute("k8s.pod.name", &c.pod_name);
+        attribute("k8s.container.name", &c.container);
+        attribute("k8s.node.name", &c.node_name);
+        for (key, name) in [
+            ("containerId", "container.id"),
+            ("image", "container.image.name"),
+            ("podUid", "k8s.pod.uid"),
+        ] {
+            if let Some(value) = custom.get(key) {
+                resource.insert(name.to_string(), value.clone());
+            }
+        }
+        for (key, prefix) in [
+            ("labels", "k8s.pod.label."),
+            ("annotations", "k8s.pod.annotation."),
+        ] {
+            if let Some(Value::Object(values)) = custom.get(key) {
+                for (name, value) in values {
+                    resource.insert(format!("{}{}", prefix, name), value.clone());
+                }
+            }
+        }
+
+        let mut attributes = entry.fields;
+        if !c.source.is_empty() {
+            attributes.insert("log.file.path".to_string(), json!(c.source));
+        }
+        if !entry.tags.is_empty() {
+            attributes.insert("tags".to_string(), json!(entry.tags));
+        }
+        if entry.repeat_count > 1 {
+            attributes.insert("repeat_count".to_string(), json!(entry.repeat_count));
+        }
+        let mut record = object(json!({
+            "ObservedTimestamp": now,
+            "Body": entry.message,
+            "Resource": resource,
+            "Attributes": attributes,
+        }));
+        if let Some(level) = entry.level {
+            record.insert(
+                "SeverityText".to_string(),
+                json!(level.as_str().to_uppercase()),
+            );
+            record.insert("SeverityNumber".to_string(), json!(severity_number(level)));
+        }
+        Value::Object(record)
+    }
+
+    // cloudevents is a structured event of the entry as its data, sourced from
+    // the container
+    fn cloudevents(
+        &self,
+        custom: &Map<String, Value>,
+        entry: Entry,
+        now: i64,
+        event_type: &str,
+    ) -> Value {
+        let c = &self.container;
+        let source = if !c.pod_name.is_empty() {
+            format!(
+                "/namespaces/{}/pods/{}/containers/{}",
+                c.ns, c.pod_name, c.container
+            )
+        } else if !c.source.is_empty() {
+            c.source.clone()
+        } else {
+            format!("/nodes/{}", c.node_name)
+        };
+        let mut data = object(json!({
+            "message": entry.message,
+            "custom": custom,
+        }));
+        if !entry.fields.is_empty() {
+            data.insert("fields".to_string(), Value::Object(entry.fields));
+        }
+        if let Some(level) = entry.level {
+            data.insert("level".to_string(), json!(level.as_str()));
+        }
+        if !entry.tags.is_empty() {
+            data.insert("tags".to_string(), json!(entry.tags));
+        }
+        if entry.repeat_count > 1 {
+            data.insert("repeat_count".to_string(), json!(entry.repeat_count));
+        }
+        json!({
+            "specversion": "1.0",
+            "id": format!("{}-{}", now, EVENT_SEQ.fetch_add(1, Ordering::Relaxed)),
+            "source": source,
+            "type": event_type,
+            "time": format_rfc3339(now),
+            "datacontenttype": "application/json",
+            "data": data,
+        })
+    }
+
+    // template renders the template with the entry, its container metadata
+    // as `container`, the `custom` block and the read time as `timestamp`
+    fn template(&self, custom: &Map<String, Value>, entry: Entry, now: i64) -> Value {
+        let record = json!({
+            "message": entry.message,
+            "fields": entry.fields,
+            "level": entry.level.map(|level| level.as_str()),
+            "tags": entry.tags,
+            "repeat_count": std::cmp::max(entry.repeat_count, 1),
+            "container": self.metadata,
+            "custom": custom,
+            "timestamp": format_rfc3339(now),
+            "timestamp_nanos": now,
+        });
+        render(&self.template, &record)
+    }
+}
+
+/// RecordEncoder encodes the records pushed by inputs in the envelope schema
+/// of the task of their container, or of their output when the task sets
+/// none. The records carry their own `custom` block.
+pub struct RecordEncoder(Encoder);
+
+impl RecordEncoder {
+    pub fn new(container: &Container, output: &str) -> Self {
+        Self(Encoder::new(container, output))
+    }
+
+    /// encode encodes a message with the keys added beside it in the legacy
+    /// schema: the fields of the record and the `fields`, `level`, `tags` and
+    /// `repeat_count` of its pipeline.
+    pub fn encode(
+        &self,
+        custom: &Map<String, Value>,
+        message: &str,
+        mut keys: Map<String, Value>,
+    ) -> String {
+        if self.0.schema == EnvelopeSchema::Legacy {
+            keys.insert("custom".to_string(), Value::Object(custom.clone()));
+            keys.insert("message".to_string(), Value::String(message.to_string()));
+            return Value::Object(keys).to_string();
+        }
+        let mut entry = Entry {
+            message: message.to_string(),
+            ..Default::default()
+        };
+        if let Some(Value::String(level)) = keys.remove("level") {
+            entry.level = filter::Level::parse(&level);
+        }
+        if let Some(Value::Array(tags)) = keys.remove("tags") {
+            entry.tags = tags
+                .into_iter()
+                .filter_map(|tag| tag.as_str().map(|tag| tag.to_string()))
+                .collect();
+        }
+        if let Some(repeat_count) = keys.remove("repeat_count").and_then(|v| v.as_u64()) {
+            entry.repeat_count = repeat_count;
+        }
+        if let Some(Value::Object(fields)) = keys.remove("fields") {
+            entry.fields = fields;
+        }
+        for (key, value) in keys {
+            entry.fields.entry(key).or_insert(value);
+        }
+        self.0.encode(custom, entry, CLOUDEVENTS_LOG_TYPE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_template, set_output_envelopes, Encoder};
+    use crate::pipeline::Entry;
+    use db::{Container, Envelope, EnvelopeSchema};
+    use serde_json::{json, Value};
+
+    fn container(schema: Option<EnvelopeSchema>, template: &str) -> Container {
+        Container {
+            ns: "default".to_string(),
+            pod_name: "web-1".to_string(),
+            container: "nginx".to_string(),
+            service_name: "web".to_string(),
+            node_name: "node1".to_string(),
+            envelope: Envelope {
+                schema,
+                template: template.to_string(),
+            },
+            ..Default::default()
+        }
+    }
+
+    fn entry() -> Entry {
+        let mut entry = Entry {
+            message: "GET / 500".to_string(),
+            level: Some(filter::Level::Error),
+            repeat_count: 2,
+            ..Default::default()
+        };
+        entry.fields.insert("status".to_string(), json!(500));
+        entry
+    }
+
+    fn encode(schema: EnvelopeSchema) -> Value {
+        let encoder = Encoder::new(&container(Some(schema), ""), "fake_output");
+        serde_json::from_str::<Value>(&encoder.entry(entry())).unwrap()
+    }
+
+    #[test]
+    fn it_works() {
+        let record = encode(EnvelopeSchema::Legacy);
+        assert_eq!(record["custom"]["nodeId"], "web-1");
+        assert_eq!(record["custom"]["version"], "v1.0.0");
+        assert_eq!(record["fields"]["status"], 500);
+
+        let record = encode(EnvelopeSchema::Flat);
+        assert_eq!(record["nodeId"], "web-1");
+        assert_eq!(record["status"], 500);
+        assert_eq!(record["level"], "error");
+        assert!(record.get("version").is_none());
+
+        let record = encode(EnvelopeSchema::Ecs);
+        assert_eq!(record["ecs"]["version"], "8.11.0");
+        assert_eq!(record["log"]["level"], "error");
+        assert_eq!(record["orchestrator"]["resource"]["name"], "web-1");
+        assert_eq!(record["service"]["name"], "web");
+        assert_eq!(record["status"], 500);
+        assert!(common::parse_rfc3339(record["@timestamp"].as_str().unwrap()).is_some());
+
+        let record = encode(EnvelopeSchema::Otel);
+        assert_eq!(record["Body"], "GET / 500");
+        assert_eq!(record["SeverityText"], "ERROR");
+        assert_eq!(record["SeverityNumber"], 17);
+        assert_eq!(record["Resource"]["k8s.pod.name"], "web-1");
+        assert_eq!(record["Attributes"]["status"], 500);
+        assert_eq!(record["Attributes"]["repeat_count"], 2);
+
+        let record = encode(EnvelopeSchema::CloudEvents);
+        assert_eq!(record["specversion"], "1.0");
+        assert_eq!(record["type"], "harvest.log");
+        assert_eq!(
+            record["source"],
+            "/namespaces/default/pods/web-1/containers/nginx"
+        );
+        assert_eq!(record["data"]["fields"]["status"], 500);
+        assert_ne!(record["id"], encode(EnvelopeSchema::CloudEvents)["id"]);
+    }
+
+    #[test]
+    fn template_it_works() {
+        let container = container(
+            Some(EnvelopeSchema::Template),
+            r#"{"msg":"${message}","status":"${fields.status}","at":"${container.ns}/${container.pod_name}","missing":"${fields.none}","count":"x${repeat_count}"}"#,
+        );
+        let encoder = Encoder::new(&container, "fake_output");
+        let record = serde_json::from_str::<Value>(&encoder.entry(entry())).unwrap();
+        assert_eq!(
+            record,
+            json!({"msg":"GET / 500","status":500,"at":"default/web-1","missing":null,"count":"x2"})
+        );
+
+        let record = encoder.record(&json!({"message":"suppressed","dropped":3}));
+        assert_eq!(
+            serde_json::from_str::<Value>(&record).unwrap()["msg"],
+            "suppressed"
+        );
+
+        // an invalid template ships legacy records
+        let container = Container {
+            envelope: Envelope {
+                schema: Some(EnvelopeSchema::Template),
+                template: "{".to_string(),
+            },
+            ..Default::default()
+        };
+        let record = Encoder::new(&container, "fake_output").entry(entry());
+        assert!(record.starts_with(r#"{"custom":"#));
+        assert!(parse_template("{").is_err());
+    }
+
+    #[test]
+    fn output_envelopes_it_works() {
+        assert_eq!(
+            set_output_envelopes("envelope_test_ecs=ecs, envelope_test_otel=OTel"),
+            Ok(2)
+        );
+        assert!(set_output_envelopes("envelope_test=xml").is_err());
+        assert!(set_output_envelopes("envelope_test").is_err());
+
+        let record = Encoder::new(&container(None, ""), "envelope_test_otel").entry(entry());
+        assert_eq!(
+            serde_json::from_str::<Value>(&record).unwrap()["Body"],
+            "GET / 500"
+        );
+        // the schema of the task wins over the schema of the output
+        let container = container(Some(EnvelopeSchema::Flat), "");
+        let record = Encoder::new(&container, "envelope_test_ecs").entry(entry());
+        assert_eq!(
+            serde_json::from_str::<Value>(&record).unwrap()["status"],
+            500
+        );
+        let record = Encoder::new(&Container::default(), "fake_output").record(&json!({}));
+        assert!(record.starts_with(r#"{"custom":"#));
+    }
+}
@@ -1,27 +1,59 @@
 #![feature(seek_stream_len)]
 extern crate crossbeam_channel;
+#[macro_use]
+extern crate lazy_static;
 use async_std::task;
 use common::{Item, Result};
-use crossbeam_channel::{unbounded, Sender};
-use db::Container;
+use crossbeam_channel::{unbounded, RecvTimeoutError, Sender};
+use db::{Container, StartPosition};
+use envelope::Encoder;
 use output::output_write;
-use serde_json::json;
+use pipeline::{Entry, Pipeline};
+use position::start_offset;
+use serde_json::{json, Map, Value};
 use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
-use std::fs::File;
+use std::fs::{self, File};
 use std::hash::{Hash, Hasher};
 use std::io::{BufRead, BufReader, Seek, SeekFrom};
 use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+mod envelope;
+mod metrics;
+mod pipeline;
+mod position;
+mod replay;
+mod tap;
+
+pub use envelope::{parse_template, set_output_envelopes, RecordEncoder};
+pub use pipeline::RecordPipeline;
+pub use replay::{
+    replay_progress, replays, rotated_files, start_replay, ReplayProgress, ReplayState,
+};
+pub use tap::{TapEvent, TapReceiver};
+
+const SOURCE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+// how often a reader with no poll interval flushes its pipeline when idle
+const PIPELINE_FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+/// the `version` of the `custom` block of every record
+pub const RECORD_VERSION: &str = "v1.0.0";
 
 #[derive(Debug)]
 pub enum SendFileEvent {
     Close,
     Other,
+    // a synthetic record written after the lines read so far
+    Record(Value),
 }
 
 #[derive(Clone)]
 pub struct FileReaderWriter {
     file_handles: Arc<Vec<RwLock<HashMap<String, Sender<SendFileEvent>>>>>,
+    // readers also check the file size on this interval, for filesystems
+    // where write events are unreliable, it is shared so a watcher falling
+    // back to polling enables it on the open readers
+    poll_interval: Arc<RwLock<Option<Duration>>>,
 }
 
 impl FileReaderWriter {
@@ -33,6 +65,36 @@ impl FileReaderWriter {
         }
         Self {
             file_handles: Arc::new(file_handles),
+            poll_interval: Arc::new(RwLock::new(None)),
+        }
+    }
+
+    pub fn with_poll_interval(self, interval: Duration) -> Self {
+        self.set_poll_interval(interval);
+        self
+    }
+
+    /// set_poll_interval makes the readers, open ones included, check the
+    /// file size on `interval`.
+    pub fn set_poll_interval(&self, interval: Duration) {
+        match self.poll_interval.write() {
+            Ok(mut w) => *w = Some(interval),
+            Err(e) => eprintln!("[ERROR] frw set poll interval failed: {:?}", e),
+        }
+        // wake the readers blocked without a timeout
+        for handles in self.file_handles.iter() {
+            if let Ok(r) = handles.read() {
+                for tx in r.values() {
+                    let _ = tx.send(SendFileEvent::Other);
+                }
+            }
+        }
+    }
+
+    fn poll_interval(&self) -> Option<Duration> {
+        match self.poll_interval.read() {
+            Ok(r) => *r,
+            Err(_) => None,
         }
     }
 
@@ -94,6 +156,10 @@ impl FileReaderWriter {
         }
     }
 
+    pub fn is_open(&self, path: &str) -> bool {
+        self.contains_key(path)
+    }
+
     pub fn close_event(&self, path: &str) {
         if let Some(tx) = self.get(path) {
             if let Err(e) = tx.send(SendFileEvent::Close) {
@@ -130,6 +196,27 @@ impl FileReaderWriter {
         }
     }
 
+    /// record_event writes a synthetic record of the container, after the lines
+    /// already written to its log when a reader is open.
+    pub fn record_event(&self, container: &Container, record: Value) {
+        if let Some(tx) = self.get(&container.path) {
+            if let Err(e) = tx.send(SendFileEvent::Record(record)) {
+                eprintln!(
+                    "[ERROR] frw send record to {:?} handle error: {:?}",
+                    &container.path, e
+                );
+            }
+            return;
+        }
+        if !container.is_upload() {
+            return;
+        }
+        output_write(
+            &container.output,
+            &Encoder::new(container, &container.output).record(&record),
+        );
+    }
+
     fn _file_size(path: &str) -> i64 {
         let mut file = match File::open(&path) {
             Ok(file) => file,
@@ -141,6 +228,27 @@ impl FileReaderWriter {
         file.stream_len().unwrap() as i64
     }
 
+    // reset_truncated reads a truncated file, such as a copytruncate
+    // rotation, again from its beginning
+    fn reset_truncated(br: &mut BufReader<File>, offset: &mut i64, path: &str) {
+        match fs::metadata(path) {
+            Ok(metadata) if (metadata.len() as i64) < *offset => {}
+            _ => return,
+        }
+        match Self::open_seek_buffer(path, 0) {
+            Ok(reader) => {
+                println!(
+                    "[INFO] frw file {:?} truncated, read from its beginning",
+                    path
+                );
+                *br = reader;
+                db::incr_offset(path, -*offset);
+                *offset = 0;
+            }
+            Err(e) => eprintln!("[ERROR] frw reopen truncated {:?} error: {:?}", path, e),
+        }
+    }
+
     fn open_seek_buffer(path: &str, offset: i64) -> Result<BufReader<File>> {
         let mut file = match File::open(path) {
             Ok(file) => file,
@@ -149,7 +257,7 @@ impl FileReaderWriter {
             }
         };
 
-        if let Err(e) = file.seek(SeekFrom::Current(offset)) {
+        if let Err(e) = file.seek(SeekFrom::Start(offset as u64)) {
             return Err(Box::new(e));
         }
 
@@ -161,17 +269,57 @@ impl FileReaderWriter {
         bf: &mut String,
         offset: &mut i64,
         container: &Container,
+        encoder: &Encoder,
+        pipeline: &mut Pipeline,
     ) {
+        // the pipeline follows the updates of the task, the held run and the
+        // suppressed lines are written before its settings change
+        if let Some(current) = db::get(&container.path) {
+            if pipeline.is_stale(&current) {
+                flush_pipeline(container, encoder, pipeline, true);
+            }
+            pipeline.reload(&current);
+        }
         while let Ok(line_size) = br.read_line(bf) {
             if line_size == 0 {
                 break;
             }
-            output_write(&container.output, &encode_message(&container, bf.as_str()));
+            for entry in process_line(container, bf.as_str(), pipeline) {
+                write_entry(container, encoder, entry);
+            }
             db::incr_offset(&container.path, line_size as i64);
             bf.clear();
 
             *offset += line_size as i64;
         }
+        flush_pipeline(container, encoder, pipeline, false);
+    }
+
+    /// tap_event reads a tapped container that is not collected, from the end
+    /// of its log and without writing to an output, until its taps are
+    /// dropped or a collecting reader is opened.
+    pub fn tap_event(&self, container: &Container) {
+        let path = container.path.as_str();
+        if self.is_open(path) || tap::has_reader(path) {
+            return;
+        }
+        let offset = Self::_file_size(path).max(0);
+        let br = match Self::open_seek_buffer(path, offset) {
+            Ok(br) => br,
+            Err(e) => {
+                eprintln!("[ERROR] tap reader open {:?} error: {:?}", path, e);
+                return;
+            }
+        };
+        if tap::add_reader(
+            path,
+            tap::TapReader::new(self.clone(), container, br, offset),
+        ) {
+            println!(
+                "[INFO] open tap reader for pod {:?} container {:?} path {:?}",
+                container.pod_name, container.container, container.path
+            );
+        }
     }
 
     pub fn open_event(&self, container: &mut Container) {
@@ -184,12 +332,25 @@ impl FileReaderWriter {
             &container.service_name, &container.pod_name, &container.container, &container.path
         );
 
+        // the start position applies once, the reader then resumes from its offset
+        match start_offset(&container.path, &container.start, container.offset) {
+            Ok(offset) => container.offset = offset,
+            Err(e) => eprintln!(
+                "[ERROR] frw resolve start position {:?} of {:?} error: {:?}",
+                &container.start, &container.path, e
+            ),
+        }
+        container.start = StartPosition::Resume;
+
         let container_clone = container.clone();
+        let encoder = Encoder::new(&container_clone, &container_clone.output);
         let mut offset = container.offset;
 
+        let frw = self.clone();
         let (tx, rx) = unbounded::<SendFileEvent>();
         task::spawn(async move {
             let mut bf = String::new();
+            let mut pipeline = Pipeline::new(&container_clone);
             let mut br = match Self::open_seek_buffer(&container_clone.path, container_clone.offset)
             {
                 Ok(it) => it,
@@ -199,11 +360,74 @@ impl FileReaderWriter {
                 }
             };
 
-            while let Ok(evt) = rx.recv() {
-                if let SendFileEvent::Close = evt {
-                    break;
+            loop {
+                // no watcher reports writes to files inside containers
+                let poll_interval = if container_clone.source.is_empty() {
+                    frw.poll_interval()
                 } else {
-                    Self::read_fn(&mut br, &mut bf, &mut offset, &container_clone).await
+                    Some(frw.poll_interval().unwrap_or(SOURCE_POLL_INTERVAL))
+                };
+                // the held run and the suppressed report are written while idle
+                let poll_interval = poll_interval
+                    .or_else(|| pipeline.is_timed().then_some(PIPELINE_FLUSH_INTERVAL));
+                let evt = match poll_interval {
+                    Some(interval) => match rx.recv_timeout(interval) {
+                        Ok(evt) => evt,
+                        Err(RecvTimeoutError::Timeout) => {
+                            match fs::metadata(&container_clone.path) {
+                                Ok(metadata) if metadata.len() as i64 != offset => {
+                                    SendFileEvent::Other
+                                }
+                                _ => {
+                                    // an idle reader still ships its held run and reports the
+                                    // lines its limits suppressed
+                                    flush_pipeline(
+                                        &container_clone,
+                                        &encoder,
+                                        &mut pipeline,
+                                        false,
+                                    );
+                                    continue;
+                                }
+                            }
+                        }
+                        Err(RecvTimeoutError::Disconnected) => break,
+                    },
+                    None => match rx.recv() {
+                        Ok(evt) => evt,
+                        Err(_) => break,
+                    },
+                };
+                match evt {
+                    SendFileEvent::Close => {
+                        flush_pipeline(&container_clone, &encoder, &mut pipeline, true);
+                        break;
+                    }
+                    SendFileEvent::Other => {
+                        Self::reset_truncated(&mut br, &mut offset, &container_clone.path);
+                        Self::read_fn(
+                            &mut br,
+                            &mut bf,
+                            &mut offset,
+                            &container_clone,
+                            &encoder,
+                            &mut pipeline,
+                        )
+                        .await
+                    }
+                    SendFileEvent::Record(record) => {
+                        Self::reset_truncated(&mut br, &mut offset, &container_clone.path);
+                        Self::read_fn(
+                            &mut br,
+                            &mut bf,
+                            &mut offset,
+                            &container_clone,
+                            &encoder,
+                            &mut pipeline,
+                        )
+                        .await;
+                        output_write(&container_clone.output, &encoder.record(&record));
+                    }
                 }
             }
         });
@@ -217,50 +441,330 @@ impl FileReaderWriter {
     }
 }
 
-fn encode_message<'a>(container: &'a Container, message: &'a str) -> String {
-    if message.len() == 0 {
-        return "".to_string();
-    }
-    let message_item = Item::from(message);
-    if message_item.is_json() {
+/// custom_fields returns the `custom` block of the records of a container.
+pub fn custom_fields(container: &Container) -> Map<String, Value> {
+    // host files carry the file as `source` and the node instead of pod metadata
+    let fields = if container.pod_name.is_empty() && !container.source.is_empty() {
         json!({
-            "custom":
-                {
-                  "nodeId":container.pod_name,
-                  "container":container.container,
-                  "serviceName":container.service_name,
-                  "ips":container.ips,
-                  "ns":container.ns,
-                  "version":"v1.0.0",
-                },
-            "message":message_item.get_key("log")}
-        )
-        .to_string()
+            "nodeName":container.node_name,
+            "version":RECORD_VERSION,
+        })
     } else {
         json!({
-            "custom":
-                {
-                  "nodeId":container.pod_name,
-                  "container":container.container,
-                  "serviceName":container.service_name,
-                  "ips":container.ips,
-                  "ns":container.ns,
-                  "version":"v1.0.0",
-                },
-            "message":message_item.string()}
-        )
-        .to_string()
+            "nodeId":container.pod_name,
+            "container":container.container,
+            "serviceName":container.service_name,
+            "ips":container.ips,
+            "ns":container.ns,
+            "version":RECORD_VERSION,
+        })
+    };
+    let mut custom = match fields {
+        Value::Object(custom) => custom,
+        _ => Map::new(),
+    };
+    if !container.source.is_empty() {
+        custom.insert(
+            "source".to_string(),
+            Value::String(container.source.clone()),
+        );
+    }
+    custom.extend(container.enrich.fields(container));
+    custom
+}
+
+// decode_message returns the message of a docker json log line
+fn decode_message(line: &str) -> Option<String> {
+    if line.len() == 0 {
+        return None;
+    }
+    let message_item = Item::from(line);
+    Some(if message_item.is_json() {
+        message_item.get_key("log")
+    } else {
+        message_item.string()
+    })
+}
+
+// decode_line returns the message of a line of a file inside the container,
+// unlike the docker json log the line is the message as is
+fn decode_line(line: &str) -> Option<String> {
+    let line = line.trim_end_matches(['\n', '\r']);
+    if line.is_empty() {
+        return None;
+    }
+    Some(line.to_string())
+}
+
+// process_line returns the entries to ship for a line read from the container log
+fn process_line(container: &Container, line: &str, pipeline: &mut Pipeline) -> Vec<Entry> {
+    let message = if container.source.is_empty() {
+        decode_message(line)
+    } else {
+        decode_line(line)
+    };
+    match message {
+        Some(message) => pipeline.process(message),
+        None => Vec::new(),
     }
 }
 
+// write_entry writes an entry to the output of the container and its taps,
+// the taps filter the entry message
+fn write_entry(container: &Container, encoder: &Encoder, entry: Entry) {
+    let message = entry.message.clone();
+    let record = encoder.entry(entry);
+    output_write(&container.output, &record);
+    tap::publish(&container.path, &message, &record);
+}
+
+fn publish_entry(path: &str, encoder: &Encoder, entry: Entry) {
+    let message = entry.message.clone();
+    tap::publish(path, &message, &encoder.entry(entry));
+}
+
+// flush_pipeline writes the run held by dedup once its window elapsed and the
+// report of the suppressed lines, `flush` writes both at once
+fn flush_pipeline(container: &Container, encoder: &Encoder, pipeline: &mut Pipeline, flush: bool) {
+    if let Some(entry) = pipeline.pending(flush) {
+        write_entry(container, encoder, entry);
+    }
+    if let Some(record) = pipeline.report(flush) {
+        output_write(&container.output, &encoder.record(&record));
+    }
+}
+
+// encode_entry encodes a processed message in the legacy schema, the fields
+// extracted from it and its tags are only added when present
+pub(crate) fn encode_entry(custom: &str, entry: Entry) -> String {
+    let mut record = format!(
+        r#"{{"custom":{},"message":{}"#,
+        custom,
+        Value::String(entry.message)
+    );
+    if !entry.fields.is_empty() {
+        record.push_str(&format!(r#","fields":{}"#, Value::Object(entry.fields)));
+    }
+    if let Some(level) = entry.level {
+        record.push_str(&format!(r#","level":"{}""#, level.as_str()));
+    }
+    if !entry.tags.is_empty() {
+        record.push_str(&format!(r#","tags":{}"#, json!(entry.tags)));
+    }
+    if entry.repeat_count > 1 {
+        record.push_str(&format!(r#","repeat_count":{}"#, entry.repeat_count));
+    }
+    record.push('}');
+    record
+}
+
+// encode_record encodes a synthetic record in the legacy schema, its `message`
+// field is kept as the record message
+pub(crate) fn encode_record(custom: &str, record: &Value) -> String {
+    let message = match record.get("message") {
+        Some(Value::String(message)) => message.clone(),
+        _ => "".to_string(),
+    };
+    format!(
+        r#"{{"custom":{},"event":{},"message":{}}}"#,
+        custom,
+        record,
+        Value::String(message)
+    )
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::FileReaderWriter;
-    use db::Container;
+    use crate::pipeline::{Entry, Pipeline};
+    use crate::{encode_entry, process_line, Encoder, FileReaderWriter};
+    use async_std::task;
+    use common::{Item, Result};
+    use db::{Container, Enrich, Redact};
+    use output::{IOutput, Output, OUTPUTS};
+    use serde_json::Value;
+    use std::fs;
+    use std::io::{BufRead, Write};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    struct Capture(Arc<Mutex<Vec<String>>>);
+    impl IOutput for Capture {
+        fn write(&mut self, _: &str, item: Item) -> Result<()> {
+            self.0.lock().unwrap().push(item.string());
+            Ok(())
+        }
+
+        fn wait(&self, _: usize) -> bool {
+            true
+        }
+    }
 
     #[test]
     fn it_works() {
         let input = FileReaderWriter::new(10);
         input.open_event(&mut Container::default());
     }
+
+    #[test]
+    fn reset_truncated_it_works() {
+        let dir = std::env::temp_dir().join("harvest_frw_truncate");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("app.log");
+        let path_str = path.to_str().unwrap();
+        fs::write(&path, "line 1\nline 2\n").unwrap();
+
+        let mut br = FileReaderWriter::open_seek_buffer(path_str, 14).unwrap();
+        let mut offset = 14;
+        FileReaderWriter::reset_truncated(&mut br, &mut offset, path_str);
+        assert_eq!(offset, 14);
+
+        // a copytruncate rotation, the file is read again from its beginning
+        fs::write(&path, "line 3\n").unwrap();
+        FileReaderWriter::reset_truncated(&mut br, &mut offset, path_str);
+        assert_eq!(offset, 0);
+        let mut line = String::new();
+        br.read_line(&mut line).unwrap();
+        assert_eq!(line, "line 3\n");
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn read_task_update_it_works() {
+        let written = Arc::new(Mutex::new(vec![]));
+        OUTPUTS
+            .write()
+            .unwrap()
+            .registry_output("capture_frw_update", Output::new(Capture(written.clone())));
+        let dir = std::env::temp_dir().join("harvest_frw_update");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("app.log");
+        let path_str = path.to_str().unwrap();
+        fs::write(&path, "mail a@b.io\n").unwrap();
+
+        let mut container = Container {
+            path: path_str.to_string(),
+            output: "capture_frw_update".to_string(),
+            ..Default::default()
+        };
+        db::insert(&container);
+        for _ in 0..100 {
+            if db::get(path_str).is_some() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        let encoder = Encoder::new(&container, &container.output);
+        let mut pipeline = Pipeline::new(&container);
+        let mut br = FileReaderWriter::open_seek_buffer(path_str, 0).unwrap();
+        let (mut bf, mut offset) = (String::new(), 0);
+        task::block_on(FileReaderWriter::read_fn(
+            &mut br,
+            &mut bf,
+            &mut offset,
+            &container,
+            &encoder,
+            &mut pipeline,
+        ));
+
+        // the running reader redacts once the task is updated with redaction
+        container.redact = Redact {
+            builtins: vec!["email".to_string()],
+            ..Default::default()
+        };
+        db::update(&container);
+        for _ in 0..100 {
+            if db::get(path_str).map(|c| c.redact) == Some(container.redact.clone()) {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        let mut file = fs::OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"mail c@d.io\n").unwrap();
+        task::block_on(FileReaderWriter::read_fn(
+            &mut br,
+            &mut bf,
+            &mut offset,
+            &container,
+            &encoder,
+            &mut pipeline,
+        ));
+
+        let written = written.lock().unwrap();
+        assert_eq!(written.len(), 2);
+        assert!(written[0].contains("a@b.io"));
+        assert!(written[1].contains("mail [EMAIL]"));
+        assert!(!written[1].contains("c@d.io"));
+
+        db::delete(path_str);
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn encode_message_with_enrich() {
+        let container = Container {
+            pod_name: "pod-12345".to_string(),
+            container_id: "58044a726890".to_string(),
+            enrich: Enrich {
+                container_id: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let encoder = Encoder::new(&container, &container.output);
+        let mut entries = process_line(
+            &container,
+            r#"{"log":"hello\n","stream":"stdout"}"#,
+            &mut Pipeline::default(),
+        );
+        let record = encoder.entry(entries.pop().unwrap());
+        let record = serde_json::from_str::<Value>(&record).unwrap();
+        assert_eq!(record["message"], "hello\n");
+        assert_eq!(record["custom"]["nodeId"], "pod-12345");
+        assert_eq!(record["custom"]["containerId"], "58044a726890");
+    }
+
+    #[test]
+    fn encode_line_with_source() {
+        let container = Container {
+            pod_name: "pod-12345".to_string(),
+            source: "/app/logs/app.log".to_string(),
+            ..Default::default()
+        };
+        let encoder = Encoder::new(&container, &container.output);
+        let mut entries = process_line(
+            &container,
+            "{\"level\":\"info\"}\n",
+            &mut Pipeline::default(),
+        );
+        let record = encoder.entry(entries.pop().unwrap());
+        let record = serde_json::from_str::<Value>(&record).unwrap();
+        assert_eq!(record["message"], "{\"level\":\"info\"}");
+        assert_eq!(record["custom"]["source"], "/app/logs/app.log");
+        assert!(process_line(&container, "\n", &mut Pipeline::default()).is_empty());
+    }
+
+    #[test]
+    fn encode_entry_with_fields() {
+        let mut entry = Entry {
+            message: "GET /".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            encode_entry("{}", Entry::default()),
+            r#"{"custom":{},"message":""}"#
+        );
+        entry
+            .fields
+            .insert("method".to_string(), Value::String("GET".to_string()));
+        entry.tags.push("_grokparsefailure".to_string());
+        entry.level = Some(filter::Level::Warn);
+        entry.repeat_count = 3;
+        let record = serde_json::from_str::<Value>(&encode_entry("{}", entry)).unwrap();
+        assert_eq!(record["repeat_count"], 3);
+        assert_eq!(record["fields"]["method"], "GET");
+        assert_eq!(record["tags"][0], "_grokparsefailure");
+        assert_eq!(record["level"], "warn");
+    }
 }
@@ -0,0 +1,107 @@
+use output::output_write;
+use serde_json::{json, Value};
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Once, RwLock};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// the metrics are written every minute unless configured
+const DEFAULT_METRICS_INTERVAL: Duration = Duration::from_secs(60);
+const METRICS_TICK: Duration = Duration::from_secs(1);
+
+struct MetricsOutput {
+    interval: Duration,
+    names: BTreeSet<String>,
+    last: Instant,
+}
+
+lazy_static! {
+    // the outputs the task metrics are written to, by output
+    static ref METRICS_OUTPUTS: RwLock<HashMap<String, MetricsOutput>> =
+        RwLock::new(HashMap::new());
+}
+
+static METRICS_WRITER: Once = Once::new();
+
+/// emit_metrics writes the named metrics to the output every `interval`
+/// seconds, the metrics of the containers of a task are written once.
+pub(crate) fn emit_metrics(output: &str, interval: u64, names: Vec<String>) {
+    let interval = match interval {
+        0 => DEFAULT_METRICS_INTERVAL,
+        secs => Duration::from_secs(secs),
+    };
+    if let Ok(mut outputs) = METRICS_OUTPUTS.write() {
+        let metrics_output = outputs
+            .entry(output.to_string())
+            .or_insert_with(|| MetricsOutput {
+                interval,
+                names: BTreeSet::new(),
+                last: Instant::now(),
+            });
+        metrics_output.interval = interval;
+        metrics_output.names.extend(names);
+    }
+    METRICS_WRITER.call_once(|| {
+        thread::spawn(|| loop {
+            thread::sleep(METRICS_TICK);
+            write_metrics();
+        });
+    });
+}
+
+fn write_metrics() {
+    let due = match METRICS_OUTPUTS.write() {
+        Ok(mut outputs) => outputs
+            .iter_mut()
+            .filter(|(_, metrics_output)| metrics_output.last.elapsed() >= metrics_output.interval)
+            .map(|(output, metrics_output)| {
+                metrics_output.last = Instant::now();
+                (
+                    output.clone(),
+                    metrics_output
+                        .names
+                        .iter()
+                        .cloned()
+                        .collect::<Vec<String>>(),
+                )
+            })
+            .collect::<Vec<(String, Vec<String>)>>(),
+        Err(_) => return,
+    };
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0);
+    for (output, names) in due {
+        for record in filter::metric_records(&names) {
+            output_write(&output, &metric_record(record, timestamp).to_string());
+        }
+    }
+}
+
+fn metric_record(mut record: Value, timestamp: u64) -> Value {
+    if let Value::Object(fields) = &mut record {
+        fields.insert("timestamp".to_string(), json!(timestamp));
+    }
+    record
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{emit_metrics, metric_record, METRICS_OUTPUTS};
+    use serde_json::json;
+    use std::time::Duration;
+
+    #[test]
+    fn it_works() {
+        emit_metrics("metrics_test_output", 30, vec!["a_total".to_string()]);
+        emit_metrics("metrics_test_output", 30, vec!["b_total".to_string()]);
+        let outputs = METRICS_OUTPUTS.read().unwrap();
+        let metrics_output = &outputs["metrics_test_output"];
+        assert_eq!(metrics_output.interval, Duration::from_secs(30));
+        assert_eq!(metrics_output.names.len(), 2);
+
+        let record = metric_record(json!({"metric":"a_total","value":1.0}), 42);
+        assert_eq!(record["timestamp"], 42);
+    }
+}
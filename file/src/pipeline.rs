@@ -0,0 +1,857 @@
+use super::metrics::emit_metrics;
+use db::{Container, Dedup, Limit, Metrics, Parse, ParseFailure, Plugin, PluginFailure, Redact};
+use filter::{
+    container_metadata, detect_level, expr_record, Deduplicator, Expr, GrokParser, Level, Limiter,
+    MetricRules, Redactor, Scripter, Suppressed, Transform, WasmPlugin,
+};
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+// the tag of the messages matching no parse pattern
+const PARSE_FAILURE_TAG: &str = "_grokparsefailure";
+
+type SharedLimiter = Arc<Mutex<Limiter>>;
+
+lazy_static! {
+    // the limiter of every pod container, shared by its readers so the
+    // restarts of a container do not refill its buckets
+    static ref LIMITERS: Mutex<HashMap<String, (Limit, SharedLimiter)>> =
+        Mutex::new(HashMap::new());
+}
+
+// limiter_key identifies the container across its restarts, the pod uid and
+// the container name stay while the container id and log path change
+fn limiter_key(container: &Container) -> String {
+    if container.pod_uid.is_empty() {
+        return container.path.clone();
+    }
+    format!(
+        "{}/{}/{}",
+        container.pod_uid, container.container, container.source
+    )
+}
+
+/// shared_limiter returns the limiter of the container, a new one when the
+/// limits of its task changed. The limiters no reader holds are dropped once
+/// idle, their buckets being full again.
+fn shared_limiter(container: &Container) -> SharedLimiter {
+    let mut limiters = match LIMITERS.lock() {
+        Ok(limiters) => limiters,
+        Err(e) => {
+            eprintln!("[ERROR] pipeline limiters lock failed: {:?}", e);
+            return Arc::new(Mutex::new(Limiter::new(&container.limit)));
+        }
+    };
+    limiters.retain(|_, (_, limiter)| {
+        Arc::strong_count(limiter) > 1 || !limiter.lock().map_or(true, |l| l.is_idle())
+    });
+    let key = limiter_key(container);
+    match limiters.get(&key) {
+        Some((limit, limiter)) if limit == &container.limit => limiter.clone(),
+        _ => {
+            let limiter = Arc::new(Mutex::new(Limiter::new(&container.limit)));
+            limiters.insert(key, (container.limit.clone(), limiter.clone()));
+            limiter
+        }
+    }
+}
+
+/// Entry is a processed message, with the fields extracted from it.
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct Entry {
+    pub(crate) message: String,
+    pub(crate) fields: Map<String, Value>,
+    pub(crate) tags: Vec<String>,
+    pub(crate) level: Option<Level>,
+    // the number of identical lines collapsed into the entry, 0 or 1 for one
+    pub(crate) repeat_count: u64,
+}
+
+impl Entry {
+    // to_record returns the entry as the record given to scripts
+    fn to_record(&self) -> Map<String, Value> {
+        match json!({
+            "message": self.message,
+            "fields": self.fields,
+            "level": self.level.map(|level| level.as_str()),
+            "tags": self.tags,
+        }) {
+            Value::Object(record) => record,
+            _ => Map::new(),
+        }
+    }
+
+    // to_expr_record returns the record a filter expression is evaluated
+    // against, the detected level overrides a level key of the message
+    fn to_expr_record(&self, metadata: &Value) -> Value {
+        let mut record = expr_record(&self.message, &self.fields);
+        if let Some(level) = self.level {
+            record.insert(
+                "level".to_string(),
+                Value::String(level.as_str().to_string()),
+            );
+        }
+        record.insert("tags".to_string(), json!(self.tags));
+        record.insert("container".to_string(), metadata.clone());
+        Value::Object(record)
+    }
+
+    // into_message returns the message with the keys the legacy schema adds
+    // beside it
+    fn into_message(self) -> (String, Map<String, Value>) {
+        let mut keys = Map::new();
+        if !self.fields.is_empty() {
+            keys.insert("fields".to_string(), Value::Object(self.fields));
+        }
+        if let Some(level) = self.level {
+            keys.insert(
+                "level".to_string(),
+                Value::String(level.as_str().to_string()),
+            );
+        }
+        if !self.tags.is_empty() {
+            keys.insert("tags".to_string(), json!(self.tags));
+        }
+        if self.repeat_count > 1 {
+            keys.insert("repeat_count".to_string(), json!(self.repeat_count));
+        }
+        (self.message, keys)
+    }
+
+    // from_record returns the entry of a record returned by a script
+    fn from_record(mut record: Map<String, Value>) -> Self {
+        Self {
+            message: match record.remove("message") {
+                Some(Value::String(message)) => message,
+                Some(Value::Null) | None => "".to_string(),
+                Some(message) => message.to_string(),
+            },
+            fields: match record.remove("fields") {
+                Some(Value::Object(fields)) => fields,
+                _ => Map::new(),
+            },
+            level: record
+                .get("level")
+                .and_then(Value::as_str)
+                .and_then(Level::parse),
+            tags: match record.remove("tags") {
+                Some(Value::Array(tags)) => tags
+                    .into_iter()
+                    .filter_map(|tag| tag.as_str().map(str::to_string))
+                    .collect(),
+                _ => Vec::new(),
+            },
+            repeat_count: 0,
+        }
+    }
+}
+
+// the settings of the task a pipeline is built from, except the script that
+// is reloaded in place
+#[derive(Default, PartialEq)]
+struct PipelineConfig {
+    redact: Redact,
+    parse: Parse,
+    plugins: Vec<Plugin>,
+    min_level: String,
+    expr: String,
+    metrics: Metrics,
+    dedup: Dedup,
+    limit: Limit,
+}
+
+impl PipelineConfig {
+    fn new(container: &Container) -> Self {
+        Self {
+            redact: container.redact.clone(),
+            parse: container.parse.clone(),
+            plugins: container.plugins.clone(),
+            min_level: container.filter.min_level.clone(),
+            expr: container.filter.expr.clone(),
+            metrics: container.metrics.clone(),
+            dedup: container.dedup.clone(),
+            limit: container.limit.clone(),
+        }
+    }
+}
+
+/// Pipeline processes the message of every line read for a container before
+/// it is encoded, it is built once per reader from the task config.
+#[derive(Default)]
+pub(crate) struct Pipeline {
+    redactor: Option<Redactor>,
+    parser: Option<(GrokParser, ParseFailure)>,
+    script: Option<Scripter>,
+    // the last script error logged, the same error is not logged for every line
+    script_error: String,
+    plugins: Vec<WasmPlugin>,
+    // the last plugin error logged
+    plugin_error: String,
+    min_level: Option<Level>,
+    expr: Option<Expr>,
+    metrics: Option<MetricRules>,
+    // the container metadata the expressions are evaluated with
+    metadata: Value,
+    dedup: Option<Deduplicator<Entry>>,
+    // shared by the readers of the container
+    limiter: Option<SharedLimiter>,
+    config: PipelineConfig,
+    // the task config is invalid, messages are dropped rather than shipped
+    // unmasked
+    broken: bool,
+}
+
+impl Pipeline {
+    pub(crate) fn new(container: &Container) -> Self {
+        let mut pipeline = Self {
+            config: PipelineConfig::new(container),
+            ..Default::default()
+        };
+        if container.redact.is_enabled() {
+            match Redactor::new(&container.redact) {
+                Ok(redactor) => pipeline.redactor = Some(redactor),
+                Err(e) => {
+                    eprintln!(
+                        "[ERROR] pipeline redaction of {:?} invalid, drop its messages: {}",
+                        container.path, e
+                    );
+                    pipeline.broken = true;
+                }
+            }
+        }
+        if container.parse.is_enabled() {
+            match GrokParser::new(&container.parse) {
+                Ok(parser) => pipeline.parser = Some((parser, container.parse.on_failure.clone())),
+                Err(e) => eprintln!(
+                    "[ERROR] pipeline parse patterns of {:?} invalid, ship raw messages: {}",
+                    container.path, e
+                ),
+            }
+        }
+        pipeline.reload_script(container);
+        for config in container.plugins.iter() {
+            match WasmPlugin::new(config) {
+                Ok(plugin) => pipeline.plugins.push(plugin),
+                Err(e) if config.on_failure == PluginFailure::Drop => {
+                    eprintln!(
+                        "[ERROR] pipeline plugin of {:?} invalid, drop its messages: {}",
+                        container.path, e
+                    );
+                    pipeline.broken = true;
+                }
+                Err(e) => eprintln!(
+                    "[ERROR] pipeline plugin of {:?} invalid, bypass it: {}",
+                    container.path, e
+                ),
+            }
+        }
+        if !container.filter.min_level.is_empty() {
+            pipeline.min_level = Level::parse(&container.filter.min_level);
+            if pipeline.min_level.is_none() {
+                eprintln!(
+                    "[ERROR] pipeline min level {:?} of {:?} unknown, ship every level",
+                    container.filter.min_level, container.path
+                );
+            }
+        }
+        if !container.filter.expr.trim().is_empty() {
+            match Expr::parse_filter(&container.filter.expr) {
+                Ok(expr) => pipeline.expr = Some(expr),
+                Err(e) => eprintln!(
+                    "[ERROR] pipeline filter of {:?} invalid, ship every message: {}",
+                    container.path, e
+                ),
+            }
+        }
+        if container.metrics.is_enabled() {
+            match MetricRules::new(&container.metrics) {
+                Ok(metrics) => {
+                    if !container.metrics.output.is_empty() {
+                        emit_metrics(
+                            &container.metrics.output,
+                            container.metrics.interval,
+                            metrics.names(),
+                        );
+                    }
+                    pipeline.metrics = Some(metrics);
+                }
+                Err(e) => eprintln!(
+                    "[ERROR] pipeline metrics of {:?} invalid, derive none: {}",
+                    container.path, e
+                ),
+            }
+        }
+        if pipeline.expr.is_some() || pipeline.metrics.is_some() {
+            pipeline.metadata = container_metadata(container);
+        }
+        if container.dedup.is_enabled() {
+            pipeline.dedup = Some(Deduplicator::new(&container.dedup));
+        }
+        if container.limit.is_enabled() {
+            pipeline.limiter = Some(shared_limiter(container));
+        }
+        pipeline
+    }
+
+    /// is_stale tells whether the settings of the task other than its script
+    /// changed since the pipeline was built.
+    pub(crate) fn is_stale(&self, container: &Container) -> bool {
+        self.config != PipelineConfig::new(container)
+    }
+
+    /// reload follows an update of the task: a stale pipeline is built again,
+    /// dropping the run held by dedup and the suppressed lines not reported
+    /// yet, otherwise only the script is compiled again when it changed. A
+    /// script that does not compile leaves the running one in place.
+    pub(crate) fn reload(&mut self, container: &Container) {
+        if self.is_stale(container) {
+            println!("[INFO] pipeline rebuild of {:?}", container.path);
+            let script = self.script.take();
+            *self = Self::new(container);
+            if self.script.is_none() && container.script.is_enabled() {
+                self.script = script;
+            }
+            return;
+        }
+        self.reload_script(container);
+    }
+
+    fn reload_script(&mut self, container: &Container) {
+        let unchanged = match &self.script {
+            Some(script) => script.is_compiled_from(&container.script),
+            None => !container.script.is_enabled(),
+        };
+        if unchanged {
+            return;
+        }
+        if !container.script.is_enabled() {
+            self.script = None;
+            return;
+        }
+        match Scripter::new(&container.script, container) {
+            Ok(script) => {
+                if self.script.is_some() {
+                    println!("[INFO] pipeline reload script of {:?}", container.path);
+                }
+                self.script = Some(script);
+                self.script_error.clear();
+            }
+            Err(e) => eprintln!(
+                "[ERROR] pipeline script of {:?} invalid, keep the running one: {}",
+                container.path, e
+            ),
+        }
+    }
+
+    /// process returns the entries to ship for the message, none drops it. A
+    /// script may split the message into several entries, and with dedup the
+    /// message is held and the entry returned is the run it ended.
+    pub(crate) fn process(&mut self, message: String) -> Vec<Entry> {
+        if self.broken {
+            return Vec::new();
+        }
+        let mut entry = Entry {
+            message: match &self.redactor {
+                Some(redactor) => redactor.redact(&message),
+                None => message,
+            },
+            ..Default::default()
+        };
+        if let Some((parser, on_failure)) = &self.parser {
+            match parser.parse(&entry.message) {
+                Some(fields) => entry.fields = fields,
+                None => match on_failure {
+                    ParseFailure::Keep => {}
+                    ParseFailure::Tag => entry.tags.push(PARSE_FAILURE_TAG.to_string()),
+                    ParseFailure::Drop => return Vec::new(),
+                },
+            }
+        }
+        entry.level = detect_level(&entry.message, &entry.fields);
+        self.transform(entry)
+            .into_iter()
+            .filter_map(|entry| {
+                let entry = self.run_plugins(entry)?;
+                self.select(entry)
+            })
+            .collect()
+    }
+
+    // run_plugins runs the plugins in order on the entry message, a failing
+    // plugin is bypassed or drops the entry as configured
+    fn run_plugins(&mut self, mut entry: Entry) -> Option<Entry> {
+        for plugin in self.plugins.iter() {
+            match plugin.transform(&entry.message) {
+                Ok(Some(message)) => entry.message = message,
+                Ok(None) => return None,
+                Err(e) => {
+                    let drop = *plugin.on_failure() == PluginFailure::Drop;
+                    let e = format!("plugin {} error: {}", plugin.name(), e);
+                    if e != self.plugin_error {
+                        eprintln!(
+                            "[ERROR] pipeline {}, {} the record",
+                            e,
+                            if drop { "drop" } else { "bypass" }
+                        );
+                        self.plugin_error = e;
+                    }
+                    if drop {
+                        return None;
+                    }
+                }
+            }
+        }
+        Some(entry)
+    }
+
+    // transform runs the script on the entry, a failing script ships the
+    // entry as is
+    fn transform(&mut self, entry: Entry) -> Vec<Entry> {
+        let script = match &self.script {
+            Some(script) => script,
+            None => return vec![entry],
+        };
+        match script.run(entry.to_record()) {
+            Ok(records) => records.into_iter().map(Entry::from_record).collect(),
+            Err(e) => {
+                if e != self.script_error {
+                    eprintln!(
+                        "[ERROR] pipeline script error, ship the record as is: {}",
+                        e
+                    );
+                    self.script_error = e;
+                }
+                vec![entry]
+            }
+        }
+    }
+
+    // select derives the metrics of an entry, then applies the min level, the
+    // filter expression, dedup and the limits to it
+    fn select(&mut self, mut entry: Entry) -> Option<Entry> {
+        let record = (self.expr.is_some() || self.metrics.is_some())
+            .then(|| entry.to_expr_record(&self.metadata));
+        if let (Some(metrics), Some(record)) = (&self.metrics, &record) {
+            metrics.observe(record);
+        }
+        if let (Some(min_level), Some(level)) = (self.min_level, entry.level) {
+            if level < min_level {
+                return None;
+            }
+        }
+        if let (Some(expr), Some(record)) = (&self.expr, &record) {
+            if !expr.eval(record) {
+                return None;
+            }
+        }
+        if let Some(dedup) = &mut self.dedup {
+            let message = entry.message.clone();
+            let (held, repeat_count) = dedup.push(&message, entry)?;
+            entry = held;
+            entry.repeat_count = repeat_count;
+        }
+        self.limit(entry)
+    }
+
+    /// is_timed tells whether the pipeline holds lines or counts to write once
+    /// time passed, even with no new line read.
+    pub(crate) fn is_timed(&self) -> bool {
+        self.dedup.is_some() || self.limiter.is_some()
+    }
+
+    /// pending returns the run held by dedup once its window elapsed, `flush`
+    /// returns it at once.
+    pub(crate) fn pending(&mut self, flush: bool) -> Option<Entry> {
+        let (mut entry, repeat_count) = self.dedup.as_mut()?.expire(flush)?;
+        entry.repeat_count = repeat_count;
+        self.limit(entry)
+    }
+
+    // a collapsed run counts as one line against the limits
+    fn limit(&mut self, entry: Entry) -> Option<Entry> {
+        if let Some(limiter) = &self.limiter {
+            let allowed = limiter.lock().map_or(true, |mut limiter| {
+                limiter.allow(&entry.message, entry.level)
+            });
+            if !allowed {
+                return None;
+            }
+        }
+        Some(entry)
+    }
+
+    /// report returns the synthetic record of the lines suppressed by the
+    /// limits, once per report interval, `flush` reports them at once.
+    pub(crate) fn report(&mut self, flush: bool) -> Option<Value> {
+        let mut limiter = self.limiter.as_ref()?.lock().ok()?;
+        let suppressed = if flush {
+            limiter.flush()
+        } else {
+            limiter.report()
+        };
+        suppressed.map(suppressed_record)
+    }
+}
+
+/// RecordPipeline processes the messages of the records pushed by inputs with
+/// the task config of a container, like a reader does the lines of its log.
+pub struct RecordPipeline(Pipeline);
+
+impl RecordPipeline {
+    pub fn new(container: &Container) -> Self {
+        Self(Pipeline::new(container))
+    }
+
+    /// process returns the messages to ship for `message`, each with the keys
+    /// added beside it: the extracted `fields`, `level`, `tags` and
+    /// `repeat_count`.
+    pub fn process(&mut self, message: &str) -> Vec<(String, Map<String, Value>)> {
+        let mut entries = self.0.process(message.to_string());
+        entries.extend(self.0.pending(false));
+        entries.into_iter().map(Entry::into_message).collect()
+    }
+}
+
+fn suppressed_record(suppressed: Suppressed) -> Value {
+    json!({
+        "reason": "suppressed",
+        "message": format!(
+            "suppressed {} lines, {} sampled out and {} over the rate limit",
+            suppressed.sampled + suppressed.rate_limited,
+            suppressed.sampled,
+            suppressed.rate_limited
+        ),
+        "sampled": suppressed.sampled,
+        "rate_limited": suppressed.rate_limited,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Pipeline, RecordPipeline};
+    use db::{
+        Container, Dedup, Limit, MetricRule, Metrics, Parse, ParseFailure, Plugin, PluginFailure,
+        Redact, RedactRule, Script,
+    };
+    use filter::Level;
+
+    #[test]
+    fn it_works() {
+        let mut container = Container::default();
+        let mut pipeline = Pipeline::new(&container);
+        assert_eq!(
+            pipeline
+                .process("mail a@b.io".to_string())
+                .pop()
+                .unwrap()
+                .message,
+            "mail a@b.io"
+        );
+
+        container.redact = Redact {
+            builtins: vec!["email".to_string()],
+            ..Default::default()
+        };
+        let mut pipeline = Pipeline::new(&container);
+        assert_eq!(
+            pipeline
+                .process("mail a@b.io".to_string())
+                .pop()
+                .unwrap()
+                .message,
+            "mail [EMAIL]"
+        );
+
+        container.redact.rules.push(RedactRule {
+            name: "broken".to_string(),
+            pattern: "(".to_string(),
+            replacement: "".to_string(),
+        });
+        assert_eq!(
+            Pipeline::new(&container).process("mail a@b.io".to_string()),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn parse_it_works() {
+        let mut container = Container {
+            redact: Redact {
+                builtins: vec!["email".to_string()],
+                ..Default::default()
+            },
+            parse: Parse {
+                patterns: vec!["user %{NOTSPACE:user} %{WORD:action}".to_string()],
+                on_failure: ParseFailure::Tag,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut pipeline = Pipeline::new(&container);
+        let entry = pipeline
+            .process("user a@b.io login\n".to_string())
+            .pop()
+            .unwrap();
+        // fields are extracted from the redacted message
+        assert_eq!(entry.fields["user"], "[EMAIL]");
+        assert_eq!(entry.fields["action"], "login");
+
+        let entry = pipeline.process("starting".to_string()).pop().unwrap();
+        assert!(entry.fields.is_empty());
+        assert_eq!(entry.tags, vec!["_grokparsefailure".to_string()]);
+
+        container.parse.on_failure = ParseFailure::Drop;
+        assert_eq!(
+            Pipeline::new(&container).process("starting".to_string()),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn min_level_it_works() {
+        let mut container = Container::default();
+        container.filter.min_level = "warn".to_string();
+        let mut pipeline = Pipeline::new(&container);
+
+        assert_eq!(pipeline.process("[INFO] started".to_string()), vec![]);
+        assert_eq!(
+            pipeline
+                .process(r#"{"level":"error","msg":"down"}"#.to_string())
+                .pop()
+                .unwrap()
+                .level,
+            Some(Level::Error)
+        );
+        // a message without a level is kept
+        assert_eq!(
+            pipeline
+                .process("plain text".to_string())
+                .pop()
+                .unwrap()
+                .level,
+            None
+        );
+    }
+
+    #[test]
+    fn limit_it_works() {
+        let container = Container {
+            path: "/var/log/pods/limit".to_string(),
+            limit: Limit {
+                lines_per_sec: 2,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut pipeline = Pipeline::new(&container);
+        let shipped = (0..5)
+            .flat_map(|_| pipeline.process("line".to_string()))
+            .count();
+        assert_eq!(shipped, 2);
+        assert_eq!(pipeline.report(false), None);
+
+        let record = pipeline.report(true).unwrap();
+        assert_eq!(record["reason"], "suppressed");
+        assert_eq!(record["rate_limited"], 3);
+        assert_eq!(pipeline.report(true), None);
+        assert_eq!(Pipeline::default().report(true), None);
+    }
+
+    #[test]
+    fn limit_restart_it_works() {
+        let mut container = Container {
+            pod_uid: "uid-crash".to_string(),
+            container: "app".to_string(),
+            path: "/var/log/pods/uid-crash/app/0.log".to_string(),
+            limit: Limit {
+                lines_per_sec: 2,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut pipeline = Pipeline::new(&container);
+        assert_eq!(pipeline.process("line".to_string()).len(), 1);
+
+        // the restarted container logs to a new path with the same bucket
+        container.path = "/var/log/pods/uid-crash/app/1.log".to_string();
+        let mut restarted = Pipeline::new(&container);
+        drop(pipeline);
+        let shipped = (0..3)
+            .flat_map(|_| restarted.process("line".to_string()))
+            .count();
+        assert_eq!(shipped, 1);
+
+        // new limits of the task start a new bucket
+        container.limit.lines_per_sec = 3;
+        let shipped = (0..5)
+            .flat_map(|_| Pipeline::new(&container).process("line".to_string()))
+            .count();
+        assert_eq!(shipped, 3);
+    }
+
+    #[test]
+    fn dedup_it_works() {
+        let container = Container {
+            dedup: Dedup {
+                window: 60,
+                normalize_numbers: true,
+            },
+            limit: Limit {
+                lines_per_sec: 1,
+                ..Default::default()
+            },
+            path: "/var/log/pods/dedup".to_string(),
+            ..Default::default()
+        };
+        let mut pipeline = Pipeline::new(&container);
+        for i in 0..1000 {
+            assert_eq!(pipeline.process(format!("retry {} failed", i)), vec![]);
+        }
+        let entry = pipeline.process("connected".to_string()).pop().unwrap();
+        assert_eq!(entry.message, "retry 0 failed");
+        assert_eq!(entry.repeat_count, 1000);
+        assert_eq!(pipeline.pending(false), None);
+        // the held run is still subject to the limits
+        assert_eq!(pipeline.pending(true), None);
+        assert_eq!(pipeline.report(true).unwrap()["rate_limited"], 1);
+        assert_eq!(Pipeline::default().pending(true), None);
+        assert!(pipeline.is_timed());
+        assert!(!Pipeline::default().is_timed());
+    }
+
+    #[test]
+    fn script_it_works() {
+        let mut container = Container {
+            pod_name: "web-1".to_string(),
+            script: Script {
+                source: r#"
+                    if record.message.starts_with("GET /healthz") { return false; }
+                    record.fields.pod = container.pod_name;
+                    record.level = "warn";
+                "#
+                .to_string(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        container.filter.min_level = "warn".to_string();
+        let mut pipeline = Pipeline::new(&container);
+        assert_eq!(pipeline.process("GET /healthz".to_string()), vec![]);
+        let entry = pipeline.process("GET /".to_string()).pop().unwrap();
+        assert_eq!(entry.fields["pod"], "web-1");
+        assert_eq!(entry.level, Some(Level::Warn));
+
+        // a changed script is compiled again, a broken one is not loaded
+        container.script.source = r#"[#{message: "a"}, #{message: "b"}]"#.to_string();
+        pipeline.reload(&container);
+        let entries = pipeline.process("GET /healthz".to_string());
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].message, "b");
+        container.script.source = "[".to_string();
+        pipeline.reload(&container);
+        assert_eq!(pipeline.process("x".to_string()).len(), 2);
+
+        // a failing script ships the record as is
+        container.script.source = "record.missing.field = 1;".to_string();
+        pipeline.reload(&container);
+        assert_eq!(
+            pipeline.process("x".to_string()).pop().unwrap().message,
+            "x"
+        );
+    }
+
+    #[test]
+    fn plugin_it_works() {
+        let mut container = Container {
+            plugins: vec![Plugin {
+                path: "/nonexistent/mask.wasm".to_string(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        // a plugin that does not load is bypassed unless it drops on failure
+        let mut pipeline = Pipeline::new(&container);
+        assert_eq!(pipeline.process("line".to_string()).len(), 1);
+
+        container.plugins[0].on_failure = PluginFailure::Drop;
+        let mut pipeline = Pipeline::new(&container);
+        assert_eq!(pipeline.process("line".to_string()), vec![]);
+    }
+
+    #[test]
+    fn expr_it_works() {
+        let mut container = Container {
+            ns: "prod".to_string(),
+            parse: Parse {
+                patterns: vec!["%{WORD:method} %{NOTSPACE:path} %{INT:status:int}".to_string()],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        container.filter.expr =
+            r#"container.ns == "prod" && status >= 500 && !(path =~ "^/health")"#.to_string();
+        let mut pipeline = Pipeline::new(&container);
+        assert_eq!(pipeline.process("GET /api 502".to_string()).len(), 1);
+        assert_eq!(pipeline.process("GET /api 200".to_string()), vec![]);
+        assert_eq!(pipeline.process("GET /healthz 503".to_string()), vec![]);
+
+        container.filter.expr = r#"level in ["error", "fatal"]"#.to_string();
+        let mut pipeline = Pipeline::new(&container);
+        assert_eq!(
+            pipeline
+                .process(r#"{"level":"ERROR","msg":"down"}"#.to_string())
+                .len(),
+            1
+        );
+        assert_eq!(pipeline.process("[INFO] up".to_string()), vec![]);
+
+        // a legacy filter keeps the messages containing it
+        container.filter.expr = "[INFO]".to_string();
+        let mut pipeline = Pipeline::new(&container);
+        assert_eq!(pipeline.process("[INFO] up".to_string()).len(), 1);
+        assert_eq!(pipeline.process("[WARN] slow".to_string()), vec![]);
+    }
+
+    #[test]
+    fn metrics_it_works() {
+        let mut container = Container {
+            service_name: "checkout".to_string(),
+            metrics: Metrics {
+                rules: vec![MetricRule {
+                    name: "pipeline_test_errors_total".to_string(),
+                    expr: r#"level == "error""#.to_string(),
+                    labels: vec![("service".to_string(), "container.service_name".to_string())]
+                        .into_iter()
+                        .collect(),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        // lines dropped by the task filters are still counted
+        container.filter.min_level = "fatal".to_string();
+        let mut pipeline = Pipeline::new(&container);
+        for message in &["[ERROR] a", "[INFO] b", r#"{"level":"error"}"#] {
+            assert_eq!(pipeline.process(message.to_string()), vec![]);
+        }
+        assert!(filter::render_metrics()
+            .contains("pipeline_test_errors_total{service=\"checkout\"} 2\n"));
+    }
+
+    #[test]
+    fn record_pipeline_it_works() {
+        let container = Container {
+            redact: Redact {
+                builtins: vec!["email".to_string()],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut pipeline = RecordPipeline::new(&container);
+        let (message, keys) = pipeline.process("[ERROR] mail a@b.io").pop().unwrap();
+        assert_eq!(message, "[ERROR] mail [EMAIL]");
+        assert_eq!(keys["level"], "error");
+    }
+}
@@ -0,0 +1,159 @@
+use common::{parse_rfc3339, Result};
+use db::StartPosition;
+use serde_json::Value;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
+
+const CHUNK_SIZE: u64 = 64 * 1024;
+
+/// start_offset resolves the start position of a file to a byte offset,
+/// always at the beginning of a line.
+pub(crate) fn start_offset(path: &str, start: &StartPosition, offset: i64) -> Result<i64> {
+    let len = File::open(path)?.metadata()?.len();
+    let offset = match start {
+        StartPosition::Resume => offset as u64,
+        StartPosition::Beginning => 0,
+        StartPosition::End => len,
+        StartPosition::Offset(offset) => *offset,
+        StartPosition::LastLines(lines) => last_lines_offset(path, len, *lines)?,
+        StartPosition::Since(since) => since_offset(path, len, *since)?,
+    };
+    Ok(std::cmp::min(offset, len) as i64)
+}
+
+// last_lines_offset reads backwards from the end and returns the offset of
+// the `lines`-th line before the end
+fn last_lines_offset(path: &str, len: u64, lines: u64) -> Result<u64> {
+    if lines == 0 {
+        return Ok(len);
+    }
+    let mut file = File::open(path)?;
+    let mut buf = vec![0u8; CHUNK_SIZE as usize];
+    let mut end = len;
+    let mut newlines = 0;
+    // a trailing newline ends the last line, it does not start a new one
+    let mut skip_last = true;
+    while end > 0 {
+        let start = end.saturating_sub(CHUNK_SIZE);
+        let chunk = &mut buf[..(end - start) as usize];
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(chunk)?;
+        for (i, b) in chunk.iter().enumerate().rev() {
+            if *b != b'\n' {
+                continue;
+            }
+            if skip_last && start + i as u64 == len - 1 {
+                skip_last = false;
+                continue;
+            }
+            newlines += 1;
+            if newlines == lines {
+                return Ok(start + i as u64 + 1);
+            }
+        }
+        end = start;
+    }
+    Ok(0)
+}
+
+// line_time returns the start of the first line at or after `pos` and the
+// docker `time` of that line, None at the end of the file
+fn line_time(br: &mut BufReader<File>, pos: u64) -> Result<Option<(u64, Option<i64>)>> {
+    let mut line = String::new();
+    let mut start = pos;
+    br.seek(SeekFrom::Start(pos.saturating_sub(1)))?;
+    if pos > 0 {
+        // the byte before `pos` tells if `pos` is a line start
+        start = pos - 1 + br.read_line(&mut line)? as u64;
+        line.clear();
+    }
+    if br.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    let time = serde_json::from_str::<Value>(&line)
+        .ok()
+        .and_then(|record| record.get("time")?.as_str().and_then(parse_rfc3339));
+    Ok(Some((start, time)))
+}
+
+// since_offset binary searches the first line with a docker `time` at or
+// after `since`, lines without a time are never skipped
+pub(crate) fn since_offset(path: &str, len: u64, since: i64) -> Result<u64> {
+    let mut br = BufReader::new(File::open(path)?);
+    let (mut lo, mut hi) = (0, len);
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        match line_time(&mut br, mid)? {
+            Some((_, Some(time))) if time < since => lo = mid + 1,
+            _ => hi = mid,
+        }
+    }
+    match line_time(&mut br, lo)? {
+        Some((start, _)) => Ok(start),
+        None => Ok(len),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::start_offset;
+    use db::StartPosition;
+    use std::fs;
+
+    #[test]
+    fn it_works() {
+        let path = std::env::temp_dir().join("harvest_start_position.log");
+        let mut lines = String::new();
+        for i in 0..100 {
+            lines.push_str(&format!(
+                "{{\"log\":\"line {}\\n\",\"stream\":\"stdout\",\"time\":\"2021-02-03T04:{:02}:{:02}.000000001Z\"}}\n",
+                i,
+                i / 60,
+                i % 60
+            ));
+        }
+        fs::write(&path, &lines).unwrap();
+        let path = path.to_str().unwrap();
+        let len = lines.len() as i64;
+        let line_start = |n: usize| lines.match_indices('\n').nth(n - 1).unwrap().0 as i64 + 1;
+
+        assert_eq!(start_offset(path, &StartPosition::Resume, 7).unwrap(), 7);
+        assert_eq!(start_offset(path, &StartPosition::Beginning, 7).unwrap(), 0);
+        assert_eq!(start_offset(path, &StartPosition::End, 0).unwrap(), len);
+        assert_eq!(
+            start_offset(path, &StartPosition::Offset(1 << 40), 0).unwrap(),
+            len
+        );
+        assert_eq!(
+            start_offset(path, &StartPosition::LastLines(0), 0).unwrap(),
+            len
+        );
+        assert_eq!(
+            start_offset(path, &StartPosition::LastLines(10), 0).unwrap(),
+            line_start(90)
+        );
+        assert_eq!(
+            start_offset(path, &StartPosition::LastLines(1000), 0).unwrap(),
+            0
+        );
+
+        // 04:01:05 is line 65
+        let since = common::parse_rfc3339("2021-02-03T04:01:05Z").unwrap();
+        assert_eq!(
+            start_offset(path, &StartPosition::Since(since), 0).unwrap(),
+            line_start(65)
+        );
+        let since = common::parse_rfc3339("2020-01-01T00:00:00Z").unwrap();
+        assert_eq!(
+            start_offset(path, &StartPosition::Since(since), 0).unwrap(),
+            0
+        );
+        let since = common::parse_rfc3339("2022-01-01T00:00:00Z").unwrap();
+        assert_eq!(
+            start_offset(path, &StartPosition::Since(since), 0).unwrap(),
+            len
+        );
+
+        let _ = fs::remove_file(path);
+    }
+}
@@ -0,0 +1,352 @@
+use super::decode_message;
+use crate::envelope::Encoder;
+use crate::pipeline::Pipeline;
+use crate::position::since_offset;
+use common::{parse_rfc3339, Result};
+use db::Container;
+use output::output_write;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::RwLock;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// finished replays are reported for this long
+const REPLAY_TTL: Duration = Duration::from_secs(3600);
+// the most finished replays kept, the oldest are evicted first
+const MAX_FINISHED_REPLAYS: usize = 100;
+
+lazy_static! {
+    // replay id -> progress
+    static ref REPLAYS: RwLock<HashMap<String, ReplayProgress>> = RwLock::new(HashMap::new());
+    static ref REPLAY_SEQ: AtomicUsize = AtomicUsize::new(0);
+}
+
+#[derive(Debug, Clone, PartialEq, serde_derive::Serialize)]
+pub enum ReplayState {
+    Running,
+    Done,
+    Failed,
+}
+
+/// ReplayProgress reports a replay job, `files` counts the current and
+/// rotated files of every container of the pod.
+#[derive(Debug, Clone, PartialEq, serde_derive::Serialize)]
+pub struct ReplayProgress {
+    pub id: String,
+    pub ns: String,
+    pub pod_name: String,
+    pub output: String,
+    pub since: i64,
+    pub until: i64,
+    pub files: usize,
+    pub files_done: usize,
+    pub lines: usize,
+    pub state: ReplayState,
+    pub error: String,
+    #[serde(skip)]
+    finished: Option<Instant>,
+}
+
+// evict_finished drops the replays finished for longer than `ttl`, then the
+// oldest finished ones over `cap`
+fn evict_finished(replays: &mut HashMap<String, ReplayProgress>, ttl: Duration, cap: usize) {
+    replays.retain(|_, progress| match progress.finished {
+        Some(finished) => finished.elapsed() < ttl,
+        None => true,
+    });
+    let mut finished = replays
+        .values()
+        .filter_map(|progress| progress.finished.map(|at| (at, progress.id.clone())))
+        .collect::<Vec<(Instant, String)>>();
+    if finished.len() <= cap {
+        return;
+    }
+    finished.sort();
+    for (_, id) in finished.iter().take(finished.len() - cap) {
+        replays.remove(id);
+    }
+}
+
+fn finish(id: &str, state: ReplayState, error: String) {
+    update_progress(id, |progress| {
+        progress.state = state;
+        progress.error = error;
+        progress.finished = Some(Instant::now());
+    });
+}
+
+fn update_progress<F>(id: &str, f: F)
+where
+    F: FnOnce(&mut ReplayProgress),
+{
+    match REPLAYS.write() {
+        Ok(mut replays) => {
+            if let Some(progress) = replays.get_mut(id) {
+                f(progress)
+            }
+        }
+        Err(e) => eprintln!("[ERROR] replay progress write lock failed: {:?}", e),
+    }
+}
+
+pub fn replay_progress(id: &str) -> Option<ReplayProgress> {
+    match REPLAYS.read() {
+        Ok(replays) => replays.get(id).cloned(),
+        Err(_) => None,
+    }
+}
+
+pub fn replays() -> Vec<ReplayProgress> {
+    match REPLAYS.read() {
+        Ok(replays) => replays.values().cloned().collect(),
+        Err(_) => vec![],
+    }
+}
+
+/// rotated_files returns the rotated files of a docker json log oldest first,
+/// followed by the current file: `<id>-json.log.N`, ..., `<id>-json.log.1`, `<id>-json.log`.
+pub fn rotated_files(path: &str) -> Vec<String> {
+    let mut files = vec![];
+    let mut n = 1;
+    while Path::new(&format!("{}.{}", path, n)).is_file() {
+        files.push(format!("{}.{}", path, n));
+        n += 1;
+    }
+    files.reverse();
+    if Path::new(path).is_file() {
+        files.push(path.to_string());
+    }
+    files
+}
+
+fn line_time(line: &str) -> Option<i64> {
+    serde_json::from_str::<Value>(line)
+        .ok()
+        .and_then(|record| record.get("time")?.as_str().and_then(parse_rfc3339))
+}
+
+// replay_file writes the lines of the file within [since, until], it returns
+// false once a line after `until` is read so newer files are skipped
+fn replay_file(
+    id: &str,
+    path: &str,
+    since: i64,
+    until: i64,
+    encoder: &Encoder,
+    pipeline: &mut Pipeline,
+    output: &str,
+) -> Result<bool> {
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+    file.seek(SeekFrom::Start(since_offset(path, len, since)?))?;
+
+    let mut br = BufReader::new(file);
+    let mut line = String::new();
+    let mut lines = 0;
+    let mut more = true;
+    loop {
+        line.clear();
+        if br.read_line(&mut line)? == 0 {
+            break;
+        }
+        if let Some(time) = line_time(&line) {
+            if time > until {
+                more = false;
+                break;
+            }
+        }
+        if let Some(message) = decode_message(&line) {
+            for entry in pipeline.process(message) {
+                output_write(output, &encoder.entry(entry));
+            }
+        }
+        lines += 1;
+        if lines % 1000 == 0 {
+            update_progress(id, |progress| progress.lines += 1000);
+        }
+    }
+    update_progress(id, |progress| {
+        progress.lines += lines % 1000;
+        progress.files_done += 1;
+    });
+    Ok(more)
+}
+
+/// start_replay re-ships the lines of the containers logged between `since` and
+/// `until` (unix nanoseconds) to `output`, apart from the running readers.
+pub fn start_replay(
+    containers: Vec<Container>,
+    since: i64,
+    until: i64,
+    output: &str,
+) -> ReplayProgress {
+    let id = format!("replay-{}", REPLAY_SEQ.fetch_add(1, Ordering::SeqCst) + 1);
+    let files = containers
+        .iter()
+        .map(|container| (container.clone(), rotated_files(&container.path)))
+        .collect::<Vec<(Container, Vec<String>)>>();
+    let progress = ReplayProgress {
+        id: id.clone(),
+        ns: containers.first().map(|c| c.ns.clone()).unwrap_or_default(),
+        pod_name: containers
+            .first()
+            .map(|c| c.pod_name.clone())
+            .unwrap_or_default(),
+        output: output.to_string(),
+        since,
+        until,
+        files: files.iter().map(|(_, paths)| paths.len()).sum(),
+        files_done: 0,
+        lines: 0,
+        state: ReplayState::Running,
+        error: "".to_string(),
+        finished: None,
+    };
+    if let Ok(mut replays) = REPLAYS.write() {
+        evict_finished(&mut replays, REPLAY_TTL, MAX_FINISHED_REPLAYS);
+        replays.insert(id.clone(), progress.clone());
+    }
+
+    let output = output.to_string();
+    thread::spawn(move || {
+        println!(
+            "[INFO] start {:?} of {:?} containers to {:?}",
+            id,
+            files.len(),
+            output
+        );
+        for (container, paths) in files.iter() {
+            let encoder =
+                Encoder::new(container, &output).with_custom("replay", Value::String(id.clone()));
+            let mut pipeline = Pipeline::new(container);
+            for path in paths.iter() {
+                match replay_file(&id, path, since, until, &encoder, &mut pipeline, &output) {
+                    Ok(true) => {}
+                    Ok(false) => break,
+                    Err(e) => {
+                        eprintln!("[ERROR] {:?} read {:?} error: {:?}", id, path, e);
+                        finish(&id, ReplayState::Failed, format!("{}: {}", path, e));
+                        return;
+                    }
+                }
+            }
+            // the run held by dedup ends with the replayed lines
+            if let Some(entry) = pipeline.pending(true) {
+                output_write(&output, &encoder.entry(entry));
+            }
+        }
+        finish(&id, ReplayState::Done, "".to_string());
+        println!("[INFO] {:?} done", id);
+    });
+    progress
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        evict_finished, replay_progress, rotated_files, start_replay, ReplayProgress, ReplayState,
+    };
+    use db::Container;
+    use std::collections::HashMap;
+    use std::fs;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    fn lines(from: usize, to: usize) -> String {
+        (from..to)
+            .map(|i| {
+                format!(
+                    "{{\"log\":\"line {}\\n\",\"stream\":\"stdout\",\"time\":\"2021-02-03T04:00:{:02}Z\"}}\n",
+                    i, i
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn it_works() {
+        let dir = std::env::temp_dir().join("harvest_replay");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("58044a726890-json.log");
+        let path_str = path.to_str().unwrap().to_string();
+        fs::write(format!("{}.2", path_str), lines(0, 10)).unwrap();
+        fs::write(format!("{}.1", path_str), lines(10, 20)).unwrap();
+        fs::write(&path, lines(20, 30)).unwrap();
+
+        assert_eq!(
+            rotated_files(&path_str),
+            vec![
+                format!("{}.2", path_str),
+                format!("{}.1", path_str),
+                path_str.clone()
+            ]
+        );
+
+        let container = Container {
+            ns: "default".to_string(),
+            pod_name: "web-7d9f-abc".to_string(),
+            path: path_str.clone(),
+            ..Default::default()
+        };
+        let since = common::parse_rfc3339("2021-02-03T04:00:05Z").unwrap();
+        let until = common::parse_rfc3339("2021-02-03T04:00:14Z").unwrap();
+        let progress = start_replay(vec![container], since, until, "counter_output");
+        assert_eq!(progress.files, 3);
+
+        let mut done = None;
+        for _ in 0..100 {
+            match replay_progress(&progress.id) {
+                Some(p) if p.state != ReplayState::Running => {
+                    done = Some(p);
+                    break;
+                }
+                _ => thread::sleep(Duration::from_millis(10)),
+            }
+        }
+        let done = done.unwrap();
+        assert_eq!(done.state, ReplayState::Done);
+        // lines 5 to 14, the current file is never read
+        assert_eq!(done.lines, 10);
+        assert_eq!(done.files_done, 2);
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn evict_it_works() {
+        let progress = |id: &str, finished: Option<Instant>| ReplayProgress {
+            id: id.to_string(),
+            ns: "".to_string(),
+            pod_name: "".to_string(),
+            output: "".to_string(),
+            since: 0,
+            until: 0,
+            files: 0,
+            files_done: 0,
+            lines: 0,
+            state: ReplayState::Running,
+            error: "".to_string(),
+            finished,
+        };
+        let now = Instant::now();
+        let mut replays = HashMap::new();
+        for p in [
+            progress("running", None),
+            progress("expired", Some(now - Duration::from_secs(120))),
+            progress("old", Some(now - Duration::from_secs(30))),
+            progress("new", Some(now)),
+        ] {
+            replays.insert(p.id.clone(), p);
+        }
+
+        evict_finished(&mut replays, Duration::from_secs(60), 1);
+        let mut ids = replays.keys().cloned().collect::<Vec<String>>();
+        ids.sort();
+        assert_eq!(ids, vec!["new", "running"]);
+    }
+}
@@ -0,0 +1,356 @@
+use crate::{process_line, publish_entry, Encoder, FileReaderWriter, Pipeline};
+use crossbeam_channel::{bounded, Receiver, Sender};
+use db::Container;
+use filter::Filter;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Once, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// records buffered per tap, a slower client loses the newer records
+const TAP_BUFFER: usize = 1024;
+// tap-only readers are read by one poller on this interval
+const TAP_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+static TAP_POLLER: Once = Once::new();
+
+lazy_static! {
+    // path -> taps of the path
+    static ref TAPS: RwLock<HashMap<String, Vec<Tap>>> = RwLock::new(HashMap::new());
+    // path -> tap-only reader of the path
+    static ref TAP_READERS: Mutex<HashMap<String, TapReader>> = Mutex::new(HashMap::new());
+    static ref TAP_SEQ: AtomicUsize = AtomicUsize::new(0);
+    static ref TAP_COUNT: AtomicUsize = AtomicUsize::new(0);
+}
+
+#[derive(Clone)]
+struct Tap {
+    id: usize,
+    filter: Option<Arc<dyn Filter + Send + Sync>>,
+    tx: Sender<String>,
+}
+
+pub enum TapEvent {
+    Record(String),
+    // nothing was read before the timeout
+    Idle,
+}
+
+/// TapReceiver receives the encoded records read from the attached paths,
+/// the paths are detached when it is dropped.
+pub struct TapReceiver {
+    id: usize,
+    paths: Vec<String>,
+    filter: Option<Arc<dyn Filter + Send + Sync>>,
+    tx: Sender<String>,
+    rx: Receiver<String>,
+    // records per second, 0 is unlimited
+    rate: u64,
+    window: Instant,
+    window_count: u64,
+    dropped: u64,
+}
+
+impl TapReceiver {
+    pub fn new(filter: Option<Arc<dyn Filter + Send + Sync>>, rate: u64) -> Self {
+        let (tx, rx) = bounded::<String>(TAP_BUFFER);
+        Self {
+            id: TAP_SEQ.fetch_add(1, Ordering::SeqCst),
+            paths: vec![],
+            filter,
+            tx,
+            rx,
+            rate,
+            window: Instant::now(),
+            window_count: 0,
+            dropped: 0,
+        }
+    }
+
+    pub fn attach(&mut self, path: &str) {
+        let tap = Tap {
+            id: self.id,
+            filter: self.filter.clone(),
+            tx: self.tx.clone(),
+        };
+        match TAPS.write() {
+            Ok(mut taps) => {
+                taps.entry(path.to_string())
+                    .or_insert_with(Vec::new)
+                    .push(tap);
+                TAP_COUNT.fetch_add(1, Ordering::SeqCst);
+                self.paths.push(path.to_string());
+            }
+            Err(e) => eprintln!("[ERROR] tap write lock failed: {:?}", e),
+        }
+    }
+
+    pub fn paths(&self) -> &[String] {
+        &self.paths
+    }
+
+    /// dropped counts the records over the rate limit.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    fn allow(&mut self) -> bool {
+        if self.rate == 0 {
+            return true;
+        }
+        if self.window.elapsed() >= Duration::from_secs(1) {
+            self.window = Instant::now();
+            self.window_count = 0;
+        }
+        if self.window_count >= self.rate {
+            self.dropped += 1;
+            return false;
+        }
+        self.window_count += 1;
+        true
+    }
+
+    pub fn next(&mut self, timeout: Duration) -> TapEvent {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            // the receiver holds a sender, so the channel never disconnects
+            match self.rx.recv_timeout(timeout) {
+                Ok(record) => {
+                    if self.allow() {
+                        return TapEvent::Record(record);
+                    }
+                }
+                Err(_) => return TapEvent::Idle,
+            }
+        }
+    }
+}
+
+impl Drop for TapReceiver {
+    fn drop(&mut self) {
+        match TAPS.write() {
+            Ok(mut taps) => {
+                for path in self.paths.iter() {
+                    if let Some(list) = taps.get_mut(path) {
+                        list.retain(|tap| tap.id != self.id);
+                        if list.is_empty() {
+                            taps.remove(path);
+                        }
+                    }
+                    TAP_COUNT.fetch_sub(1, Ordering::SeqCst);
+                }
+            }
+            Err(e) => eprintln!("[ERROR] tap write lock failed: {:?}", e),
+        }
+    }
+}
+
+pub(crate) fn is_tapped(path: &str) -> bool {
+    if TAP_COUNT.load(Ordering::SeqCst) == 0 {
+        return false;
+    }
+    match TAPS.read() {
+        Ok(taps) => taps.contains_key(path),
+        Err(_) => false,
+    }
+}
+
+/// publish sends the record of a line to the taps of the path whose filter
+/// passes the line.
+pub(crate) fn publish(path: &str, line: &str, record: &str) {
+    if record.is_empty() || !is_tapped(path) {
+        return;
+    }
+    let taps = match TAPS.read() {
+        Ok(taps) => match taps.get(path) {
+            Some(list) => list.clone(),
+            None => return,
+        },
+        Err(_) => return,
+    };
+    for tap in taps {
+        if let Some(filter) = &tap.filter {
+            if !filter.pass(line) {
+                continue;
+            }
+        }
+        // a full tap drops the record instead of blocking the reader
+        let _ = tap.tx.try_send(record.to_string());
+    }
+}
+
+/// TapReader reads a tapped container that is not collected, without writing
+/// to an output.
+pub(crate) struct TapReader {
+    frw: FileReaderWriter,
+    container: Container,
+    encoder: Encoder,
+    pipeline: Pipeline,
+    br: BufReader<File>,
+    bf: String,
+    offset: i64,
+}
+
+impl TapReader {
+    pub(crate) fn new(
+        frw: FileReaderWriter,
+        container: &Container,
+        br: BufReader<File>,
+        offset: i64,
+    ) -> Self {
+        Self {
+            frw,
+            container: container.clone(),
+            encoder: Encoder::new(container, &container.output),
+            pipeline: Pipeline::new(container),
+            br,
+            bf: String::new(),
+            offset,
+        }
+    }
+
+    // read publishes the lines written since the last read, it returns false
+    // once the taps are dropped or a collecting reader is opened
+    fn read(&mut self) -> bool {
+        let path = self.container.path.as_str();
+        if !is_tapped(path) || self.frw.is_open(path) {
+            return false;
+        }
+        // a truncated file is read again from its beginning
+        if FileReaderWriter::_file_size(path) < self.offset {
+            self.offset = 0;
+            self.br = match FileReaderWriter::open_seek_buffer(path, 0) {
+                Ok(br) => br,
+                Err(_) => return false,
+            };
+        }
+        while let Ok(line_size) = self.br.read_line(&mut self.bf) {
+            if line_size == 0 {
+                break;
+            }
+            for entry in process_line(&self.container, self.bf.as_str(), &mut self.pipeline) {
+                publish_entry(path, &self.encoder, entry);
+            }
+            self.offset += line_size as i64;
+            self.bf.clear();
+        }
+        if let Some(entry) = self.pipeline.pending(false) {
+            publish_entry(path, &self.encoder, entry);
+        }
+        true
+    }
+}
+
+/// add_reader reads the path with a tap-only reader until its taps are
+/// dropped or a collecting reader is opened, false when one runs already.
+pub(crate) fn add_reader(path: &str, reader: TapReader) -> bool {
+    let added = match TAP_READERS.lock() {
+        Ok(mut readers) if !readers.contains_key(path) => {
+            readers.insert(path.to_string(), reader);
+            true
+        }
+        _ => false,
+    };
+    TAP_POLLER.call_once(|| {
+        thread::spawn(poll_readers);
+    });
+    added
+}
+
+pub(crate) fn has_reader(path: &str) -> bool {
+    match TAP_READERS.lock() {
+        Ok(readers) => readers.contains_key(path),
+        Err(_) => false,
+    }
+}
+
+// poll_readers reads every tap-only reader on the interval and closes the
+// ones no longer needed
+fn poll_readers() {
+    loop {
+        thread::sleep(TAP_POLL_INTERVAL);
+        match TAP_READERS.lock() {
+            Ok(mut readers) => readers.retain(|path, reader| {
+                let open = reader.read();
+                if !open {
+                    println!("[INFO] close tap reader of path {:?}", path);
+                }
+                open
+            }),
+            Err(e) => eprintln!("[ERROR] tap readers lock failed: {:?}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_tapped, publish, TapEvent, TapReader, TapReceiver};
+    use crate::FileReaderWriter;
+    use db::Container;
+    use filter::ContainsFilter;
+    use std::fs;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn it_works() {
+        let path = "/var/log/harvest_tap_test.log";
+        assert!(!is_tapped(path));
+
+        let mut receiver = TapReceiver::new(Some(Arc::new(ContainsFilter::new("error"))), 1);
+        receiver.attach(path);
+        assert!(is_tapped(path));
+
+        publish(path, "info line", "{\"message\":\"info line\"}");
+        publish(path, "error 1", "{\"message\":\"error 1\"}");
+        publish(path, "error 2", "{\"message\":\"error 2\"}");
+        match receiver.next(Duration::from_millis(10)) {
+            TapEvent::Record(record) => assert_eq!(record, "{\"message\":\"error 1\"}"),
+            _ => panic!("expected a record"),
+        }
+        // the second error is over the rate of one record per second
+        assert!(matches!(
+            receiver.next(Duration::from_millis(10)),
+            TapEvent::Idle
+        ));
+        assert_eq!(receiver.dropped(), 1);
+
+        drop(receiver);
+        assert!(!is_tapped(path));
+    }
+
+    #[test]
+    fn reader_it_works() {
+        let dir = std::env::temp_dir().join("harvest_tap_reader");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("58044a726890-json.log");
+        let path_str = path.to_str().unwrap();
+        fs::write(&path, "").unwrap();
+
+        let mut receiver = TapReceiver::new(None, 0);
+        receiver.attach(path_str);
+        let container = Container {
+            path: path_str.to_string(),
+            ..Default::default()
+        };
+        let br = FileReaderWriter::open_seek_buffer(path_str, 0).unwrap();
+        let mut reader = TapReader::new(FileReaderWriter::new(10), &container, br, 0);
+
+        fs::write(&path, "{\"log\":\"hello\\n\",\"stream\":\"stdout\"}\n").unwrap();
+        assert!(reader.read());
+        match receiver.next(Duration::from_millis(10)) {
+            TapEvent::Record(record) => assert!(record.contains("hello")),
+            _ => panic!("expected a record"),
+        }
+
+        // the reader closes once its taps are dropped
+        drop(receiver);
+        assert!(!reader.read());
+
+        let _ = fs::remove_dir_all(dir);
+    }
+}
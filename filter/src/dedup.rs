@@ -0,0 +1,130 @@
+use db::Dedup;
+use std::time::{Duration, Instant};
+
+// a run held by a dedup compares lines by their key
+struct Run<T> {
+    key: String,
+    item: T,
+    repeat_count: u64,
+    first: Instant,
+}
+
+/// Deduplicator holds the first item of a run of identical lines until the run
+/// ends, only one run is held so its memory is bounded by a line.
+pub struct Deduplicator<T> {
+    window: Duration,
+    normalize_numbers: bool,
+    run: Option<Run<T>>,
+}
+
+/// normalize masks every run of digits of the line with `#`.
+pub fn normalize(line: &str) -> String {
+    let mut normalized = String::with_capacity(line.len());
+    let mut digits = false;
+    for c in line.chars() {
+        if c.is_ascii_digit() {
+            if !digits {
+                normalized.push('#');
+            }
+            digits = true;
+        } else {
+            normalized.push(c);
+            digits = false;
+        }
+    }
+    normalized
+}
+
+impl<T> Deduplicator<T> {
+    pub fn new(config: &Dedup) -> Self {
+        Self {
+            window: Duration::from_secs(config.window),
+            normalize_numbers: config.normalize_numbers,
+            run: None,
+        }
+    }
+
+    fn key(&self, line: &str) -> String {
+        let line = line.trim_end_matches(['\n', '\r']);
+        if self.normalize_numbers {
+            normalize(line)
+        } else {
+            line.to_string()
+        }
+    }
+
+    /// push adds the item of a line, it returns the previous run with its
+    /// repeat count when the line does not repeat it.
+    pub fn push(&mut self, line: &str, item: T) -> Option<(T, u64)> {
+        let key = self.key(line);
+        let now = Instant::now();
+        if let Some(run) = &mut self.run {
+            if run.key == key && now.saturating_duration_since(run.first) < self.window {
+                run.repeat_count += 1;
+                return None;
+            }
+        }
+        self.run
+            .replace(Run {
+                key,
+                item,
+                repeat_count: 1,
+                first: now,
+            })
+            .map(|run| (run.item, run.repeat_count))
+    }
+
+    /// expire returns the held run once its window elapsed, or at once with
+    /// `flush`.
+    pub fn expire(&mut self, flush: bool) -> Option<(T, u64)> {
+        match &self.run {
+            Some(run) if flush || run.first.elapsed() >= self.window => {
+                self.run.take().map(|run| (run.item, run.repeat_count))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{normalize, Deduplicator};
+    use db::Dedup;
+
+    #[test]
+    fn it_works() {
+        let mut dedup = Deduplicator::new(&Dedup {
+            window: 60,
+            normalize_numbers: false,
+        });
+        assert_eq!(dedup.push("retry\n", 1), None);
+        assert_eq!(dedup.push("retry\n", 2), None);
+        assert_eq!(dedup.push("retry", 3), None);
+        assert_eq!(dedup.push("connected", 4), Some((1, 3)));
+        assert_eq!(dedup.expire(false), None);
+        assert_eq!(dedup.expire(true), Some((4, 1)));
+        assert_eq!(dedup.expire(true), None);
+
+        let mut dedup = Deduplicator::new(&Dedup {
+            window: 60,
+            normalize_numbers: true,
+        });
+        assert_eq!(dedup.push("retry 1 of request 8812", 1), None);
+        assert_eq!(dedup.push("retry 2 of request 8813", 2), None);
+        assert_eq!(dedup.push("giving up", 3), Some((1, 2)));
+
+        assert_eq!(normalize("took 15ms at 10.1.0.5"), "took #ms at #.#.#.#");
+    }
+
+    #[test]
+    fn window_it_works() {
+        let mut dedup = Deduplicator::new(&Dedup {
+            window: 0,
+            normalize_numbers: false,
+        });
+        // an elapsed window starts a new run
+        assert_eq!(dedup.push("retry", 1), None);
+        assert_eq!(dedup.push("retry", 2), Some((1, 1)));
+        assert_eq!(dedup.expire(false), Some((2, 1)));
+    }
+}
@@ -0,0 +1,661 @@
+use super::Filter;
+use regex::Regex;
+use serde_json::{Map, Value};
+use std::fmt;
+
+/// ExprError is an invalid expression, `column` is the 1-based position of
+/// the offending character.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExprError {
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ExprError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "expr error at column {}: {}", self.column, self.message)
+    }
+}
+
+fn error<T>(column: usize, message: String) -> Result<T, ExprError> {
+    Err(ExprError { column, message })
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    Num(f64),
+    True,
+    False,
+    Null,
+    In,
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    Comma,
+    And,
+    Or,
+    Not,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Match,
+    NotMatch,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Ident(ident) => write!(f, "{:?}", ident),
+            Token::Str(s) => write!(f, "string {:?}", s),
+            Token::Num(n) => write!(f, "number {}", n),
+            Token::True => write!(f, "true"),
+            Token::False => write!(f, "false"),
+            Token::Null => write!(f, "null"),
+            Token::In => write!(f, "in"),
+            Token::LParen => write!(f, "\"(\""),
+            Token::RParen => write!(f, "\")\""),
+            Token::LBracket => write!(f, "\"[\""),
+            Token::RBracket => write!(f, "\"]\""),
+            Token::Comma => write!(f, "\",\""),
+            Token::And => write!(f, "\"&&\""),
+            Token::Or => write!(f, "\"||\""),
+            Token::Not => write!(f, "\"!\""),
+            Token::Eq => write!(f, "\"==\""),
+            Token::Ne => write!(f, "\"!=\""),
+            Token::Lt => write!(f, "\"<\""),
+            Token::Le => write!(f, "\"<=\""),
+            Token::Gt => write!(f, "\">\""),
+            Token::Ge => write!(f, "\">=\""),
+            Token::Match => write!(f, "\"=~\""),
+            Token::NotMatch => write!(f, "\"!~\""),
+        }
+    }
+}
+
+fn is_ident_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_' || c == '@'
+}
+
+fn is_ident(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '@' || c == '.' || c == '-'
+}
+
+// tokenize returns the tokens of the source with their column
+fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, ExprError> {
+    let chars = source.chars().collect::<Vec<char>>();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let column = i + 1;
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        let next = chars.get(i + 1).copied();
+        let (token, len) = match (c, next) {
+            ('(', _) => (Token::LParen, 1),
+            (')', _) => (Token::RParen, 1),
+            ('[', _) => (Token::LBracket, 1),
+            (']', _) => (Token::RBracket, 1),
+            (',', _) => (Token::Comma, 1),
+            ('&', Some('&')) => (Token::And, 2),
+            ('|', Some('|')) => (Token::Or, 2),
+            ('=', Some('=')) => (Token::Eq, 2),
+            ('=', Some('~')) => (Token::Match, 2),
+            ('!', Some('=')) => (Token::Ne, 2),
+            ('!', Some('~')) => (Token::NotMatch, 2),
+            ('!', _) => (Token::Not, 1),
+            ('<', Some('=')) => (Token::Le, 2),
+            ('<', _) => (Token::Lt, 1),
+            ('>', Some('=')) => (Token::Ge, 2),
+            ('>', _) => (Token::Gt, 1),
+            ('"', _) | ('\'', _) => {
+                let mut s = String::new();
+                let mut j = i + 1;
+                loop {
+                    match chars.get(j) {
+                        None => return error(column, "unterminated string".to_string()),
+                        Some(&q) if q == c => break,
+                        Some('\\') => {
+                            match chars.get(j + 1) {
+                                Some('n') => s.push('\n'),
+                                Some('t') => s.push('\t'),
+                                Some(&escaped) => s.push(escaped),
+                                None => return error(column, "unterminated string".to_string()),
+                            }
+                            j += 2;
+                        }
+                        Some(&other) => {
+                            s.push(other);
+                            j += 1;
+                        }
+                    }
+                }
+                (Token::Str(s), j + 1 - i)
+            }
+            (c, _)
+                if c.is_ascii_digit() || (c == '-' && next.is_some_and(|n| n.is_ascii_digit())) =>
+            {
+                let mut j = i + 1;
+                while j < chars.len()
+                    && (chars[j].is_ascii_digit()
+                        || chars[j] == '.'
+                        || chars[j] == 'e'
+                        || chars[j] == 'E'
+                        || ((chars[j] == '-' || chars[j] == '+')
+                            && (chars[j - 1] == 'e' || chars[j - 1] == 'E')))
+                {
+                    j += 1;
+                }
+                let literal = chars[i..j].iter().collect::<String>();
+                match literal.parse::<f64>() {
+                    Ok(n) => (Token::Num(n), j - i),
+                    Err(_) => return error(column, format!("invalid number {:?}", literal)),
+                }
+            }
+            (c, _) if is_ident_start(c) => {
+                let mut j = i + 1;
+                while j < chars.len() && is_ident(chars[j]) {
+                    j += 1;
+                }
+                let ident = chars[i..j].iter().collect::<String>();
+                let token = match ident.as_str() {
+                    "true" => Token::True,
+                    "false" => Token::False,
+                    "null" => Token::Null,
+                    "in" => Token::In,
+                    _ => Token::Ident(ident),
+                };
+                (token, j - i)
+            }
+            (c, _) => return error(column, format!("unexpected character {:?}", c)),
+        };
+        tokens.push((token, column));
+        i += len;
+    }
+    Ok(tokens)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Compare {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug)]
+enum Operand {
+    Path(Vec<String>),
+    Literal(Value),
+}
+
+#[derive(Debug)]
+enum Node {
+    Or(Box<Node>, Box<Node>),
+    And(Box<Node>, Box<Node>),
+    Not(Box<Node>),
+    Compare(Operand, Compare, Operand),
+    Match(Operand, Regex, bool),
+    In(Operand, Operand),
+    Truthy(Operand),
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(token, _)| token)
+    }
+
+    fn column(&self) -> usize {
+        self.tokens
+            .get(self.pos)
+            .map(|(_, column)| *column)
+            .unwrap_or(self.end)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).map(|(token, _)| token.clone());
+        self.pos += 1;
+        token
+    }
+
+    fn unexpected<T>(&self, expected: &str) -> Result<T, ExprError> {
+        match self.peek() {
+            Some(token) => error(
+                self.column(),
+                format!("unexpected {}, expected {}", token, expected),
+            ),
+            None => error(
+                self.column(),
+                format!("unexpected end of expression, expected {}", expected),
+            ),
+        }
+    }
+
+    fn or(&mut self) -> Result<Node, ExprError> {
+        let mut node = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.next();
+            node = Node::Or(Box::new(node), Box::new(self.and()?));
+        }
+        Ok(node)
+    }
+
+    fn and(&mut self) -> Result<Node, ExprError> {
+        let mut node = self.unary()?;
+        while self.peek() == Some(&Token::And) {
+            self.next();
+            node = Node::And(Box::new(node), Box::new(self.unary()?));
+        }
+        Ok(node)
+    }
+
+    fn unary(&mut self) -> Result<Node, ExprError> {
+        match self.peek() {
+            Some(Token::Not) => {
+                self.next();
+                Ok(Node::Not(Box::new(self.unary()?)))
+            }
+            Some(Token::LParen) => {
+                self.next();
+                let node = self.or()?;
+                if self.peek() != Some(&Token::RParen) {
+                    return self.unexpected("\")\"");
+                }
+                self.next();
+                Ok(node)
+            }
+            _ => self.comparison(),
+        }
+    }
+
+    fn comparison(&mut self) -> Result<Node, ExprError> {
+        let left = self.operand()?;
+        let compare = match self.peek() {
+            Some(Token::Eq) => Compare::Eq,
+            Some(Token::Ne) => Compare::Ne,
+            Some(Token::Lt) => Compare::Lt,
+            Some(Token::Le) => Compare::Le,
+            Some(Token::Gt) => Compare::Gt,
+            Some(Token::Ge) => Compare::Ge,
+            Some(Token::Match) | Some(Token::NotMatch) => {
+                let negate = self.next() == Some(Token::NotMatch);
+                let column = self.column();
+                let pattern = match self.next() {
+                    Some(Token::Str(pattern)) => pattern,
+                    _ => {
+                        self.pos -= 1;
+                        return self.unexpected("a regex string");
+                    }
+                };
+                return match Regex::new(&pattern) {
+                    Ok(regex) => Ok(Node::Match(left, regex, negate)),
+                    Err(e) => error(column, format!("invalid regex {:?}: {}", pattern, e)),
+                };
+            }
+            Some(Token::In) => {
+                self.next();
+                return Ok(Node::In(left, self.operand()?));
+            }
+            _ => return Ok(Node::Truthy(left)),
+        };
+        self.next();
+        Ok(Node::Compare(left, compare, self.operand()?))
+    }
+
+    fn literal(&mut self) -> Result<Value, ExprError> {
+        match self.next() {
+            Some(Token::Str(s)) => Ok(Value::String(s)),
+            Some(Token::Num(n)) => Ok(serde_json::Number::from_f64(n)
+                .map(Value::Number)
+                .unwrap_or(Value::Null)),
+            Some(Token::True) => Ok(Value::Bool(true)),
+            Some(Token::False) => Ok(Value::Bool(false)),
+            Some(Token::Null) => Ok(Value::Null),
+            _ => {
+                self.pos -= 1;
+                self.unexpected("a value")
+            }
+        }
+    }
+
+    fn operand(&mut self) -> Result<Operand, ExprError> {
+        match self.peek() {
+            Some(Token::Ident(_)) => match self.next() {
+                Some(Token::Ident(ident)) => Ok(Operand::Path(
+                    ident.split('.').map(|key| key.to_string()).collect(),
+                )),
+                _ => unreachable!(),
+            },
+            Some(Token::LBracket) => {
+                self.next();
+                let mut list = Vec::new();
+                if self.peek() == Some(&Token::RBracket) {
+                    self.next();
+                    return Ok(Operand::Literal(Value::Array(list)));
+                }
+                loop {
+                    list.push(self.literal()?);
+                    match self.next() {
+                        Some(Token::Comma) => {}
+                        Some(Token::RBracket) => break,
+                        _ => {
+                            self.pos -= 1;
+                            return self.unexpected("\",\" or \"]\"");
+                        }
+                    }
+                }
+                Ok(Operand::Literal(Value::Array(list)))
+            }
+            Some(_) => Ok(Operand::Literal(self.literal()?)),
+            None => self.unexpected("a field or a value"),
+        }
+    }
+}
+
+/// resolve returns the value of a record at a path of keys and list indexes.
+pub fn resolve<'a>(record: &'a Value, path: &[String]) -> Option<&'a Value> {
+    let mut value = record;
+    for key in path {
+        value = match value {
+            Value::Object(map) => map.get(key)?,
+            Value::Array(list) => list.get(key.parse::<usize>().ok()?)?,
+            _ => return None,
+        };
+    }
+    Some(value)
+}
+
+pub(crate) fn as_number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => s.trim().parse::<f64>().ok(),
+        _ => None,
+    }
+}
+
+fn as_text(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Null => None,
+        other => Some(other.to_string()),
+    }
+}
+
+// equal compares numbers by value, so a string field "500" equals 500
+fn equal(left: &Value, right: &Value) -> bool {
+    if left.is_number() || right.is_number() {
+        if let (Some(l), Some(r)) = (as_number(left), as_number(right)) {
+            return l == r;
+        }
+    }
+    left == right
+}
+
+fn truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(b) => *b,
+        Value::Number(n) => n.as_f64() != Some(0.0),
+        Value::String(s) => !s.is_empty(),
+        Value::Array(list) => !list.is_empty(),
+        Value::Object(_) => true,
+    }
+}
+
+// a filter with one of these is an expression, plain text without them is a
+// legacy substring filter
+const EXPR_CHARS: [char; 9] = ['&', '|', '=', '!', '<', '>', '~', '"', '\''];
+
+/// Expr is a filter expression evaluated against a record, such as
+/// `level in ["error","warn"] && status >= 500 && !(path =~ "/health")`.
+///
+/// Fields are dotted paths into the record, a missing field is null. `==`,
+/// `!=`, `<`, `<=`, `>` and `>=` compare numbers by value and strings in
+/// order, `=~` and `!~` match a regex, `in` looks a value up in a list or a
+/// substring up in a string, and a field alone is true unless null, false,
+/// 0 or empty. `!`, `&&`, `||` and parentheses combine them.
+#[derive(Debug)]
+pub struct Expr {
+    root: Node,
+    legacy: bool,
+}
+
+impl Expr {
+    pub fn parse(source: &str) -> Result<Self, ExprError> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser {
+            tokens,
+            pos: 0,
+            end: source.chars().count() + 1,
+        };
+        if parser.peek().is_none() {
+            return error(1, "empty expression".to_string());
+        }
+        let root = parser.or()?;
+        if parser.peek().is_some() {
+            return parser.unexpected("\"&&\", \"||\" or the end");
+        }
+        Ok(Self {
+            root,
+            legacy: false,
+        })
+    }
+
+    /// parse_filter parses the `expr` of a task filter. Plain text without
+    /// operators or quotes that is not an expression, such as `[INFO]`, is a
+    /// legacy filter matched as a substring of the message.
+    pub fn parse_filter(source: &str) -> Result<Self, ExprError> {
+        match Self::parse(source) {
+            Ok(expr) => Ok(expr),
+            Err(_) if !source.contains(&EXPR_CHARS[..]) => Ok(Self {
+                root: Node::In(
+                    Operand::Literal(Value::String(source.to_string())),
+                    Operand::Path(vec!["message".to_string()]),
+                ),
+                legacy: true,
+            }),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// is_legacy tells whether the filter is matched as a substring.
+    pub fn is_legacy(&self) -> bool {
+        self.legacy
+    }
+
+    /// eval tells whether the record matches the expression.
+    pub fn eval(&self, record: &Value) -> bool {
+        Self::eval_node(&self.root, record)
+    }
+
+    fn value<'a>(operand: &'a Operand, record: &'a Value) -> &'a Value {
+        match operand {
+            Operand::Path(path) => resolve(record, path).unwrap_or(&Value::Null),
+            Operand::Literal(value) => value,
+        }
+    }
+
+    fn eval_node(node: &Node, record: &Value) -> bool {
+        match node {
+            Node::Or(left, right) => {
+                Self::eval_node(left, record) || Self::eval_node(right, record)
+            }
+            Node::And(left, right) => {
+                Self::eval_node(left, record) && Self::eval_node(right, record)
+            }
+            Node::Not(node) => !Self::eval_node(node, record),
+            Node::Truthy(operand) => truthy(Self::value(operand, record)),
+            Node::Match(operand, regex, negate) => {
+                let matched = as_text(Self::value(operand, record))
+                    .map(|text| regex.is_match(&text))
+                    .unwrap_or(false);
+                matched != *negate
+            }
+            Node::In(left, right) => {
+                let left = Self::value(left, record);
+                match Self::value(right, record) {
+                    Value::Array(list) => list.iter().any(|item| equal(left, item)),
+                    Value::String(s) => as_text(left).is_some_and(|text| s.contains(&text)),
+                    _ => false,
+                }
+            }
+            Node::Compare(left, compare, right) => {
+                let left = Self::value(left, record);
+                let right = Self::value(right, record);
+                match compare {
+                    Compare::Eq => equal(left, right),
+                    Compare::Ne => !equal(left, right),
+                    _ => {
+                        let ordering = match (as_number(left), as_number(right)) {
+                            (Some(l), Some(r)) if left.is_number() || right.is_number() => {
+                                l.partial_cmp(&r)
+                            }
+                            _ => match (left, right) {
+                                (Value::String(l), Value::String(r)) => Some(l.cmp(r)),
+                                _ => None,
+                            },
+                        };
+                        match ordering {
+                            Some(ordering) => match compare {
+                                Compare::Lt => ordering.is_lt(),
+                                Compare::Le => ordering.is_le(),
+                                Compare::Gt => ordering.is_gt(),
+                                _ => ordering.is_ge(),
+                            },
+                            None => false,
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// expr_record returns the record an expression is evaluated against, the
+/// keys of a json message and the parsed fields with the message as `message`.
+pub fn expr_record(message: &str, fields: &Map<String, Value>) -> Map<String, Value> {
+    let mut record = match serde_json::from_str::<Value>(message) {
+        Ok(Value::Object(record)) => record,
+        _ => Map::new(),
+    };
+    for (key, value) in fields {
+        record.insert(key.clone(), value.clone());
+    }
+    record.insert("message".to_string(), Value::String(message.to_string()));
+    record
+}
+
+impl Filter for Expr {
+    fn pass(&self, message: &str) -> bool {
+        self.eval(&Value::Object(expr_record(message, &Map::new())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{expr_record, Expr};
+    use crate::Filter;
+    use serde_json::{json, Map};
+
+    #[test]
+    fn it_works() {
+        let expr =
+            Expr::parse(r#"level in ["error","warn"] && status >= 500 && !(path =~ "/health")"#)
+                .unwrap();
+        assert!(expr.eval(&json!({"level":"error","status":503,"path":"/api"})));
+        assert!(expr.eval(&json!({"level":"warn","status":"500","path":"/api"})));
+        assert!(!expr.eval(&json!({"level":"info","status":503,"path":"/api"})));
+        assert!(!expr.eval(&json!({"level":"error","status":404,"path":"/api"})));
+        assert!(!expr.eval(&json!({"level":"error","status":503,"path":"/healthz"})));
+        assert!(!expr.eval(&json!({"level":"error"})));
+
+        let expr = Expr::parse(
+            r#"container.ns == 'prod' || (user.id != null && user.tags.0 == "vip") || "timeout" in message"#,
+        )
+        .unwrap();
+        assert!(expr.eval(&json!({"container":{"ns":"prod"}})));
+        assert!(expr.eval(&json!({"user":{"id":1,"tags":["vip"]}})));
+        assert!(expr.eval(&json!({"message":"read timeout after 3s"})));
+        assert!(!expr.eval(&json!({"user":{"tags":["vip"]}})));
+
+        let expr = Expr::parse("error && duration_ms > 1.5e3 && method !~ '^(GET|HEAD)$'").unwrap();
+        assert!(expr.pass(r#"{"error":"EOF","duration_ms":2000,"method":"POST"}"#));
+        assert!(!expr.pass(r#"{"error":"","duration_ms":2000,"method":"POST"}"#));
+        assert!(!expr.pass("plain text"));
+
+        let mut fields = Map::new();
+        fields.insert("status".to_string(), json!(502));
+        let record = expr_record(r#"{"status":200,"msg":"ok"}"#, &fields);
+        assert_eq!(record["status"], 502);
+        assert_eq!(record["msg"], "ok");
+    }
+
+    #[test]
+    fn errors_it_works() {
+        for (source, column, message) in &[
+            ("", 1, "empty expression"),
+            (
+                "status >=",
+                10,
+                "unexpected end of expression, expected a field or a value",
+            ),
+            ("status >= 500 &&", 17, "unexpected end of expression"),
+            ("(level == \"error\"", 18, "expected \")\""),
+            ("level == \"error", 10, "unterminated string"),
+            ("path =~ \"(\"", 9, "invalid regex"),
+            (
+                "path =~ 5",
+                9,
+                "unexpected number 5, expected a regex string",
+            ),
+            ("level in [\"a\" \"b\"]", 15, "expected \",\" or \"]\""),
+            ("status > 5 status", 12, "unexpected \"status\""),
+            ("status # 5", 8, "unexpected character '#'"),
+        ] {
+            let err = Expr::parse(source).unwrap_err();
+            assert_eq!(err.column, *column, "{:?}: {}", source, err);
+            assert!(err.message.contains(message), "{:?}: {}", source, err);
+        }
+        assert_eq!(
+            Expr::parse("a ==").unwrap_err().to_string(),
+            "expr error at column 5: unexpected end of expression, expected a field or a value"
+        );
+    }
+
+    #[test]
+    fn legacy_it_works() {
+        let expr = Expr::parse_filter("[INFO]").unwrap();
+        assert!(expr.is_legacy());
+        assert!(expr.eval(&json!({"message":"2021-01-01 [INFO] started"})));
+        assert!(!expr.eval(&json!({"message":"2021-01-01 [WARN] slow"})));
+
+        let expr = Expr::parse_filter(r#""[INFO]" in message"#).unwrap();
+        assert!(!expr.is_legacy());
+        assert!(expr.eval(&json!({"message":"[INFO] started"})));
+
+        // a broken expression is not taken for text
+        assert_eq!(
+            Expr::parse_filter("status >= 500 &&& ok")
+                .unwrap_err()
+                .column,
+            17
+        );
+        assert!(Expr::parse_filter(r#"message == "[INFO]"#).is_err());
+    }
+}
@@ -0,0 +1,281 @@
+use db::Parse;
+use regex::Regex;
+use serde_json::{Map, Number, Value};
+use std::collections::HashMap;
+
+// patterns referring to patterns deeper than this are taken as recursive
+const MAX_DEPTH: usize = 32;
+
+/// LIBRARY is the bundled grok patterns, one `NAME pattern` per line.
+const LIBRARY: &str = r#"
+USERNAME [a-zA-Z0-9._-]+
+USER %{USERNAME}
+INT [+-]?\d+
+POSINT \b[1-9]\d*\b
+NONNEGINT \b\d+\b
+NUMBER [+-]?(?:\d+(?:\.\d+)?|\.\d+)
+BASE16NUM (?:0[xX])?[0-9A-Fa-f]+
+WORD \b\w+\b
+NOTSPACE \S+
+SPACE \s*
+DATA .*?
+GREEDYDATA .*
+QUOTEDSTRING "(?:[^"\\]|\\.)*"
+QS %{QUOTEDSTRING}
+UUID [A-Fa-f0-9]{8}-(?:[A-Fa-f0-9]{4}-){3}[A-Fa-f0-9]{12}
+IPV4 (?:(?:25[0-5]|2[0-4]\d|1?\d?\d)\.){3}(?:25[0-5]|2[0-4]\d|1?\d?\d)
+IPV6 (?:[0-9A-Fa-f]{0,4}:){2,7}[0-9A-Fa-f]{0,4}
+IP (?:%{IPV6}|%{IPV4})
+HOSTNAME \b[0-9A-Za-z][0-9A-Za-z-]{0,62}(?:\.[0-9A-Za-z][0-9A-Za-z-]{0,62})*\.?\b
+IPORHOST (?:%{IP}|%{HOSTNAME})
+HOSTPORT %{IPORHOST}:%{POSINT}
+UNIXPATH (?:/[^\s/]*)+
+URIPATHPARAM \S+
+MONTH \b(?:Jan|Feb|Mar|Apr|May|Jun|Jul|Aug|Sep|Oct|Nov|Dec)[a-z]*\b
+MONTHNUM (?:0?[1-9]|1[0-2])
+MONTHDAY (?:0[1-9]|[12]\d|3[01]|[1-9])
+YEAR \d{4}
+HOUR (?:2[0-3]|[01]?\d)
+MINUTE [0-5]\d
+SECOND (?:[0-5]?\d|60)(?:[.,]\d+)?
+TIME %{HOUR}:%{MINUTE}:%{SECOND}
+ISO8601_TIMEZONE (?:Z|[+-]%{HOUR}(?::?%{MINUTE}))
+TIMESTAMP_ISO8601 %{YEAR}-%{MONTHNUM}-%{MONTHDAY}[T ]%{HOUR}:?%{MINUTE}(?::?%{SECOND})?%{ISO8601_TIMEZONE}?
+HTTPDATE %{MONTHDAY}/%{MONTH}/%{YEAR}:%{TIME} %{INT}
+LOGLEVEL (?i:trace|debug|info|notice|warn(?:ing)?|err(?:or)?|crit(?:ical)?|fatal|severe|emerg(?:ency)?|alert)
+JAVACLASS (?:[a-zA-Z$_][a-zA-Z$_0-9]*\.)*[a-zA-Z$_][a-zA-Z$_0-9]*
+COMMONAPACHELOG %{IPORHOST:clientip} %{USER:ident} %{USER:auth} \[%{HTTPDATE:timestamp}\] "(?:%{WORD:verb} %{NOTSPACE:request}(?: HTTP/%{NUMBER:httpversion})?|%{DATA:rawrequest})" %{INT:response:int} (?:%{INT:bytes:int}|-)
+COMBINEDAPACHELOG %{COMMONAPACHELOG} %{QS:referrer} %{QS:agent}
+APACHE_ERROR \[%{DATA:timestamp}\] \[(?:%{WORD:module})?:%{LOGLEVEL:level}\] \[pid %{POSINT:pid:int}(?::tid %{INT:tid:int})?\](?: \[client %{IPORHOST:clientip}(?::%{POSINT:clientport:int})?\])? %{GREEDYDATA:message}
+NGINX_ACCESS %{IPORHOST:remote_addr} - %{USER:remote_user} \[%{HTTPDATE:time_local}\] "%{WORD:method} %{NOTSPACE:request} HTTP/%{NUMBER:http_version}" %{INT:status:int} %{INT:body_bytes_sent:int} %{QS:http_referer} %{QS:http_user_agent}
+NGINX_ERRORTIME %{YEAR}/%{MONTHNUM}/%{MONTHDAY} %{TIME}
+NGINX_ERROR %{NGINX_ERRORTIME:time} \[%{LOGLEVEL:level}\] %{POSINT:pid:int}#%{NONNEGINT:tid:int}: %{GREEDYDATA:message}
+JAVA_LOG4J %{TIMESTAMP_ISO8601:timestamp}\s+%{LOGLEVEL:level}\s+(?:\[%{DATA:thread}\]\s+)?%{JAVACLASS:logger}(?::%{INT:line:int})?\s+-\s+%{GREEDYDATA:message}
+KLOGLEVEL [IWEF]
+KLOGDATE \d{4}
+GO_KLOG %{KLOGLEVEL:level}%{KLOGDATE:date} %{TIME:time}\s+%{INT:thread:int} %{NOTSPACE:file}:%{POSINT:line:int}\] %{GREEDYDATA:message}
+"#;
+
+lazy_static! {
+    static ref REFERENCE: Regex =
+        Regex::new(r"%\{(\w+)(?::([\w.@-]+))?(?::(int|float))?\}").unwrap();
+    static ref DEFINITIONS: HashMap<&'static str, &'static str> = LIBRARY
+        .lines()
+        .filter_map(|line| {
+            let mut split = line.splitn(2, ' ');
+            match (split.next(), split.next()) {
+                (Some(name), Some(pattern)) => Some((name, pattern)),
+                _ => None,
+            }
+        })
+        .collect();
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FieldType {
+    String,
+    Int,
+    Float,
+}
+
+// a named group of the compiled regex and the field it fills
+#[derive(Debug, Clone)]
+struct Field {
+    group: String,
+    name: String,
+    field_type: FieldType,
+}
+
+struct Compiled {
+    regex: Regex,
+    fields: Vec<Field>,
+}
+
+fn expand(
+    pattern: &str,
+    definitions: &HashMap<String, String>,
+    depth: usize,
+    fields: &mut Vec<Field>,
+) -> Result<String, String> {
+    if depth > MAX_DEPTH {
+        return Err(format!("grok pattern {:?} is recursive", pattern));
+    }
+    let mut expanded = String::new();
+    let mut last = 0;
+    for caps in REFERENCE.captures_iter(pattern) {
+        let reference = caps.get(0).unwrap();
+        expanded.push_str(&pattern[last..reference.start()]);
+        last = reference.end();
+
+        let name = &caps[1];
+        let definition = match definitions.get(name) {
+            Some(definition) => definition.as_str(),
+            None => match DEFINITIONS.get(name) {
+                Some(definition) => *definition,
+                None => return Err(format!("unknown grok pattern {:?}", name)),
+            },
+        };
+        let inner = expand(definition, definitions, depth + 1, fields)?;
+        match caps.get(2) {
+            Some(field) => {
+                let group = format!("g{}", fields.len());
+                fields.push(Field {
+                    group: group.clone(),
+                    name: field.as_str().to_string(),
+                    field_type: match caps.get(3).map(|t| t.as_str()) {
+                        Some("int") => FieldType::Int,
+                        Some("float") => FieldType::Float,
+                        _ => FieldType::String,
+                    },
+                });
+                expanded.push_str(&format!("(?P<{}>{})", group, inner));
+            }
+            None => expanded.push_str(&format!("(?:{})", inner)),
+        }
+    }
+    expanded.push_str(&pattern[last..]);
+    Ok(expanded)
+}
+
+fn compile(pattern: &str, definitions: &HashMap<String, String>) -> Result<Compiled, String> {
+    let mut fields = vec![];
+    let expanded = expand(pattern, definitions, 0, &mut fields)?;
+    let regex =
+        Regex::new(&expanded).map_err(|e| format!("invalid grok pattern {:?}: {}", pattern, e))?;
+    // the named captures of a plain regex fill the field of their name
+    for name in regex.capture_names().flatten() {
+        if !fields.iter().any(|field| field.group == name) {
+            fields.push(Field {
+                group: name.to_string(),
+                name: name.to_string(),
+                field_type: FieldType::String,
+            });
+        }
+    }
+    Ok(Compiled { regex, fields })
+}
+
+/// GrokParser extracts the fields of a message with the first matching
+/// pattern of a task.
+pub struct GrokParser {
+    patterns: Vec<Compiled>,
+}
+
+impl GrokParser {
+    pub fn new(config: &Parse) -> Result<Self, String> {
+        let patterns = config
+            .patterns
+            .iter()
+            .map(|pattern| compile(pattern, &config.definitions))
+            .collect::<Result<Vec<Compiled>, String>>()?;
+        Ok(Self { patterns })
+    }
+
+    /// parse returns the fields of the first matching pattern, None when no
+    /// pattern matches.
+    pub fn parse(&self, message: &str) -> Option<Map<String, Value>> {
+        let message = message.trim_end_matches(['\n', '\r']);
+        for compiled in self.patterns.iter() {
+            let caps = match compiled.regex.captures(message) {
+                Some(caps) => caps,
+                None => continue,
+            };
+            let mut fields = Map::new();
+            for field in compiled.fields.iter() {
+                let value = match caps.name(&field.group) {
+                    Some(value) => value.as_str(),
+                    None => continue,
+                };
+                let value = match field.field_type {
+                    FieldType::Int => match value.parse::<i64>() {
+                        Ok(n) => Value::Number(n.into()),
+                        Err(_) => Value::String(value.to_string()),
+                    },
+                    FieldType::Float => {
+                        match value.parse::<f64>().ok().and_then(Number::from_f64) {
+                            Some(n) => Value::Number(n),
+                            None => Value::String(value.to_string()),
+                        }
+                    }
+                    FieldType::String => Value::String(value.to_string()),
+                };
+                fields.insert(field.name.clone(), value);
+            }
+            return Some(fields);
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GrokParser;
+    use db::Parse;
+
+    fn parser(patterns: &[&str]) -> GrokParser {
+        GrokParser::new(&Parse {
+            patterns: patterns.iter().map(|p| p.to_string()).collect(),
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn library_it_works() {
+        let fields = parser(&["%{NGINX_ACCESS}"])
+            .parse(r#"10.1.0.5 - - [03/Feb/2021:04:05:06 +0000] "GET /index.html HTTP/1.1" 200 612 "-" "curl/7.68.0""#)
+            .unwrap();
+        assert_eq!(fields["remote_addr"], "10.1.0.5");
+        assert_eq!(fields["method"], "GET");
+        assert_eq!(fields["status"], 200);
+        assert_eq!(fields["http_user_agent"], "\"curl/7.68.0\"");
+
+        let fields = parser(&["%{COMBINEDAPACHELOG}"])
+            .parse(r#"127.0.0.1 - frank [10/Oct/2000:13:55:36 -0700] "GET /apache_pb.gif HTTP/1.0" 200 2326 "http://www.example.com/start.html" "Mozilla/4.08""#)
+            .unwrap();
+        assert_eq!(fields["auth"], "frank");
+        assert_eq!(fields["bytes"], 2326);
+
+        let fields = parser(&["%{JAVA_LOG4J}"])
+            .parse("2021-02-03 04:05:06,789 ERROR [main] com.example.App - connection refused\n")
+            .unwrap();
+        assert_eq!(fields["level"], "ERROR");
+        assert_eq!(fields["thread"], "main");
+        assert_eq!(fields["logger"], "com.example.App");
+        assert_eq!(fields["message"], "connection refused");
+
+        let fields = parser(&["%{GO_KLOG}"])
+            .parse("E0203 04:05:06.789012   12345 main.go:42] watch failed")
+            .unwrap();
+        assert_eq!(fields["level"], "E");
+        assert_eq!(fields["line"], 42);
+        assert_eq!(fields["message"], "watch failed");
+    }
+
+    #[test]
+    fn regex_and_definitions_it_works() {
+        let parser = parser(&[r"^took (?P<duration>\d+)ms$", "%{NGINX_ERROR}"]);
+        assert_eq!(parser.parse("took 15ms").unwrap()["duration"], "15");
+        assert!(parser.parse("plain text").is_none());
+
+        let mut parse = Parse {
+            patterns: vec!["%{ORDER:order} %{NUMBER:amount:float}".to_string()],
+            ..Default::default()
+        };
+        assert!(GrokParser::new(&parse).is_err());
+        parse
+            .definitions
+            .insert("ORDER".to_string(), r"ord-\d+".to_string());
+        let fields = GrokParser::new(&parse)
+            .unwrap()
+            .parse("ord-7 12.5")
+            .unwrap();
+        assert_eq!(fields["order"], "ord-7");
+        assert_eq!(fields["amount"], 12.5);
+
+        parse
+            .definitions
+            .insert("ORDER".to_string(), "%{ORDER}".to_string());
+        assert!(GrokParser::new(&parse).is_err());
+    }
+}
@@ -0,0 +1,167 @@
+use regex::Regex;
+use serde_json::{Map, Value};
+
+// keys holding the level of json messages and of parsed fields
+const LEVEL_KEYS: [&str; 5] = ["level", "severity", "lvl", "loglevel", "log.level"];
+// only the head of a message is searched for a level prefix
+const PREFIX_SIZE: usize = 64;
+
+lazy_static! {
+    static ref KLOG: Regex = Regex::new(r"^([IWEF])\d{4} ").unwrap();
+    static ref LOGFMT: Regex =
+        Regex::new(r#"(?:^|\s)(?:level|lvl|severity)="?([A-Za-z]+)"#).unwrap();
+    static ref PREFIX: Regex = Regex::new(
+        r"(?i)(?:^\s*|\[)(trace|debug|info|notice|warn|warning|error|err|fatal|critical|crit|panic)(?:\]|[\s:])"
+    )
+    .unwrap();
+    // a bare level word inside the message only counts in upper case, such as
+    // the level of log4j lines after their timestamp
+    static ref UPPER: Regex = Regex::new(
+        r"(?:^|\s)(TRACE|DEBUG|INFO|NOTICE|WARN|WARNING|ERROR|ERR|FATAL|CRITICAL|CRIT|PANIC)(?:[\s:]|$)"
+    )
+    .unwrap();
+}
+
+/// Level is the normalized severity of a message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+    Fatal,
+}
+
+impl Level {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Level::Trace => "trace",
+            Level::Debug => "debug",
+            Level::Info => "info",
+            Level::Warn => "warn",
+            Level::Error => "error",
+            Level::Fatal => "fatal",
+        }
+    }
+
+    /// parse normalizes a level name, including the klog letters.
+    pub fn parse(name: &str) -> Option<Level> {
+        match name.trim().to_lowercase().as_str() {
+            "trace" | "t" => Some(Level::Trace),
+            "debug" | "dbg" | "d" => Some(Level::Debug),
+            "info" | "information" | "notice" | "i" => Some(Level::Info),
+            "warn" | "warning" | "w" => Some(Level::Warn),
+            "error" | "err" | "e" => Some(Level::Error),
+            "fatal" | "critical" | "crit" | "panic" | "alert" | "emerg" | "f" => Some(Level::Fatal),
+            _ => None,
+        }
+    }
+
+    // from_number maps the numeric levels of pino and bunyan
+    fn from_number(level: i64) -> Option<Level> {
+        match level {
+            10 => Some(Level::Trace),
+            20 => Some(Level::Debug),
+            30 => Some(Level::Info),
+            40 => Some(Level::Warn),
+            50 => Some(Level::Error),
+            60 => Some(Level::Fatal),
+            _ => None,
+        }
+    }
+}
+
+// level_of reads the first level key holding a known level, a key with an
+// unknown value is skipped
+fn level_of(fields: &Map<String, Value>) -> Option<Level> {
+    LEVEL_KEYS.iter().find_map(|key| match fields.get(*key) {
+        Some(Value::String(name)) => Level::parse(name),
+        Some(Value::Number(n)) => n.as_i64().and_then(Level::from_number),
+        _ => None,
+    })
+}
+
+/// detect_level finds the level of a message in the fields parsed from it,
+/// its json `level`/`severity` keys, a klog header, a logfmt `level=` pair,
+/// an `[INFO]`-style prefix or an upper case level word.
+pub fn detect_level(message: &str, fields: &Map<String, Value>) -> Option<Level> {
+    if let Some(level) = level_of(fields) {
+        return Some(level);
+    }
+    if message.trim_start().starts_with('{') {
+        if let Ok(Value::Object(object)) = serde_json::from_str::<Value>(message) {
+            return level_of(&object);
+        }
+    }
+    if let Some(caps) = KLOG.captures(message) {
+        return Level::parse(&caps[1]);
+    }
+    if let Some(caps) = LOGFMT.captures(message) {
+        if let Some(level) = Level::parse(&caps[1]) {
+            return Some(level);
+        }
+    }
+    let mut end = message.len().min(PREFIX_SIZE);
+    while !message.is_char_boundary(end) {
+        end -= 1;
+    }
+    let head = &message[..end];
+    PREFIX
+        .captures(head)
+        .or_else(|| UPPER.captures(head))
+        .and_then(|caps| Level::parse(&caps[1]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{detect_level, Level};
+    use serde_json::{Map, Value};
+
+    #[test]
+    fn it_works() {
+        let none = Map::new();
+        let detect = |message: &str| detect_level(message, &none);
+
+        assert_eq!(
+            detect(r#"{"level":"WARNING","msg":"slow"}"#),
+            Some(Level::Warn)
+        );
+        assert_eq!(detect(r#"{"severity":"error"}"#), Some(Level::Error));
+        assert_eq!(detect(r#"{"level":50,"msg":"pino"}"#), Some(Level::Error));
+        assert_eq!(
+            detect("E0316 04:05:06.789012   12345 main.go:42] watch failed"),
+            Some(Level::Error)
+        );
+        assert_eq!(
+            detect("time=2021-02-03T04:05:06Z level=debug msg=\"cache hit\""),
+            Some(Level::Debug)
+        );
+        assert_eq!(detect("[INFO] server started"), Some(Level::Info));
+        assert_eq!(
+            detect("2021-02-03 04:05:06,789 ERROR [main] App - refused"),
+            Some(Level::Error)
+        );
+        assert_eq!(detect("request failed with error: timeout"), None);
+        assert_eq!(
+            detect("2021-02-03 04:05:06 [error] 1#1: open failed"),
+            Some(Level::Error)
+        );
+        assert_eq!(detect("information about errors"), None);
+
+        let mut fields = Map::new();
+        fields.insert("level".to_string(), Value::String("E".to_string()));
+        assert_eq!(detect_level("anything", &fields), Some(Level::Error));
+
+        // an unknown level value does not hide the next level key
+        assert_eq!(
+            detect(r#"{"level":"verbose","severity":"warn"}"#),
+            Some(Level::Warn)
+        );
+        fields.insert("level".to_string(), Value::Number(7.into()));
+        assert_eq!(detect_level("[ERROR] refused", &fields), Some(Level::Error));
+
+        assert!(Level::Warn > Level::Info);
+        assert_eq!(Level::parse("Fatal").unwrap().as_str(), "fatal");
+    }
+}
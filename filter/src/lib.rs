@@ -1,7 +1,35 @@
+#[macro_use]
+extern crate lazy_static;
+
+mod dedup;
+mod expr;
+mod grok;
+mod level;
+mod limit;
+mod metric;
+mod plugin;
+mod redact;
+mod script;
+
+pub use dedup::{normalize, Deduplicator};
+pub use expr::{expr_record, resolve, Expr, ExprError};
+pub use grok::GrokParser;
+pub use level::{detect_level, Level};
+pub use limit::{Limiter, Suppressed};
+pub use metric::{metric_records, render_metrics, MetricRules};
+pub use plugin::{load_plugin_dir, WasmPlugin};
+pub use redact::{luhn, redaction_counts, Redactor};
+pub use script::{container_metadata, Scripter};
+
 pub trait Filter {
     fn pass(&self, message: &str) -> bool;
 }
 
+/// Transform rewrites a message, Ok(None) drops it.
+pub trait Transform {
+    fn transform(&self, message: &str) -> Result<Option<String>, String>;
+}
+
 struct BaseFilter {}
 
 impl Filter for BaseFilter {
@@ -11,10 +39,40 @@ impl Filter for BaseFilter {
     }
 }
 
+/// ContainsFilter passes the messages containing the pattern, an empty
+/// pattern passes every message.
+pub struct ContainsFilter {
+    pattern: String,
+}
+
+impl ContainsFilter {
+    pub fn new(pattern: &str) -> Self {
+        Self {
+            pattern: pattern.to_string(),
+        }
+    }
+}
+
+impl Filter for ContainsFilter {
+    fn pass(&self, message: &str) -> bool {
+        message.contains(&self.pattern)
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use super::{ContainsFilter, Filter};
+
     #[test]
     fn it_works() {
         assert_eq!(2 + 2, 4);
     }
+
+    #[test]
+    fn contains_it_works() {
+        let filter = ContainsFilter::new("error");
+        assert!(filter.pass("level=error msg=timeout"));
+        assert!(!filter.pass("level=info msg=ok"));
+        assert!(ContainsFilter::new("").pass("anything"));
+    }
 }
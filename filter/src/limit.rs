@@ -0,0 +1,227 @@
+use super::Level;
+use db::{Limit, SampleBy};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// the suppressed lines are reported every minute unless configured
+const DEFAULT_REPORT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// TokenBucket holds up to one second of `rate` tokens, refilled continuously.
+#[derive(Debug)]
+struct TokenBucket {
+    rate: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: u64) -> Self {
+        Self {
+            rate: rate as f64,
+            tokens: rate as f64,
+            last: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        self.last = now;
+    }
+
+    // a cost over the bucket size passes once the bucket is full
+    fn has(&self, cost: u64) -> bool {
+        self.tokens >= (cost as f64).min(self.rate)
+    }
+
+    fn take(&mut self, cost: u64) {
+        self.tokens = (self.tokens - cost as f64).max(0.0);
+    }
+}
+
+/// Suppressed counts the lines dropped by a limiter since its last report.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Suppressed {
+    pub sampled: u64,
+    pub rate_limited: u64,
+}
+
+/// Limiter applies the rate limits and the sampling of a task to the lines of
+/// one container.
+pub struct Limiter {
+    lines: Option<TokenBucket>,
+    bytes: Option<TokenBucket>,
+    sample_rate: Option<f64>,
+    sample_by: SampleBy,
+    // xorshift state of the random sampling
+    seed: u64,
+    suppressed: Suppressed,
+    report_interval: Duration,
+    last_report: Instant,
+    last_seen: Instant,
+}
+
+impl Limiter {
+    pub fn new(config: &Limit) -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0)
+            | 1;
+        Self {
+            lines: Some(config.lines_per_sec)
+                .filter(|rate| *rate > 0)
+                .map(TokenBucket::new),
+            bytes: Some(config.bytes_per_sec)
+                .filter(|rate| *rate > 0)
+                .map(TokenBucket::new),
+            sample_rate: config.is_sampled().then_some(config.sample_rate),
+            sample_by: config.sample_by.clone(),
+            seed,
+            suppressed: Suppressed::default(),
+            report_interval: match config.report_interval {
+                0 => DEFAULT_REPORT_INTERVAL,
+                secs => Duration::from_secs(secs),
+            },
+            last_report: Instant::now(),
+            last_seen: Instant::now(),
+        }
+    }
+
+    fn random(&mut self) -> f64 {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 7;
+        self.seed ^= self.seed << 17;
+        (self.seed >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn sample(&mut self, message: &str, rate: f64) -> bool {
+        match self.sample_by {
+            SampleBy::Random => self.random() < rate,
+            SampleBy::Hash => {
+                let mut hasher = DefaultHasher::new();
+                message.hash(&mut hasher);
+                (hasher.finish() >> 11) as f64 / ((1u64 << 53) as f64) < rate
+            }
+        }
+    }
+
+    /// allow tells whether the line is shipped, error lines are never
+    /// sampled out but count against the rate limits.
+    pub fn allow(&mut self, message: &str, level: Option<Level>) -> bool {
+        self.last_seen = Instant::now();
+        if let Some(rate) = self.sample_rate {
+            let error = matches!(level, Some(level) if level >= Level::Error);
+            if !error && !self.sample(message, rate) {
+                self.suppressed.sampled += 1;
+                return false;
+            }
+        }
+
+        let now = Instant::now();
+        let size = message.len() as u64;
+        if let Some(lines) = &mut self.lines {
+            lines.refill(now);
+        }
+        if let Some(bytes) = &mut self.bytes {
+            bytes.refill(now);
+        }
+        let lines_ok = self.lines.as_ref().is_none_or(|lines| lines.has(1));
+        let bytes_ok = self.bytes.as_ref().is_none_or(|bytes| bytes.has(size));
+        if !lines_ok || !bytes_ok {
+            self.suppressed.rate_limited += 1;
+            return false;
+        }
+        if let Some(lines) = &mut self.lines {
+            lines.take(1);
+        }
+        if let Some(bytes) = &mut self.bytes {
+            bytes.take(size);
+        }
+        true
+    }
+
+    /// is_idle tells whether the limiter saw no line for long enough that its
+    /// buckets are full again and has no suppressed lines left to report.
+    pub fn is_idle(&self) -> bool {
+        self.last_seen.elapsed() >= Duration::from_secs(1)
+            && self.suppressed == Suppressed::default()
+    }
+
+    /// report returns the lines suppressed since the last report once the
+    /// report interval elapsed, None when nothing was suppressed.
+    pub fn report(&mut self) -> Option<Suppressed> {
+        if self.last_report.elapsed() < self.report_interval {
+            return None;
+        }
+        self.flush()
+    }
+
+    /// flush returns the lines suppressed since the last report at once.
+    pub fn flush(&mut self) -> Option<Suppressed> {
+        self.last_report = Instant::now();
+        if self.suppressed == Suppressed::default() {
+            return None;
+        }
+        Some(std::mem::take(&mut self.suppressed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Limiter, Suppressed};
+    use crate::Level;
+    use db::{Limit, SampleBy};
+
+    #[test]
+    fn rate_it_works() {
+        let mut limiter = Limiter::new(&Limit {
+            lines_per_sec: 3,
+            bytes_per_sec: 1000,
+            ..Default::default()
+        });
+        let allowed = (0..10).filter(|_| limiter.allow("line", None)).count();
+        assert_eq!(allowed, 3);
+        assert_eq!(limiter.report(), None);
+        assert_eq!(
+            limiter.flush(),
+            Some(Suppressed {
+                sampled: 0,
+                rate_limited: 7
+            })
+        );
+        assert_eq!(limiter.flush(), None);
+        // the buckets refill for a second after the last line
+        assert!(!limiter.is_idle());
+
+        let mut limiter = Limiter::new(&Limit {
+            bytes_per_sec: 10,
+            ..Default::default()
+        });
+        // a line over the bucket size passes when the bucket is full
+        assert!(limiter.allow("a line longer than ten bytes", None));
+        assert!(!limiter.allow("short", None));
+    }
+
+    #[test]
+    fn sample_it_works() {
+        let mut limiter = Limiter::new(&Limit {
+            sample_rate: 0.1,
+            ..Default::default()
+        });
+        let kept = (0..10000)
+            .filter(|_| limiter.allow("line", Some(Level::Info)))
+            .count();
+        assert!(kept > 500 && kept < 1500, "kept {}", kept);
+        assert!((0..100).all(|_| limiter.allow("boom", Some(Level::Error))));
+
+        let mut limiter = Limiter::new(&Limit {
+            sample_rate: 0.5,
+            sample_by: SampleBy::Hash,
+            ..Default::default()
+        });
+        let first = limiter.allow("same message", None);
+        assert!((0..10).all(|_| limiter.allow("same message", None) == first));
+    }
+}
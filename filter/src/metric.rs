@@ -0,0 +1,490 @@
+use super::expr::{as_number, resolve};
+use super::Expr;
+use db::{MetricKind, Metrics};
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex, RwLock};
+
+// the default histogram buckets, in seconds as the prometheus client defaults
+const DEFAULT_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+// the label sets of a metric are capped, fields with unbounded values would
+// grow the registry with every record
+const MAX_SERIES: usize = 10_000;
+
+// the label values of a series, sorted by label name
+type LabelSet = Vec<(String, String)>;
+
+enum Series {
+    Counter(f64),
+    // cumulative bucket counts
+    Histogram {
+        buckets: Vec<u64>,
+        sum: f64,
+        count: u64,
+    },
+}
+
+struct Family {
+    kind: MetricKind,
+    help: String,
+    buckets: Vec<f64>,
+    series: BTreeMap<LabelSet, Series>,
+    overflow: bool,
+}
+
+lazy_static! {
+    // the metrics of every task by name, the series of the containers of a
+    // task aggregate into the same family, each family is locked on its own
+    // so the readers only wait on the readers observing the same metric
+    static ref FAMILIES: RwLock<BTreeMap<String, Arc<Mutex<Family>>>> =
+        RwLock::new(BTreeMap::new());
+}
+
+fn is_metric_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_' || c == ':')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':')
+}
+
+fn is_label_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !name.starts_with("__")
+}
+
+fn path(field: &str) -> Vec<String> {
+    field.split('.').map(|key| key.to_string()).collect()
+}
+
+fn label_value(value: Option<&Value>) -> String {
+    match value {
+        Some(Value::String(s)) => s.clone(),
+        Some(Value::Null) | None => "".to_string(),
+        Some(other) => other.to_string(),
+    }
+}
+
+struct Rule {
+    name: String,
+    kind: MetricKind,
+    expr: Option<Expr>,
+    labels: Vec<(String, Vec<String>)>,
+    value: Option<Vec<String>>,
+    family: Arc<Mutex<Family>>,
+}
+
+/// MetricRules derives the metrics of a task from the records of one of its
+/// containers into the registry of the agent.
+pub struct MetricRules {
+    rules: Vec<Rule>,
+}
+
+impl MetricRules {
+    pub fn new(config: &Metrics) -> Result<Self, String> {
+        let mut rules = Vec::new();
+        for rule in config.rules.iter() {
+            if !is_metric_name(&rule.name) {
+                return Err(format!("invalid metric name {:?}", rule.name));
+            }
+            let error = |e: String| format!("metric {}: {}", rule.name, e);
+            for label in rule.labels.keys() {
+                if !is_label_name(label) || (rule.kind == MetricKind::Histogram && label == "le") {
+                    return Err(error(format!("invalid label name {:?}", label)));
+                }
+            }
+            if rule.kind == MetricKind::Histogram && rule.value.is_empty() {
+                return Err(error("a histogram needs a value field".to_string()));
+            }
+            let buckets = if rule.buckets.is_empty() {
+                DEFAULT_BUCKETS.to_vec()
+            } else {
+                rule.buckets.clone()
+            };
+            if buckets.iter().any(|bucket| !bucket.is_finite())
+                || buckets.windows(2).any(|pair| pair[0] >= pair[1])
+            {
+                return Err(error("buckets are not increasing".to_string()));
+            }
+            let expr = match rule.expr.trim() {
+                "" => None,
+                expr => Some(Expr::parse(expr).map_err(|e| error(e.to_string()))?),
+            };
+            let mut families = FAMILIES.write().map_err(|e| error(e.to_string()))?;
+            let family = families
+                .entry(rule.name.clone())
+                .or_insert_with(|| {
+                    Arc::new(Mutex::new(Family {
+                        kind: rule.kind.clone(),
+                        help: rule.help.clone(),
+                        buckets,
+                        series: BTreeMap::new(),
+                        overflow: false,
+                    }))
+                })
+                .clone();
+            drop(families);
+            let kind = family
+                .lock()
+                .map_err(|e| error(e.to_string()))?
+                .kind
+                .clone();
+            if kind != rule.kind {
+                return Err(error(format!("registered as a {:?}", kind)));
+            }
+            rules.push(Rule {
+                name: rule.name.clone(),
+                kind: rule.kind.clone(),
+                expr,
+                labels: rule
+                    .labels
+                    .iter()
+                    .map(|(label, field)| (label.clone(), path(field)))
+                    .collect(),
+                value: Some(&rule.value)
+                    .filter(|value| !value.is_empty())
+                    .map(|value| path(value)),
+                family,
+            });
+        }
+        Ok(Self { rules })
+    }
+
+    /// names returns the metric names of the rules.
+    pub fn names(&self) -> Vec<String> {
+        self.rules.iter().map(|rule| rule.name.clone()).collect()
+    }
+
+    /// observe adds the record to the metrics of the rules it matches, a
+    /// record without a numeric value field, or with a negative one for a
+    /// counter, is not observed.
+    pub fn observe(&self, record: &Value) {
+        for rule in self.rules.iter() {
+            if let Some(expr) = &rule.expr {
+                if !expr.eval(record) {
+                    continue;
+                }
+            }
+            let value = match &rule.value {
+                Some(path) => match resolve(record, path).and_then(as_number) {
+                    Some(value) => value,
+                    None => continue,
+                },
+                None => 1.0,
+            };
+            // a counter never decreases
+            if rule.kind == MetricKind::Counter && (value.is_nan() || value < 0.0) {
+                continue;
+            }
+            let labels = rule
+                .labels
+                .iter()
+                .map(|(label, path)| (label.clone(), label_value(resolve(record, path))))
+                .collect::<LabelSet>();
+
+            let mut family = match rule.family.lock() {
+                Ok(family) => family,
+                Err(_) => return,
+            };
+            if !family.series.contains_key(&labels) && family.series.len() >= MAX_SERIES {
+                if !family.overflow {
+                    eprintln!(
+                        "[ERROR] metric {} exceeds {} series, drop new label values",
+                        rule.name, MAX_SERIES
+                    );
+                    family.overflow = true;
+                }
+                continue;
+            }
+            let family = &mut *family;
+            let buckets = family.buckets.len();
+            let series = family
+                .series
+                .entry(labels)
+                .or_insert_with(|| match rule.kind {
+                    MetricKind::Counter => Series::Counter(0.0),
+                    MetricKind::Histogram => Series::Histogram {
+                        buckets: vec![0; buckets],
+                        sum: 0.0,
+                        count: 0,
+                    },
+                });
+            match series {
+                Series::Counter(total) => *total += value,
+                Series::Histogram {
+                    buckets,
+                    sum,
+                    count,
+                } => {
+                    for (bound, bucket) in family.buckets.iter().zip(buckets.iter_mut()) {
+                        if value <= *bound {
+                            *bucket += 1;
+                        }
+                    }
+                    *sum += value;
+                    *count += 1;
+                }
+            }
+        }
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn format_labels(labels: &[(String, String)], le: Option<&str>) -> String {
+    let mut pairs = labels
+        .iter()
+        .map(|(label, value)| format!("{}=\"{}\"", label, escape_label(value)))
+        .collect::<Vec<String>>();
+    if let Some(le) = le {
+        pairs.push(format!("le=\"{}\"", le));
+    }
+    if pairs.is_empty() {
+        return "".to_string();
+    }
+    format!("{{{}}}", pairs.join(","))
+}
+
+/// render_metrics returns the metrics of every task in the prometheus text
+/// exposition format.
+pub fn render_metrics() -> String {
+    let mut text = String::new();
+    let families = match FAMILIES.read() {
+        Ok(families) => families,
+        Err(_) => return text,
+    };
+    for (name, family) in families.iter() {
+        let family = match family.lock() {
+            Ok(family) => family,
+            Err(_) => continue,
+        };
+        if family.series.is_empty() {
+            continue;
+        }
+        if !family.help.is_empty() {
+            let help = family.help.replace('\\', "\\\\").replace('\n', "\\n");
+            let _ = writeln!(text, "# HELP {} {}", name, help);
+        }
+        let kind = match family.kind {
+            MetricKind::Counter => "counter",
+            MetricKind::Histogram => "histogram",
+        };
+        let _ = writeln!(text, "# TYPE {} {}", name, kind);
+        for (labels, series) in family.series.iter() {
+            match series {
+                Series::Counter(total) => {
+                    let _ = writeln!(text, "{}{} {}", name, format_labels(labels, None), total);
+                }
+                Series::Histogram {
+                    buckets,
+                    sum,
+                    count,
+                } => {
+                    for (bound, bucket) in family.buckets.iter().zip(buckets.iter()) {
+                        let le = bound.to_string();
+                        let _ = writeln!(
+                            text,
+                            "{}_bucket{} {}",
+                            name,
+                            format_labels(labels, Some(&le)),
+                            bucket
+                        );
+                    }
+                    let labels_inf = format_labels(labels, Some("+Inf"));
+                    let labels = format_labels(labels, None);
+                    let _ = writeln!(text, "{}_bucket{} {}", name, labels_inf, count);
+                    let _ = writeln!(text, "{}_sum{} {}", name, labels, sum);
+                    let _ = writeln!(text, "{}_count{} {}", name, labels, count);
+                }
+            }
+        }
+    }
+    text
+}
+
+/// metric_records returns a record for every series of the named metrics.
+pub fn metric_records(names: &[String]) -> Vec<Value> {
+    let families = match FAMILIES.read() {
+        Ok(families) => families,
+        Err(_) => return Vec::new(),
+    };
+    let mut records = Vec::new();
+    for name in names.iter() {
+        let family = match families.get(name).map(|family| family.lock()) {
+            Some(Ok(family)) => family,
+            _ => continue,
+        };
+        for (labels, series) in family.series.iter() {
+            let labels = labels
+                .iter()
+                .map(|(label, value)| (label.clone(), Value::String(value.clone())))
+                .collect::<Map<String, Value>>();
+            records.push(match series {
+                Series::Counter(total) => json!({
+                    "metric": name,
+                    "type": "counter",
+                    "labels": labels,
+                    "value": total,
+                }),
+                Series::Histogram {
+                    buckets,
+                    sum,
+                    count,
+                } => json!({
+                    "metric": name,
+                    "type": "histogram",
+                    "labels": labels,
+                    "buckets": family
+                        .buckets
+                        .iter()
+                        .zip(buckets.iter())
+                        .map(|(bound, bucket)| (bound.to_string(), json!(bucket)))
+                        .collect::<Map<String, Value>>(),
+                    "sum": sum,
+                    "count": count,
+                }),
+            });
+        }
+    }
+    records
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{metric_records, render_metrics, MetricRules};
+    use db::{MetricKind, MetricRule, Metrics};
+    use serde_json::json;
+
+    fn rules(rules: Vec<MetricRule>) -> Result<MetricRules, String> {
+        MetricRules::new(&Metrics {
+            rules,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn counter_it_works() {
+        let rules = rules(vec![MetricRule {
+            name: "test_log_errors_total".to_string(),
+            help: "error lines".to_string(),
+            expr: r#"level == "error""#.to_string(),
+            labels: vec![("service".to_string(), "container.service_name".to_string())]
+                .into_iter()
+                .collect(),
+            ..Default::default()
+        }])
+        .unwrap();
+        rules.observe(&json!({"level":"error","container":{"service_name":"api"}}));
+        rules.observe(&json!({"level":"error","container":{"service_name":"api"}}));
+        rules.observe(&json!({"level":"info","container":{"service_name":"api"}}));
+        rules.observe(&json!({"level":"error","container":{"service_name":"we\"b"}}));
+
+        let text = render_metrics();
+        assert!(text.contains("# HELP test_log_errors_total error lines\n"));
+        assert!(text.contains("# TYPE test_log_errors_total counter\n"));
+        assert!(text.contains("test_log_errors_total{service=\"api\"} 2\n"));
+        assert!(text.contains("test_log_errors_total{service=\"we\\\"b\"} 1\n"));
+
+        let records = metric_records(&rules.names());
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["labels"]["service"], "api");
+        assert_eq!(records[0]["value"], 2.0);
+    }
+
+    #[test]
+    fn counter_value_it_works() {
+        let counter = rules(vec![MetricRule {
+            name: "test_sent_bytes_total".to_string(),
+            value: "bytes".to_string(),
+            ..Default::default()
+        }])
+        .unwrap();
+        for bytes in &[
+            json!(512),
+            json!("128"),
+            json!(-100),
+            json!("-1"),
+            json!("NaN"),
+        ] {
+            counter.observe(&json!({ "bytes": bytes }));
+        }
+        assert!(render_metrics().contains("test_sent_bytes_total 640\n"));
+
+        // the same metric can not be registered with another kind
+        let err = rules(vec![MetricRule {
+            name: "test_sent_bytes_total".to_string(),
+            kind: MetricKind::Histogram,
+            value: "bytes".to_string(),
+            ..Default::default()
+        }])
+        .err()
+        .unwrap();
+        assert!(err.contains("registered as a Counter"), "{}", err);
+    }
+
+    #[test]
+    fn histogram_it_works() {
+        let rules = rules(vec![MetricRule {
+            name: "test_request_seconds".to_string(),
+            kind: MetricKind::Histogram,
+            value: "duration".to_string(),
+            buckets: vec![0.1, 1.0],
+            ..Default::default()
+        }])
+        .unwrap();
+        for duration in &[json!(0.05), json!("0.5"), json!(3), json!("n/a")] {
+            rules.observe(&json!({ "duration": duration }));
+        }
+        let text = render_metrics();
+        assert!(text.contains("test_request_seconds_bucket{le=\"0.1\"} 1\n"));
+        assert!(text.contains("test_request_seconds_bucket{le=\"1\"} 2\n"));
+        assert!(text.contains("test_request_seconds_bucket{le=\"+Inf\"} 3\n"));
+        assert!(text.contains("test_request_seconds_sum 3.55\n"));
+        assert!(text.contains("test_request_seconds_count 3\n"));
+        assert_eq!(metric_records(&rules.names())[0]["buckets"]["1"], 2);
+    }
+
+    #[test]
+    fn invalid_it_works() {
+        let rule = MetricRule {
+            name: "test_invalid".to_string(),
+            ..Default::default()
+        };
+        for rule in [
+            MetricRule {
+                name: "0bad".to_string(),
+                ..rule.clone()
+            },
+            MetricRule {
+                expr: "level ==".to_string(),
+                ..rule.clone()
+            },
+            MetricRule {
+                labels: vec![("__name".to_string(), "a".to_string())]
+                    .into_iter()
+                    .collect(),
+                ..rule.clone()
+            },
+            MetricRule {
+                kind: MetricKind::Histogram,
+                ..rule.clone()
+            },
+            MetricRule {
+                kind: MetricKind::Histogram,
+                value: "duration".to_string(),
+                buckets: vec![1.0, 0.5],
+                ..rule.clone()
+            },
+        ] {
+            assert!(rules(vec![rule]).is_err());
+        }
+    }
+}
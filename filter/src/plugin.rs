@@ -0,0 +1,469 @@
+use super::{Filter, Transform};
+use db::{Plugin, PluginFailure};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::SystemTime;
+use wasmi::{
+    Config, Engine, Linker, Memory, Module, Store, StoreLimits, StoreLimitsBuilder, TypedFunc,
+};
+
+// the limits of a call unless configured
+const DEFAULT_FUEL: u64 = 1_000_000;
+const DEFAULT_MAX_MEMORY: usize = 16 * 1024 * 1024;
+
+const PLUGIN_EXTENSION: &str = "wasm";
+
+lazy_static! {
+    // one engine compiles every module, with fuel metering
+    static ref ENGINE: Engine = {
+        let mut config = Config::default();
+        config.consume_fuel(true);
+        Engine::new(&config)
+    };
+    // the compiled modules by path, a module is compiled again when its file changes
+    static ref MODULES: RwLock<HashMap<PathBuf, (SystemTime, Arc<Module>)>> =
+        RwLock::new(HashMap::new());
+    static ref PLUGIN_DIR: RwLock<PathBuf> = RwLock::new(PathBuf::new());
+}
+
+/// load_plugin_dir sets the directory of the plugins loaded by name, and
+/// compiles its modules ahead of the tasks using them.
+pub fn load_plugin_dir(dir: &str) -> Result<usize, String> {
+    if let Ok(mut plugin_dir) = PLUGIN_DIR.write() {
+        *plugin_dir = PathBuf::from(dir);
+    }
+    let entries = fs::read_dir(dir).map_err(|e| format!("plugin dir {:?}: {}", dir, e))?;
+    let mut loaded = 0;
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some(PLUGIN_EXTENSION) {
+            continue;
+        }
+        match module(&path) {
+            Ok(_) => loaded += 1,
+            Err(e) => eprintln!("[ERROR] plugin load {:?} error: {}", path, e),
+        }
+    }
+    Ok(loaded)
+}
+
+// resolve returns the module file of a plugin, a name or a path never leaves
+// the plugin directory
+fn resolve(config: &Plugin) -> Result<PathBuf, String> {
+    let dir = PLUGIN_DIR
+        .read()
+        .map(|dir| dir.clone())
+        .map_err(|e| e.to_string())?;
+    if dir.as_os_str().is_empty() {
+        return Err(format!(
+            "plugin {:?} needs the plugin dir of the agent",
+            if config.path.is_empty() {
+                &config.name
+            } else {
+                &config.path
+            }
+        ));
+    }
+    if !config.path.is_empty() {
+        // a relative path is in the plugin directory
+        let path = dir
+            .join(&config.path)
+            .canonicalize()
+            .map_err(|e| format!("plugin {:?}: {}", config.path, e))?;
+        let dir = dir.canonicalize().map_err(|e| e.to_string())?;
+        if !path.starts_with(&dir) {
+            return Err(format!(
+                "plugin {:?} is outside the plugin dir {:?}",
+                config.path, dir
+            ));
+        }
+        return Ok(path);
+    }
+    if config.name.is_empty() || config.name.contains(['/', '\\']) || config.name.starts_with('.') {
+        return Err(format!("invalid plugin name {:?}", config.name));
+    }
+    Ok(dir.join(format!("{}.{}", config.name, PLUGIN_EXTENSION)))
+}
+
+// module returns the compiled module of the file from the cache
+fn module(path: &Path) -> Result<Arc<Module>, String> {
+    let modified = fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .map_err(|e| format!("plugin {:?}: {}", path, e))?;
+    if let Ok(modules) = MODULES.read() {
+        if let Some((cached, module)) = modules.get(path) {
+            if *cached == modified {
+                return Ok(module.clone());
+            }
+        }
+    }
+    let wasm = fs::read(path).map_err(|e| format!("plugin {:?}: {}", path, e))?;
+    let module = Module::new(&ENGINE, &wasm[..])
+        .map(Arc::new)
+        .map_err(|e| format!("plugin {:?} compile error: {}", path, e))?;
+    // the sandbox provides no host functions, not even WASI
+    if let Some(import) = module.imports().next() {
+        return Err(format!(
+            "plugin {:?} imports {}::{}, imports are not supported",
+            path,
+            import.module(),
+            import.name()
+        ));
+    }
+    if let Ok(mut modules) = MODULES.write() {
+        modules.insert(path.to_path_buf(), (modified, module.clone()));
+    }
+    Ok(module)
+}
+
+// Runtime is an instance of a plugin module with its exports
+struct Runtime {
+    store: Store<StoreLimits>,
+    memory: Memory,
+    alloc: TypedFunc<i32, i32>,
+    filter: Option<TypedFunc<(i32, i32), i32>>,
+    transform: Option<TypedFunc<(i32, i32), i64>>,
+}
+
+impl Runtime {
+    // the start function of the module runs on the fuel of a call
+    fn new(module: &Module, max_memory: usize, fuel: u64) -> Result<Self, String> {
+        let mut store = Store::new(
+            &ENGINE,
+            StoreLimitsBuilder::new().memory_size(max_memory).build(),
+        );
+        store.limiter(|limits| limits);
+        store.set_fuel(fuel).map_err(|e| e.to_string())?;
+        let instance = Linker::<StoreLimits>::new(&ENGINE)
+            .instantiate(&mut store, module)
+            .and_then(|instance| instance.start(&mut store))
+            .map_err(|e| format!("instantiate error: {}", e))?;
+        let memory = instance
+            .get_memory(&store, "memory")
+            .ok_or_else(|| "no memory export".to_string())?;
+        let alloc = instance
+            .get_typed_func::<i32, i32>(&store, "alloc")
+            .map_err(|e| format!("alloc export: {}", e))?;
+        let filter = instance
+            .get_typed_func::<(i32, i32), i32>(&store, "filter")
+            .ok();
+        let transform = instance
+            .get_typed_func::<(i32, i32), i64>(&store, "transform")
+            .ok();
+        if filter.is_none() && transform.is_none() {
+            return Err("no filter or transform export".to_string());
+        }
+        Ok(Self {
+            store,
+            memory,
+            alloc,
+            filter,
+            transform,
+        })
+    }
+
+    fn call(&mut self, message: &str, fuel: u64) -> Result<Option<String>, String> {
+        self.store.set_fuel(fuel).map_err(|e| e.to_string())?;
+        let len = i32::try_from(message.len()).map_err(|e| e.to_string())?;
+        let ptr = self
+            .alloc
+            .call(&mut self.store, len)
+            .map_err(|e| e.to_string())?;
+        self.memory
+            .write(&mut self.store, ptr as u32 as usize, message.as_bytes())
+            .map_err(|e| format!("write message: {}", e))?;
+
+        if let Some(filter) = &self.filter {
+            if filter
+                .call(&mut self.store, (ptr, len))
+                .map_err(|e| e.to_string())?
+                == 0
+            {
+                return Ok(None);
+            }
+        }
+        let transform = match &self.transform {
+            Some(transform) => transform,
+            None => return Ok(Some(message.to_string())),
+        };
+        let result = transform
+            .call(&mut self.store, (ptr, len))
+            .map_err(|e| e.to_string())?;
+        if result < 0 {
+            return Ok(None);
+        }
+        let start = (result >> 32) as u32 as usize;
+        let end = start + (result as u32 as usize);
+        let bytes = self
+            .memory
+            .data(&self.store)
+            .get(start..end)
+            .ok_or_else(|| "transform result out of memory bounds".to_string())?;
+        String::from_utf8(bytes.to_vec())
+            .map(Some)
+            .map_err(|_| "transform result is not utf-8".to_string())
+    }
+}
+
+/// WasmPlugin runs a WebAssembly module on messages, in a sandbox without
+/// imports where every call is metered by fuel and the memory is capped.
+///
+/// The module exports its `memory` and `alloc(len) -> ptr`, the message is
+/// written at the pointer `alloc` returns. A `filter(ptr, len) -> i32` export
+/// drops the message on 0, a `transform(ptr, len) -> i64` export drops it on
+/// a negative value or returns the new message as `ptr << 32 | len`. The
+/// instance of a failed call is dropped and the next call gets a new one.
+pub struct WasmPlugin {
+    name: String,
+    module: Arc<Module>,
+    fuel: u64,
+    max_memory: usize,
+    on_failure: PluginFailure,
+    runtime: Mutex<Option<Runtime>>,
+}
+
+impl WasmPlugin {
+    pub fn new(config: &Plugin) -> Result<Self, String> {
+        let path = resolve(config)?;
+        let module = module(&path)?;
+        let max_memory = match config.max_memory {
+            0 => DEFAULT_MAX_MEMORY,
+            max_memory => max_memory,
+        };
+        let fuel = match config.fuel {
+            0 => DEFAULT_FUEL,
+            fuel => fuel,
+        };
+        let runtime = Runtime::new(&module, max_memory, fuel)
+            .map_err(|e| format!("plugin {:?} {}", path, e))?;
+        Ok(Self {
+            name: path.display().to_string(),
+            module,
+            fuel,
+            max_memory,
+            on_failure: config.on_failure.clone(),
+            runtime: Mutex::new(Some(runtime)),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn on_failure(&self) -> &PluginFailure {
+        &self.on_failure
+    }
+}
+
+impl Transform for WasmPlugin {
+    fn transform(&self, message: &str) -> Result<Option<String>, String> {
+        let mut runtime = self.runtime.lock().map_err(|e| e.to_string())?;
+        if runtime.is_none() {
+            *runtime = Some(Runtime::new(&self.module, self.max_memory, self.fuel)?);
+        }
+        let result = match runtime.as_mut() {
+            Some(runtime) => runtime.call(message, self.fuel),
+            None => return Err("plugin not instantiated".to_string()),
+        };
+        if result.is_err() {
+            *runtime = None;
+        }
+        result
+    }
+}
+
+impl Filter for WasmPlugin {
+    fn pass(&self, message: &str) -> bool {
+        match self.transform(message) {
+            Ok(message) => message.is_some(),
+            Err(_) => self.on_failure == PluginFailure::Bypass,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{load_plugin_dir, WasmPlugin, MODULES};
+    use crate::{Filter, Transform};
+    use db::{Plugin, PluginFailure};
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    const UPPER: &str = r#"
+    (module
+      (memory (export "memory") 1)
+      (func (export "alloc") (param i32) (result i32) (i32.const 1024))
+      (func (export "filter") (param $ptr i32) (param $len i32) (result i32)
+        (i32.ne (i32.load8_u (local.get $ptr)) (i32.const 35)))
+      (func (export "transform") (param $ptr i32) (param $len i32) (result i64)
+        (local $i i32) (local $c i32)
+        (block $done
+          (loop $next
+            (br_if $done (i32.ge_u (local.get $i) (local.get $len)))
+            (local.set $c (i32.load8_u (i32.add (local.get $ptr) (local.get $i))))
+            (if (i32.and (i32.ge_u (local.get $c) (i32.const 97))
+                         (i32.le_u (local.get $c) (i32.const 122)))
+              (then (i32.store8 (i32.add (local.get $ptr) (local.get $i))
+                                (i32.sub (local.get $c) (i32.const 32)))))
+            (local.set $i (i32.add (local.get $i) (i32.const 1)))
+            (br $next)))
+        (i64.or (i64.shl (i64.extend_i32_u (local.get $ptr)) (i64.const 32))
+                (i64.extend_i32_u (local.get $len)))))
+    "#;
+
+    const SPIN: &str = r#"
+    (module
+      (memory (export "memory") 1)
+      (func (export "alloc") (param i32) (result i32) (i32.const 0))
+      (func (export "transform") (param i32 i32) (result i64)
+        (loop $spin (br $spin))
+        (i64.const -1)))
+    "#;
+
+    fn write_module(dir: &Path, name: &str, wat: &str) -> String {
+        let path = dir.join(format!("{}.wasm", name));
+        fs::write(&path, wat::parse_str(wat).unwrap()).unwrap();
+        path.display().to_string()
+    }
+
+    // the plugin dir of the agent is global, every test loads the same one
+    fn plugin_dir(test: &str) -> (PathBuf, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("plugin-{}", std::process::id()));
+        let test_dir = dir.join(test);
+        fs::create_dir_all(&test_dir).unwrap();
+        load_plugin_dir(&dir.display().to_string()).unwrap();
+        (dir, test_dir)
+    }
+
+    #[test]
+    fn it_works() {
+        let (dir, _) = plugin_dir("it-works");
+        let path = write_module(&dir, "upper", UPPER);
+        assert_eq!(load_plugin_dir(&dir.display().to_string()), Ok(1));
+        assert!(MODULES.read().unwrap().contains_key(&PathBuf::from(&path)));
+
+        let plugin = WasmPlugin::new(&Plugin {
+            name: "upper".to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(
+            plugin.transform("took 15ms").unwrap(),
+            Some("TOOK 15MS".to_string())
+        );
+        assert_eq!(plugin.transform("# comment").unwrap(), None);
+        assert!(plugin.pass("line"));
+        assert!(!plugin.pass("# comment"));
+
+        for name in &["../upper", "", "missing"] {
+            assert!(WasmPlugin::new(&Plugin {
+                name: name.to_string(),
+                ..Default::default()
+            })
+            .is_err());
+        }
+
+        // a path never leaves the plugin dir
+        let outside = std::env::temp_dir().join(format!("upper-{}.wasm", std::process::id()));
+        fs::copy(&path, &outside).unwrap();
+        for path in &[
+            outside.display().to_string(),
+            format!("../upper-{}.wasm", std::process::id()),
+        ] {
+            let err = WasmPlugin::new(&Plugin {
+                path: path.to_string(),
+                ..Default::default()
+            })
+            .err()
+            .unwrap();
+            assert!(err.contains("outside the plugin dir"), "{}", err);
+        }
+        assert!(WasmPlugin::new(&Plugin {
+            path: "upper.wasm".to_string(),
+            ..Default::default()
+        })
+        .is_ok());
+        fs::remove_file(&outside).unwrap();
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn sandbox_it_works() {
+        let (_, dir) = plugin_dir("sandbox");
+        let mut config = Plugin {
+            path: write_module(&dir, "spin", SPIN),
+            fuel: 10_000,
+            ..Default::default()
+        };
+        let plugin = WasmPlugin::new(&config).unwrap();
+        assert!(plugin.transform("line").is_err());
+        // a failed call gets a new instance next time
+        assert!(plugin.transform("line").is_err());
+        assert!(plugin.pass("line"));
+        config.on_failure = PluginFailure::Drop;
+        assert!(!WasmPlugin::new(&config).unwrap().pass("line"));
+
+        let imports = r#"
+        (module
+          (import "wasi_snapshot_preview1" "fd_write" (func (param i32 i32 i32 i32) (result i32)))
+          (memory (export "memory") 1)
+          (func (export "alloc") (param i32) (result i32) (i32.const 0))
+          (func (export "filter") (param i32 i32) (result i32) (i32.const 1)))
+        "#;
+        let err = WasmPlugin::new(&Plugin {
+            path: write_module(&dir, "imports", imports),
+            ..Default::default()
+        })
+        .err()
+        .unwrap();
+        assert!(err.contains("imports are not supported"), "{}", err);
+
+        let large = r#"
+        (module
+          (memory (export "memory") 64)
+          (func (export "alloc") (param i32) (result i32) (i32.const 0))
+          (func (export "filter") (param i32 i32) (result i32) (i32.const 1)))
+        "#;
+        assert!(WasmPlugin::new(&Plugin {
+            path: write_module(&dir, "large", large),
+            max_memory: 1024 * 1024,
+            ..Default::default()
+        })
+        .is_err());
+
+        // the start function runs metered
+        let start = r#"
+        (module
+          (memory (export "memory") 1)
+          (global $ready (mut i32) (i32.const 0))
+          (func $init (global.set $ready (i32.const 1)))
+          (start $init)
+          (func (export "alloc") (param i32) (result i32) (i32.const 0))
+          (func (export "filter") (param i32 i32) (result i32) (global.get $ready)))
+        "#;
+        let plugin = WasmPlugin::new(&Plugin {
+            path: write_module(&dir, "start", start),
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(plugin.pass("line"));
+        let start_spin = r#"
+        (module
+          (memory (export "memory") 1)
+          (func $init (loop $spin (br $spin)))
+          (start $init)
+          (func (export "alloc") (param i32) (result i32) (i32.const 0))
+          (func (export "filter") (param i32 i32) (result i32) (i32.const 1)))
+        "#;
+        assert!(WasmPlugin::new(&Plugin {
+            path: write_module(&dir, "start_spin", start_spin),
+            fuel: 10_000,
+            ..Default::default()
+        })
+        .is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
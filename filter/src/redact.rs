@@ -0,0 +1,294 @@
+use db::{Redact, RedactRule};
+use regex::{Captures, Regex};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+
+pub const EMAIL: &str = "email";
+pub const PHONE: &str = "phone";
+pub const CREDIT_CARD: &str = "credit_card";
+pub const BEARER_TOKEN: &str = "bearer_token";
+pub const PASSWORD: &str = "password";
+// counter of the values masked by `fields`
+pub const JSON_FIELD: &str = "json_field";
+
+const FIELD_REPLACEMENT: &str = "[REDACTED]";
+
+lazy_static! {
+    // rule name -> redactions by the rule
+    static ref REDACTIONS: RwLock<HashMap<String, Arc<AtomicU64>>> = RwLock::new(HashMap::new());
+}
+
+fn counter(name: &str) -> Arc<AtomicU64> {
+    match REDACTIONS.write() {
+        Ok(mut redactions) => redactions
+            .entry(name.to_string())
+            .or_insert_with(|| Arc::new(AtomicU64::new(0)))
+            .clone(),
+        Err(e) => {
+            eprintln!("[ERROR] redaction counters write lock failed: {:?}", e);
+            Arc::new(AtomicU64::new(0))
+        }
+    }
+}
+
+/// redaction_counts returns the redactions per rule since the start.
+pub fn redaction_counts() -> HashMap<String, u64> {
+    match REDACTIONS.read() {
+        Ok(redactions) => redactions
+            .iter()
+            .map(|(name, count)| (name.clone(), count.load(Ordering::Relaxed)))
+            .collect(),
+        Err(_) => HashMap::new(),
+    }
+}
+
+// builtin returns the pattern and the replacement of a builtin detector
+fn builtin(name: &str) -> Option<(&'static str, &'static str)> {
+    match name {
+        EMAIL => Some((r"[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,}", "[EMAIL]")),
+        PHONE => Some((
+            r"\+?\b\d{1,3}[ .-]?\(?\d{3}\)?[ .-]?\d{3}[ .-]?\d{4}\b",
+            "[PHONE]",
+        )),
+        CREDIT_CARD => Some((r"\b\d(?:[ -]?\d){12,18}\b", "[CREDIT_CARD]")),
+        BEARER_TOKEN => Some((r"(?i)\b(bearer)\s+[A-Za-z0-9._~+/-]+=*", "$1 [TOKEN]")),
+        PASSWORD => Some((
+            r#"(?i)\b(password|passwd|pwd)("?\s*[:=]\s*"?)[^\s"&,;]+"#,
+            "$1$2[PASSWORD]",
+        )),
+        _ => None,
+    }
+}
+
+/// luhn validates the check digit of a card number, separators are ignored.
+pub fn luhn(number: &str) -> bool {
+    let digits = number
+        .chars()
+        .filter(|c| c.is_ascii_digit())
+        .map(|c| c as u32 - '0' as u32)
+        .collect::<Vec<u32>>();
+    if digits.len() < 13 || digits.len() > 19 {
+        return false;
+    }
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, d)| match i % 2 {
+            1 if *d * 2 > 9 => *d * 2 - 9,
+            1 => *d * 2,
+            _ => *d,
+        })
+        .sum();
+    sum.is_multiple_of(10)
+}
+
+struct Rule {
+    regex: Regex,
+    replacement: String,
+    // a match is only masked when it validates
+    validate: Option<fn(&str) -> bool>,
+    count: Arc<AtomicU64>,
+}
+
+/// Redactor masks the messages of a task, it is built once per reader.
+pub struct Redactor {
+    rules: Vec<Rule>,
+    fields: Vec<String>,
+    field_count: Arc<AtomicU64>,
+}
+
+impl Redactor {
+    pub fn new(config: &Redact) -> Result<Self, String> {
+        let mut rules = vec![];
+        // card numbers first, so the phone detector does not take their digits
+        let mut builtins = config.builtins.iter().collect::<Vec<&String>>();
+        builtins.sort_by_key(|name| name.as_str() != CREDIT_CARD);
+        for name in builtins {
+            let (pattern, replacement) = match builtin(name) {
+                Some(builtin) => builtin,
+                None => return Err(format!("unknown builtin redaction {:?}", name)),
+            };
+            rules.push(Rule {
+                regex: Regex::new(pattern).map_err(|e| e.to_string())?,
+                replacement: replacement.to_string(),
+                validate: if name == CREDIT_CARD {
+                    Some(luhn)
+                } else {
+                    None
+                },
+                count: counter(name),
+            });
+        }
+        for RedactRule {
+            name,
+            pattern,
+            replacement,
+        } in config.rules.iter()
+        {
+            let regex = Regex::new(pattern)
+                .map_err(|e| format!("invalid redaction rule {:?}: {}", name, e))?;
+            rules.push(Rule {
+                regex,
+                replacement: replacement.clone(),
+                validate: None,
+                count: counter(name),
+            });
+        }
+        Ok(Self {
+            rules,
+            fields: config.fields.iter().map(|f| f.to_lowercase()).collect(),
+            field_count: counter(JSON_FIELD),
+        })
+    }
+
+    // mask_fields replaces the values of the fields at any depth of the json value
+    fn mask_fields(&self, value: &mut Value) -> u64 {
+        let mut masked = 0;
+        match value {
+            Value::Object(map) => {
+                for (key, value) in map.iter_mut() {
+                    if self.fields.contains(&key.to_lowercase()) {
+                        *value = Value::String(FIELD_REPLACEMENT.to_string());
+                        masked += 1;
+                    } else {
+                        masked += self.mask_fields(value);
+                    }
+                }
+            }
+            Value::Array(values) => {
+                for value in values.iter_mut() {
+                    masked += self.mask_fields(value);
+                }
+            }
+            _ => {}
+        }
+        masked
+    }
+
+    pub fn redact(&self, message: &str) -> String {
+        let mut message = message.to_string();
+        if !self.fields.is_empty() && message.trim_start().starts_with('{') {
+            if let Ok(mut value) = serde_json::from_str::<Value>(&message) {
+                let masked = self.mask_fields(&mut value);
+                if masked > 0 {
+                    self.field_count.fetch_add(masked, Ordering::Relaxed);
+                    let newline = if message.ends_with('\n') { "\n" } else { "" };
+                    message = format!("{}{}", value, newline);
+                }
+            }
+        }
+
+        for rule in self.rules.iter() {
+            let mut masked = 0;
+            let redacted = rule.regex.replace_all(&message, |caps: &Captures| {
+                let matched = &caps[0];
+                if let Some(validate) = rule.validate {
+                    if !validate(matched) {
+                        return matched.to_string();
+                    }
+                }
+                masked += 1;
+                let mut replacement = String::new();
+                caps.expand(&rule.replacement, &mut replacement);
+                replacement
+            });
+            if masked > 0 {
+                rule.count.fetch_add(masked, Ordering::Relaxed);
+                message = redacted.into_owned();
+            }
+        }
+        message
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{luhn, redaction_counts, Redactor};
+    use db::{Redact, RedactRule};
+
+    #[test]
+    fn it_works() {
+        let redactor = Redactor::new(&Redact {
+            builtins: vec![
+                "email".to_string(),
+                "phone".to_string(),
+                "credit_card".to_string(),
+                "bearer_token".to_string(),
+                "password".to_string(),
+            ],
+            ..Default::default()
+        })
+        .unwrap();
+
+        assert_eq!(
+            redactor.redact("user jane.doe@example.com logged in"),
+            "user [EMAIL] logged in"
+        );
+        assert_eq!(redactor.redact("call +1 415-555-0132"), "call [PHONE]");
+        assert_eq!(
+            redactor.redact("card 4111 1111 1111 1111 charged"),
+            "card [CREDIT_CARD] charged"
+        );
+        // an order id failing the luhn check is kept
+        assert_eq!(
+            redactor.redact("order 1234567890123456"),
+            "order 1234567890123456"
+        );
+        assert_eq!(
+            redactor.redact("Authorization: Bearer eyJhbGciOi.J9.abc"),
+            "Authorization: Bearer [TOKEN]"
+        );
+        assert_eq!(
+            redactor.redact("login password=hunter2 ok"),
+            "login password=[PASSWORD] ok"
+        );
+
+        assert!(luhn("4111-1111-1111-1111"));
+        assert!(!luhn("4111-1111-1111-1112"));
+        assert!(redaction_counts()["credit_card"] >= 1);
+
+        assert!(Redactor::new(&Redact {
+            builtins: vec!["ssn".to_string()],
+            ..Default::default()
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn rules_and_fields_it_works() {
+        let redactor = Redactor::new(&Redact {
+            rules: vec![RedactRule {
+                name: "session".to_string(),
+                pattern: r"session=(?P<prefix>\w{4})\w+".to_string(),
+                replacement: "session=${prefix}****".to_string(),
+            }],
+            fields: vec!["Secret".to_string()],
+            ..Default::default()
+        })
+        .unwrap();
+
+        assert_eq!(
+            redactor.redact("session=abcd123456 started"),
+            "session=abcd**** started"
+        );
+        assert_eq!(
+            redactor.redact("{\"user\":\"jane\",\"auth\":{\"secret\":\"s3cr3t\"}}\n"),
+            "{\"auth\":{\"secret\":\"[REDACTED]\"},\"user\":\"jane\"}\n"
+        );
+        assert_eq!(redactor.redact("secret=plain"), "secret=plain");
+        assert!(redaction_counts()["session"] >= 1);
+
+        assert!(Redactor::new(&Redact {
+            rules: vec![RedactRule {
+                name: "broken".to_string(),
+                pattern: "(".to_string(),
+                replacement: "".to_string(),
+            }],
+            ..Default::default()
+        })
+        .is_err());
+    }
+}
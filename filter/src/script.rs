@@ -0,0 +1,236 @@
+use db::{Container, Script};
+use rhai::module_resolvers::DummyModuleResolver;
+use rhai::serde::{from_dynamic, to_dynamic};
+use rhai::{Dynamic, Engine, EvalAltResult, Scope, AST};
+use serde_json::{json, Map, Value};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// the limits of a run unless configured
+const DEFAULT_MAX_OPERATIONS: u64 = 100_000;
+const DEFAULT_TIMEOUT: Duration = Duration::from_millis(10);
+const DEFAULT_MAX_STRING_SIZE: usize = 1024 * 1024;
+const DEFAULT_MAX_COLLECTION_SIZE: usize = 10_000;
+
+fn or_default<T: PartialEq + Default>(value: T, default: T) -> T {
+    if value == T::default() {
+        default
+    } else {
+        value
+    }
+}
+
+/// Scripter runs the script of a task on the records of one container, in an
+/// engine without modules, printing or host access.
+///
+/// The script gets the record as `record`, a map of `message`, `fields`,
+/// `level` and `tags`, and the container metadata as the constant `container`.
+/// It ships `record` as modified unless it returns false to drop it, a map to
+/// replace it or an array of maps to split it.
+pub struct Scripter {
+    config: Script,
+    engine: Engine,
+    ast: AST,
+    container: Dynamic,
+    timeout: Duration,
+    // the deadline of the current run in nanoseconds since `started`
+    started: Instant,
+    deadline: Arc<AtomicU64>,
+}
+
+/// container_metadata returns the container fields exposed to scripts.
+pub fn container_metadata(container: &Container) -> Value {
+    json!({
+        "ns": container.ns,
+        "pod_name": container.pod_name,
+        "container": container.container,
+        "service_name": container.service_name,
+        "node_name": container.node_name,
+        "path": container.path,
+        "source": container.source,
+        "image": container.image,
+        "labels": container.labels,
+        "annotations": container.annotations,
+    })
+}
+
+impl Scripter {
+    pub fn new(config: &Script, container: &Container) -> Result<Self, String> {
+        let started = Instant::now();
+        let deadline = Arc::new(AtomicU64::new(u64::MAX));
+
+        let mut engine = Engine::new();
+        engine.set_module_resolver(DummyModuleResolver::new());
+        engine.on_print(|_| {});
+        engine.on_debug(|_, _, _| {});
+        engine.set_max_operations(or_default(config.max_operations, DEFAULT_MAX_OPERATIONS));
+        engine.set_max_string_size(or_default(config.max_string_size, DEFAULT_MAX_STRING_SIZE));
+        let max_collection_size =
+            or_default(config.max_collection_size, DEFAULT_MAX_COLLECTION_SIZE);
+        engine.set_max_array_size(max_collection_size);
+        engine.set_max_map_size(max_collection_size);
+        engine.set_max_call_levels(32);
+        engine.set_max_expr_depths(64, 32);
+        let run_deadline = deadline.clone();
+        engine.on_progress(move |_| {
+            let elapsed = started.elapsed().as_nanos() as u64;
+            if elapsed > run_deadline.load(Ordering::Relaxed) {
+                return Some(Dynamic::UNIT);
+            }
+            None
+        });
+
+        let ast = engine
+            .compile(&config.source)
+            .map_err(|e| format!("script compile error: {}", e))?;
+        let container = to_dynamic(container_metadata(container))
+            .map_err(|e| format!("script container error: {}", e))?;
+        Ok(Self {
+            config: config.clone(),
+            engine,
+            ast,
+            container,
+            timeout: match config.timeout_ms {
+                0 => DEFAULT_TIMEOUT,
+                ms => Duration::from_millis(ms),
+            },
+            started,
+            deadline,
+        })
+    }
+
+    /// is_compiled_from tells whether the scripter runs the config, a changed
+    /// config is compiled again.
+    pub fn is_compiled_from(&self, config: &Script) -> bool {
+        &self.config == config
+    }
+
+    /// run returns the records to ship for the record, none drops it.
+    pub fn run(&self, record: Map<String, Value>) -> Result<Vec<Map<String, Value>>, String> {
+        let record = to_dynamic(Value::Object(record)).map_err(|e| e.to_string())?;
+        let mut scope = Scope::new();
+        scope.push_dynamic("record", record);
+        scope.push_constant_dynamic("container", self.container.clone());
+
+        let deadline = self.started.elapsed() + self.timeout;
+        self.deadline
+            .store(deadline.as_nanos() as u64, Ordering::Relaxed);
+        let result = self
+            .engine
+            .eval_ast_with_scope::<Dynamic>(&mut scope, &self.ast);
+        self.deadline.store(u64::MAX, Ordering::Relaxed);
+        let result = result.map_err(|e| match *e {
+            EvalAltResult::ErrorTerminated(..) => {
+                format!("script timed out after {:?}", self.timeout)
+            }
+            e => e.to_string(),
+        })?;
+
+        let records = if result.is_unit() || result.as_bool() == Ok(true) {
+            vec![scope.get_value::<Dynamic>("record").unwrap_or_default()]
+        } else if result.as_bool() == Ok(false) {
+            vec![]
+        } else if result.is_array() {
+            result.into_array()?
+        } else {
+            vec![result]
+        };
+        records
+            .iter()
+            .map(|record| match from_dynamic::<Value>(record) {
+                Ok(Value::Object(record)) => Ok(record),
+                Ok(_) => Err(format!(
+                    "script returned a {} instead of a record",
+                    record.type_name()
+                )),
+                Err(e) => Err(e.to_string()),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Scripter;
+    use db::{Container, Script};
+    use serde_json::{json, Map, Value};
+    use std::collections::HashMap;
+
+    fn record(message: &str) -> Map<String, Value> {
+        match json!({"message": message, "fields": {}, "tags": []}) {
+            Value::Object(record) => record,
+            _ => unreachable!(),
+        }
+    }
+
+    fn new_scripter(source: &str) -> Scripter {
+        let container = Container {
+            path: "/var/log/pods/tenant-a_web-1/nginx/0.log".to_string(),
+            labels: vec![("app".to_string(), "web".to_string())]
+                .into_iter()
+                .collect::<HashMap<String, String>>(),
+            ..Default::default()
+        };
+        let config = Script {
+            source: source.to_string(),
+            ..Default::default()
+        };
+        Scripter::new(&config, &container).unwrap()
+    }
+
+    #[test]
+    fn it_works() {
+        let scripter = new_scripter(
+            r#"
+            if record.message.contains("GET /healthz") { return false; }
+            record.fields.tenant = container.path.split("/")[4].split("_")[0];
+            record.fields.app = container.labels.app;
+            "#,
+        );
+        let records = scripter.run(record("GET /index")).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0]["fields"]["tenant"], "tenant-a");
+        assert_eq!(records[0]["fields"]["app"], "web");
+        assert!(scripter.run(record("GET /healthz")).unwrap().is_empty());
+
+        let scripter = new_scripter(
+            r#"record.message.split(";").map(|part| { part.trim(); #{ message: part, fields: #{} } })"#,
+        );
+        let records = scripter.run(record("a; b; c")).unwrap();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[2]["message"], "c");
+
+        assert!(scripter.run(record("a")).is_ok());
+        assert!(Scripter::new(
+            &Script {
+                source: "record.message = ".to_string(),
+                ..Default::default()
+            },
+            &Container::default()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn limits_it_works() {
+        let scripter = new_scripter("loop {}");
+        assert!(scripter.run(record("a")).is_err());
+
+        let scripter = new_scripter(r#"let s = "x"; loop { s += s; }"#);
+        assert!(scripter.run(record("a")).is_err());
+
+        let config = Script {
+            source: "loop {}".to_string(),
+            max_operations: u64::MAX,
+            timeout_ms: 20,
+            ..Default::default()
+        };
+        let scripter = Scripter::new(&config, &Container::default()).unwrap();
+        let err = scripter.run(record("a")).unwrap_err();
+        assert!(err.contains("timed out"), "{}", err);
+        assert!(scripter.is_compiled_from(&config));
+
+        assert!(new_scripter("42").run(record("a")).is_err());
+    }
+}
@@ -0,0 +1,292 @@
+use super::{IInput, InputSink, Record};
+use common::Result;
+use glob::glob;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Seek, SeekFrom};
+use std::os::unix::fs::MetadataExt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+// HostFile is an open host file, `line` holds a line read before it was
+// completely written
+struct HostFile {
+    inode: u64,
+    offset: u64,
+    reader: BufReader<File>,
+    line: String,
+}
+
+impl HostFile {
+    fn open(path: &str, inode: u64, offset: u64) -> Result<Self> {
+        let mut file = File::open(path)?;
+        file.seek(SeekFrom::Start(offset))?;
+        Ok(Self {
+            inode,
+            offset,
+            reader: BufReader::new(file),
+            line: String::new(),
+        })
+    }
+}
+
+/// HostFiles tails the host files matching glob patterns such as `/var/log/*.log`,
+/// records carry the file path as `source` instead of pod metadata.
+pub struct HostFiles {
+    patterns: Vec<String>,
+    node_name: String,
+    sink: InputSink,
+    // a new inode at the same path is a rotated file
+    files: HashMap<String, HostFile>,
+    synced: bool,
+}
+
+impl HostFiles {
+    pub fn new(patterns: Vec<String>, node_name: &str, sink: InputSink) -> Self {
+        Self {
+            patterns,
+            node_name: node_name.to_string(),
+            sink,
+            files: HashMap::new(),
+            synced: false,
+        }
+    }
+
+    fn stats(&self) -> HashMap<String, (u64, u64)> {
+        let mut files = HashMap::new();
+        for pattern in self.patterns.iter() {
+            let entries = match glob(pattern) {
+                Ok(entries) => entries,
+                Err(e) => {
+                    eprintln!("[ERROR] host path pattern {:?} error: {:?}", pattern, e);
+                    continue;
+                }
+            };
+            for entry in entries.filter_map(|entry| entry.ok()) {
+                let metadata = match fs::metadata(&entry) {
+                    Ok(metadata) if metadata.is_file() => metadata,
+                    _ => continue,
+                };
+                if let Some(path) = entry.to_str() {
+                    files.insert(path.to_string(), (metadata.ino(), metadata.len()));
+                }
+            }
+        }
+        files
+    }
+
+    // read emits the complete lines written to the file since the last read
+    fn read(&mut self, path: &str) {
+        let file = match self.files.get_mut(path) {
+            Some(file) => file,
+            None => return,
+        };
+        loop {
+            match file.reader.read_line(&mut file.line) {
+                Ok(0) => break,
+                Ok(size) => {
+                    file.offset += size as u64;
+                    if !file.line.ends_with('\n') {
+                        break;
+                    }
+                }
+                Err(e) => {
+                    eprintln!("[ERROR] read host file {:?} error: {:?}", path, e);
+                    break;
+                }
+            }
+            let line = file.line.trim_end_matches(['\n', '\r']);
+            self.sink.emit(
+                &Record::new(path, line).with_custom("nodeName", self.node_name.clone().into()),
+            );
+            file.line.clear();
+        }
+    }
+
+    fn open(&mut self, path: &str, inode: u64, offset: u64) {
+        match HostFile::open(path, inode, offset) {
+            Ok(file) => {
+                println!("[INFO] open host file {:?} at offset {:?}", path, offset);
+                self.files.insert(path.to_string(), file);
+            }
+            Err(e) => eprintln!("[ERROR] open host file {:?} error: {:?}", path, e),
+        }
+    }
+
+    /// sync opens the files matching the patterns, reads their new lines and
+    /// closes the removed ones. Files found by the first sync are tailed from
+    /// their end, files created or rotated later from their beginning.
+    pub fn sync(&mut self) -> usize {
+        let stats = self.stats();
+        let mut changes = 0;
+
+        for (path, (inode, len)) in stats.iter() {
+            match self.files.get(path) {
+                Some(file) if file.inode == *inode && file.offset <= *len => {}
+                Some(file) if file.inode == *inode => {
+                    // a copytruncate rotation, the file is read again from its beginning
+                    println!("[INFO] host file {:?} truncated", path);
+                    self.open(path, *inode, 0);
+                    changes += 1;
+                }
+                Some(_) => {
+                    // a rename rotation, the lines left in the renamed file are read first
+                    println!("[INFO] host file {:?} rotated", path);
+                    self.read(path);
+                    self.open(path, *inode, 0);
+                    changes += 1;
+                }
+                None => {
+                    let offset = if self.synced { 0 } else { *len };
+                    self.open(path, *inode, offset);
+                    changes += 1;
+                }
+            }
+            self.read(path);
+        }
+
+        let removed = self
+            .files
+            .keys()
+            .filter(|path| !stats.contains_key(*path))
+            .cloned()
+            .collect::<Vec<String>>();
+        for path in removed {
+            self.read(&path);
+            self.files.remove(&path);
+            changes += 1;
+        }
+
+        self.synced = true;
+        changes
+    }
+
+    /// close reads the lines left and closes every open file.
+    pub fn close(&mut self) {
+        let paths = self.files.keys().cloned().collect::<Vec<String>>();
+        for path in paths {
+            self.read(&path);
+        }
+        self.files.clear();
+    }
+}
+
+/// HostFilesInput syncs the host files on an interval, their lines are
+/// written to the sink.
+pub struct HostFilesInput {
+    patterns: Vec<String>,
+    node_name: String,
+    interval: Duration,
+    stopped: Arc<AtomicBool>,
+}
+
+impl HostFilesInput {
+    pub fn new(patterns: Vec<String>, node_name: &str, interval: Duration) -> Self {
+        Self {
+            patterns,
+            node_name: node_name.to_string(),
+            interval,
+            stopped: Arc::new(AtomicBool::new(false)),
+        }
+    }
+}
+
+impl IInput for HostFilesInput {
+    fn start(&mut self, sink: InputSink) -> Result<()> {
+        self.stopped.store(false, Ordering::SeqCst);
+        let stopped = self.stopped.clone();
+        let interval = self.interval;
+        let mut host_files = HostFiles::new(self.patterns.clone(), &self.node_name, sink);
+        thread::spawn(move || loop {
+            if stopped.load(Ordering::SeqCst) {
+                host_files.close();
+                return;
+            }
+            host_files.sync();
+            thread::sleep(interval);
+        });
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HostFiles;
+    use crate::InputSink;
+    use filter::Filter;
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+
+    struct Collector(Arc<Mutex<Vec<String>>>);
+    impl Filter for Collector {
+        fn pass(&self, message: &str) -> bool {
+            self.0.lock().unwrap().push(message.to_string());
+            true
+        }
+    }
+
+    fn append(path: &std::path::Path, line: &str) {
+        let mut file = OpenOptions::new().append(true).open(path).unwrap();
+        file.write_all(line.as_bytes()).unwrap();
+    }
+
+    #[test]
+    fn it_works() {
+        let dir = std::env::temp_dir().join("harvest_host_files");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let kubelet = dir.join("kubelet.log");
+        fs::write(&kubelet, "I0101 kubelet started\n").unwrap();
+
+        let seen = Arc::new(Mutex::new(vec![]));
+        let sink = InputSink::new("fake_output").with_filter(Collector(seen.clone()));
+        let pattern = format!("{}/*.log", dir.to_str().unwrap());
+        let mut host_files = HostFiles::new(vec![pattern], "node1", sink);
+        // files found by the first sync are tailed from their end
+        assert_eq!(host_files.sync(), 1);
+        assert_eq!(host_files.sync(), 0);
+        assert!(seen.lock().unwrap().is_empty());
+
+        append(&kubelet, "I0102 pod synced\nI0103 par");
+        assert_eq!(host_files.sync(), 0);
+        append(&kubelet, "tial line\n");
+        assert_eq!(host_files.sync(), 0);
+
+        let containerd = dir.join("containerd.log");
+        fs::write(&containerd, "containerd started\n").unwrap();
+        assert_eq!(host_files.sync(), 1);
+
+        // a rename rotation, the line written before it is read from the old file
+        append(&kubelet, "I0104 before rotation\n");
+        fs::rename(&kubelet, dir.join("kubelet.log.1")).unwrap();
+        fs::write(&kubelet, "I0105 after rotation\n").unwrap();
+        assert_eq!(host_files.sync(), 1);
+
+        // a copytruncate rotation, the file is read again from its beginning
+        fs::write(&containerd, "new\n").unwrap();
+        assert_eq!(host_files.sync(), 1);
+
+        fs::remove_file(&containerd).unwrap();
+        assert_eq!(host_files.sync(), 1);
+
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![
+                "I0102 pod synced",
+                "I0103 partial line",
+                "containerd started",
+                "I0104 before rotation",
+                "I0105 after rotation",
+                "new",
+            ]
+        );
+        let _ = fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,393 @@
+use super::{IInput, InputSink, Record};
+use common::Result;
+use db::Container;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+/// POD_HEADER attributes pushed records to `ns/pod` or `ns/pod/container`,
+/// `ns/pod` picks the collected container of the pod first by name.
+pub const POD_HEADER: &str = "X-Harvest-Pod";
+// the record field overriding the pod of the connection or request
+const POD_FIELD: &str = "pod";
+const MESSAGE_FIELDS: [&str; 3] = ["message", "msg", "log"];
+// longer lines are dropped instead of buffered
+const MAX_LINE_SIZE: u64 = 1024 * 1024;
+
+/// resolve_container finds the collected container of a pushed record, by the
+/// `ns/pod[/container]` reference or else by the peer ip against `Container.ips`.
+/// A reference without container, like a peer ip, resolves to the collected
+/// container of the pod first by name.
+pub fn resolve_container(pod: Option<&str>, peer_ip: &str) -> Option<Container> {
+    let containers = match pod {
+        Some(pod) => {
+            let mut items = pod.splitn(3, '/');
+            let ns = items.next().unwrap_or("");
+            let pod_name = items.next().unwrap_or("");
+            let name = items.next();
+            db::get_container_slice_by_pod(ns, pod_name)
+                .into_iter()
+                .filter(|(_, container)| match name {
+                    Some(name) => container.container == name,
+                    None => true,
+                })
+                .collect()
+        }
+        None => db::get_container_slice_by_ip(peer_ip),
+    };
+    containers
+        .into_iter()
+        .map(|(_, container)| container)
+        .filter(|container| container.is_upload())
+        .min_by(|a, b| a.container.cmp(&b.container))
+}
+
+// skip_line discards the rest of a line buffer by buffer, it returns false at
+// the end of the stream
+fn skip_line<R: BufRead>(reader: &mut R) -> io::Result<bool> {
+    loop {
+        let (found, used) = {
+            let buf = reader.fill_buf()?;
+            if buf.is_empty() {
+                return Ok(false);
+            }
+            match buf.iter().position(|b| *b == b'\n') {
+                Some(i) => (true, i + 1),
+                None => (false, buf.len()),
+            }
+        };
+        reader.consume(used);
+        if found {
+            return Ok(true);
+        }
+    }
+}
+
+// ingest_record converts a pushed line, a json line keeps its fields beside
+// the message and may name its pod
+fn ingest_record(line: &str, container: &Container, fields: Map<String, Value>) -> Record {
+    let mut record = Record::new("ingest", line);
+    record.custom.extend(file::custom_fields(container));
+    record
+        .custom
+        .insert("source".to_string(), Value::String("ingest".to_string()));
+    if fields.is_empty() {
+        return record;
+    }
+    record.message = MESSAGE_FIELDS
+        .iter()
+        .find_map(|key| fields.get(*key).and_then(|message| message.as_str()))
+        .unwrap_or(line)
+        .to_string();
+    record.fields = fields
+        .into_iter()
+        .filter(|(key, _)| key != POD_FIELD && !MESSAGE_FIELDS.contains(&key.as_str()))
+        .collect();
+    record
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, serde_derive::Serialize)]
+pub struct IngestResult {
+    pub accepted: usize,
+    pub dropped: usize,
+}
+
+/// ingest routes every line of `body` to the task output of its pod, lines of
+/// pods without a task go to the fallback sink when it has an output. The
+/// sinks of the pods are kept in `sinks` by container path, so a connection
+/// reuses them across its lines.
+pub fn ingest(
+    body: &str,
+    pod: Option<&str>,
+    peer_ip: &str,
+    fallback: &InputSink,
+    sinks: &mut HashMap<String, InputSink>,
+) -> IngestResult {
+    let mut result = IngestResult::default();
+    // the lines of a body mostly name the same pod, it is resolved once
+    let mut containers: HashMap<Option<String>, Option<Container>> = HashMap::new();
+    for line in body
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+    {
+        let fields = match serde_json::from_str::<Value>(line) {
+            Ok(Value::Object(fields)) => fields,
+            _ => Map::new(),
+        };
+        let line_pod = fields.get(POD_FIELD).and_then(|pod| pod.as_str()).or(pod);
+        let container = containers
+            .entry(line_pod.map(|pod| pod.to_string()))
+            .or_insert_with(|| resolve_container(line_pod, peer_ip))
+            .clone();
+        match container {
+            Some(container) => {
+                let record = ingest_record(line, &container, fields);
+                sinks
+                    .entry(container.path.clone())
+                    .or_insert_with(|| InputSink::new(&container.output).with_pipeline(&container))
+                    .emit(&record);
+                result.accepted += 1;
+            }
+            None if !fallback.output().is_empty() => {
+                fallback.emit(
+                    &Record::new("ingest", line)
+                        .with_custom("peerIp", Value::String(peer_ip.to_string())),
+                );
+                result.accepted += 1;
+            }
+            None => result.dropped += 1,
+        }
+    }
+    result
+}
+
+/// TcpInput accepts newline delimited json on a tcp address, records are
+/// attributed to the pod named by their `pod` field or by the peer ip.
+pub struct TcpInput {
+    addr: String,
+    stopped: Arc<AtomicBool>,
+}
+
+impl TcpInput {
+    pub fn new(addr: &str) -> Self {
+        Self {
+            addr: addr.to_string(),
+            stopped: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    fn handle(stream: TcpStream, sink: InputSink, stopped: Arc<AtomicBool>) {
+        let peer_ip = match stream.peer_addr() {
+            Ok(addr) => addr.ip().to_string(),
+            Err(_) => return,
+        };
+        let mut reader = BufReader::new(stream);
+        let mut line = Vec::new();
+        let mut sinks = HashMap::new();
+        while !stopped.load(Ordering::SeqCst) {
+            line.clear();
+            match reader
+                .by_ref()
+                .take(MAX_LINE_SIZE)
+                .read_until(b'\n', &mut line)
+            {
+                Ok(0) => return,
+                Ok(_) if line.last() != Some(&b'\n') && line.len() as u64 == MAX_LINE_SIZE => {
+                    eprintln!(
+                        "[ERROR] ingest drop line longer than {} from {:?}",
+                        MAX_LINE_SIZE, peer_ip
+                    );
+                    match skip_line(&mut reader) {
+                        Ok(true) => {}
+                        _ => return,
+                    }
+                }
+                Ok(_) => {
+                    ingest(
+                        &String::from_utf8_lossy(&line),
+                        None,
+                        &peer_ip,
+                        &sink,
+                        &mut sinks,
+                    );
+                }
+                Err(e) => {
+                    eprintln!("[ERROR] ingest read from {:?} error: {:?}", peer_ip, e);
+                    return;
+                }
+            }
+        }
+    }
+}
+
+impl IInput for TcpInput {
+    fn start(&mut self, sink: InputSink) -> Result<()> {
+        self.stopped.store(false, Ordering::SeqCst);
+        let listener = TcpListener::bind(&self.addr)?;
+        println!("[INFO] ingest listen on {:?}", self.addr);
+        let stopped = self.stopped.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                if stopped.load(Ordering::SeqCst) {
+                    return;
+                }
+                match stream {
+                    Ok(stream) => {
+                        let sink = sink.clone();
+                        let stopped = stopped.clone();
+                        thread::spawn(move || Self::handle(stream, sink, stopped));
+                    }
+                    Err(e) => eprintln!("[ERROR] ingest accept error: {:?}", e),
+                }
+            }
+        });
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ingest, ingest_record, resolve_container, skip_line};
+    use crate::InputSink;
+    use common::{Item, Result};
+    use db::{Container, Redact};
+    use output::{IOutput, Output, OUTPUTS};
+    use serde_json::{Map, Value};
+    use std::collections::HashMap;
+    use std::io::{BufRead, BufReader};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    struct Capture(Arc<Mutex<Vec<String>>>);
+    impl IOutput for Capture {
+        fn write(&mut self, _: &str, item: Item) -> Result<()> {
+            self.0.lock().unwrap().push(item.string());
+            Ok(())
+        }
+
+        fn wait(&self, _: usize) -> bool {
+            true
+        }
+    }
+
+    #[test]
+    fn it_works() {
+        let mut container = Container {
+            ns: "batch".to_string(),
+            pod_name: "job-1-abcde".to_string(),
+            container: "worker".to_string(),
+            path: "/var/lib/docker/containers/ingest/ingest-json.log".to_string(),
+            ips: vec!["10.1.0.9".to_string()],
+            output: "fake_output".to_string(),
+            ..Default::default()
+        };
+        container.upload();
+        db::insert(&container);
+        for _ in 0..100 {
+            if db::get(&container.path).is_some() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        assert!(resolve_container(Some("batch/job-1-abcde"), "").is_some());
+        assert!(resolve_container(Some("batch/job-1-abcde/worker"), "").is_some());
+        assert!(resolve_container(Some("batch/job-1-abcde/sidecar"), "").is_none());
+        assert!(resolve_container(None, "10.1.0.9").is_some());
+        assert!(resolve_container(None, "10.1.0.10").is_none());
+
+        // a pod reference without container resolves to the first by name
+        let sidecar = Container {
+            container: "agent".to_string(),
+            path: "/var/lib/docker/containers/ingest/ingest-agent-json.log".to_string(),
+            ..container.clone()
+        };
+        db::insert(&sidecar);
+        for _ in 0..100 {
+            if db::get(&sidecar.path).is_some() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        let resolved = resolve_container(Some("batch/job-1-abcde"), "").unwrap();
+        assert_eq!(resolved.container, "agent");
+        db::delete(&sidecar.path);
+        for _ in 0..100 {
+            if db::get(&sidecar.path).is_none() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let body = "{\"msg\":\"done\",\"rows\":3}\nplain line\n\n{\"pod\":\"other/pod\",\"msg\":\"lost\"}\n";
+        let mut sinks = HashMap::new();
+        let result = ingest(body, None, "10.1.0.9", &InputSink::new(""), &mut sinks);
+        assert_eq!(result.accepted, 2);
+        assert_eq!(result.dropped, 1);
+        // the sink of the pod is kept for the next lines of the connection
+        assert_eq!(sinks.len(), 1);
+        assert!(sinks.contains_key(&container.path));
+
+        let fields = match serde_json::from_str::<Value>(
+            r#"{"pod":"batch/job-1-abcde","msg":"done","rows":3}"#,
+        ) {
+            Ok(Value::Object(fields)) => fields,
+            _ => Map::new(),
+        };
+        let record = ingest_record("", &container, fields);
+        assert_eq!(record.message, "done");
+        assert_eq!(record.fields["rows"], 3);
+        assert!(record.fields.get("pod").is_none());
+        assert_eq!(record.custom["nodeId"], "job-1-abcde");
+        assert_eq!(record.custom["source"], "ingest");
+
+        db::delete(&container.path);
+    }
+
+    #[test]
+    fn redact_it_works() {
+        let written = Arc::new(Mutex::new(vec![]));
+        OUTPUTS
+            .write()
+            .unwrap()
+            .registry_output("capture_ingest", Output::new(Capture(written.clone())));
+        let mut container = Container {
+            ns: "batch".to_string(),
+            pod_name: "mailer-1-abcde".to_string(),
+            container: "mailer".to_string(),
+            path: "/var/lib/docker/containers/ingest/mailer-json.log".to_string(),
+            output: "capture_ingest".to_string(),
+            redact: Redact {
+                builtins: vec!["email".to_string()],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        container.upload();
+        db::insert(&container);
+        for _ in 0..100 {
+            if db::get(&container.path).is_some() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let body = "{\"msg\":\"sent to jane@example.com\"}\n";
+        let result = ingest(
+            body,
+            Some("batch/mailer-1-abcde"),
+            "",
+            &InputSink::new(""),
+            &mut HashMap::new(),
+        );
+        assert_eq!(result.accepted, 1);
+        let written = written.lock().unwrap();
+        assert_eq!(written.len(), 1);
+        assert!(written[0].contains("sent to [EMAIL]"));
+        assert!(!written[0].contains("jane@example.com"));
+
+        db::delete(&container.path);
+    }
+
+    #[test]
+    fn skip_line_it_works() {
+        let mut long = vec![b'x'; 64];
+        long.extend_from_slice(b"\nnext\n");
+        let mut reader = BufReader::with_capacity(8, &long[..]);
+        assert!(skip_line(&mut reader).unwrap());
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(line, "next\n");
+        assert!(!skip_line(&mut reader).unwrap());
+    }
+}
@@ -0,0 +1,262 @@
+use super::{IInput, InputSink, Record};
+use common::Result;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+// a corrupted stream must not make the reader allocate gigabytes
+const MAX_FIELD_SIZE: u64 = 16 * 1024 * 1024;
+const JOURNALCTL: &str = "journalctl";
+
+pub type JournalEntry = BTreeMap<String, String>;
+
+/// JournalReader reads the systemd journal export format, entries are
+/// `KEY=value` lines ended by an empty line, binary fields are the key line
+/// followed by a little endian u64 size, the data and a newline.
+pub struct JournalReader<R> {
+    reader: R,
+}
+
+impl<R: BufRead> JournalReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self { reader }
+    }
+
+    fn read_binary_field(&mut self) -> Result<String> {
+        let mut size = [0u8; 8];
+        self.reader.read_exact(&mut size)?;
+        let size = u64::from_le_bytes(size);
+        if size > MAX_FIELD_SIZE {
+            return Err(format!("journal field of {} bytes is too large", size).into());
+        }
+        let mut data = vec![0u8; size as usize];
+        self.reader.read_exact(&mut data)?;
+        let mut newline = [0u8; 1];
+        self.reader.read_exact(&mut newline)?;
+        Ok(String::from_utf8_lossy(&data).to_string())
+    }
+
+    /// next_entry returns the next entry, None at the end of the stream.
+    pub fn next_entry(&mut self) -> Result<Option<JournalEntry>> {
+        let mut entry = JournalEntry::new();
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            if self.reader.read_until(b'\n', &mut line)? == 0 {
+                if entry.is_empty() {
+                    return Ok(None);
+                }
+                return Ok(Some(entry));
+            }
+            if line.last() == Some(&b'\n') {
+                line.pop();
+            }
+            if line.is_empty() {
+                if entry.is_empty() {
+                    continue;
+                }
+                return Ok(Some(entry));
+            }
+
+            match line.iter().position(|b| *b == b'=') {
+                Some(index) => {
+                    entry.insert(
+                        String::from_utf8_lossy(&line[..index]).to_string(),
+                        String::from_utf8_lossy(&line[index + 1..]).to_string(),
+                    );
+                }
+                None => {
+                    let key = String::from_utf8_lossy(&line).to_string();
+                    let value = self.read_binary_field()?;
+                    entry.insert(key, value);
+                }
+            }
+        }
+    }
+}
+
+/// JournalSource is a `journalctl -o export -f` stream or a file in the export format.
+#[derive(Debug, Clone, PartialEq)]
+pub enum JournalSource {
+    Journalctl,
+    File(String),
+}
+
+impl From<&str> for JournalSource {
+    fn from(source: &str) -> Self {
+        if source == JOURNALCTL {
+            return JournalSource::Journalctl;
+        }
+        JournalSource::File(source.to_string())
+    }
+}
+
+// entry_record converts a journal entry, the record carries `source` instead
+// of pod metadata and keeps the journal fields besides the message
+fn entry_record(node_name: &str, entry: &JournalEntry) -> Record {
+    let message = entry.get("MESSAGE").cloned().unwrap_or_default();
+    let journal = entry
+        .iter()
+        .filter(|(k, _)| k.as_str() != "MESSAGE")
+        .map(|(k, v)| (k.clone(), Value::String(v.clone())))
+        .collect::<Map<String, Value>>();
+    Record::new("journald", &message)
+        .with_custom("nodeName", Value::String(node_name.to_string()))
+        .with_field("journal", Value::Object(journal))
+}
+
+/// JournalInput reads the journal source in the background, journalctl is
+/// restarted after the last entry read when it exits.
+pub struct JournalInput {
+    source: JournalSource,
+    node_name: String,
+    stopped: Arc<AtomicBool>,
+}
+
+impl JournalInput {
+    pub fn new(source: JournalSource, node_name: &str) -> Self {
+        Self {
+            source,
+            node_name: node_name.to_string(),
+            stopped: Arc::new(AtomicBool::new(false)),
+        }
+    }
+}
+
+struct JournalWorker {
+    node_name: String,
+    sink: InputSink,
+    stopped: Arc<AtomicBool>,
+    cursor: Option<String>,
+}
+
+impl JournalWorker {
+    fn read_entries<R: BufRead>(&mut self, reader: R) -> Result<()> {
+        let mut reader = JournalReader::new(reader);
+        while let Some(entry) = reader.next_entry()? {
+            if self.stopped.load(Ordering::SeqCst) {
+                return Ok(());
+            }
+            self.sink.emit(&entry_record(&self.node_name, &entry));
+            if let Some(cursor) = entry.get("__CURSOR") {
+                self.cursor = Some(cursor.clone());
+            }
+        }
+        Ok(())
+    }
+
+    fn follow_journalctl(&mut self) -> Result<()> {
+        let mut command = Command::new(JOURNALCTL);
+        command.args(["-o", "export", "-f"]);
+        // a restarted journalctl resumes after the last entry written
+        match &self.cursor {
+            Some(cursor) => command.args(["--after-cursor", cursor.as_str()]),
+            None => command.args(["-n", "0"]),
+        };
+        let mut child = command.stdout(Stdio::piped()).spawn()?;
+        let stdout = match child.stdout.take() {
+            Some(stdout) => stdout,
+            None => return Err("journalctl stdout is not piped".into()),
+        };
+        let result = self.read_entries(BufReader::new(stdout));
+        let _ = child.kill();
+        let _ = child.wait();
+        result
+    }
+
+    fn run(&mut self, source: JournalSource) {
+        match source {
+            JournalSource::Journalctl => {
+                while !self.stopped.load(Ordering::SeqCst) {
+                    if let Err(e) = self.follow_journalctl() {
+                        eprintln!("[ERROR] follow journalctl error: {:?}", e);
+                    }
+                    thread::sleep(Duration::from_secs(1));
+                }
+            }
+            JournalSource::File(path) => {
+                let result = File::open(&path)
+                    .map_err(|e| e.into())
+                    .and_then(|file| self.read_entries(BufReader::new(file)));
+                if let Err(e) = result {
+                    eprintln!("[ERROR] read journal file {:?} error: {:?}", path, e);
+                }
+            }
+        }
+    }
+}
+
+impl IInput for JournalInput {
+    fn start(&mut self, sink: InputSink) -> Result<()> {
+        self.stopped.store(false, Ordering::SeqCst);
+        let source = self.source.clone();
+        let mut worker = JournalWorker {
+            node_name: self.node_name.clone(),
+            sink,
+            stopped: self.stopped.clone(),
+            cursor: None,
+        };
+        thread::spawn(move || worker.run(source));
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{entry_record, JournalReader, JournalSource};
+    use db::Container;
+    use file::RecordEncoder;
+    use serde_json::Value;
+
+    #[test]
+    fn it_works() {
+        let mut export =
+            b"__CURSOR=s=1\n_SYSTEMD_UNIT=kubelet.service\nPRIORITY=6\nMESSAGE=kubelet started\n\n"
+                .to_vec();
+        export.extend_from_slice(b"__CURSOR=s=2\nMESSAGE\n");
+        export.extend_from_slice(&(12u64).to_le_bytes());
+        export.extend_from_slice(b"line1\nline2\n\n\n");
+        export.extend_from_slice(b"_TRANSPORT=kernel\n\n");
+
+        let mut reader = JournalReader::new(&export[..]);
+        let first = reader.next_entry().unwrap().unwrap();
+        assert_eq!(first["_SYSTEMD_UNIT"], "kubelet.service");
+        assert_eq!(first["MESSAGE"], "kubelet started");
+
+        let second = reader.next_entry().unwrap().unwrap();
+        assert_eq!(second["MESSAGE"], "line1\nline2\n");
+        assert_eq!(second["__CURSOR"], "s=2");
+
+        let third = reader.next_entry().unwrap().unwrap();
+        assert_eq!(third["_TRANSPORT"], "kernel");
+        assert!(reader.next_entry().unwrap().is_none());
+
+        let record = entry_record("node1", &first);
+        let record = RecordEncoder::new(&Container::default(), "fake_output").encode(
+            &record.custom,
+            &record.message,
+            record.fields,
+        );
+        let record = serde_json::from_str::<Value>(&record).unwrap();
+        assert_eq!(record["message"], "kubelet started");
+        assert_eq!(record["custom"]["source"], "journald");
+        assert_eq!(record["journal"]["PRIORITY"], "6");
+        assert!(record["journal"].get("MESSAGE").is_none());
+
+        let mut truncated = b"MESSAGE\n".to_vec();
+        truncated.extend_from_slice(&(u64::MAX).to_le_bytes());
+        assert!(JournalReader::new(&truncated[..]).next_entry().is_err());
+
+        assert_eq!(JournalSource::from("journalctl"), JournalSource::Journalctl);
+    }
+}
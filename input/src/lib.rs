@@ -0,0 +1,423 @@
+#[macro_use]
+extern crate lazy_static;
+
+use common::Result;
+use db::Container;
+use file::{RecordEncoder, RecordPipeline, RECORD_VERSION};
+use filter::Filter;
+use output::output_write;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+
+mod host_files;
+mod ingest;
+mod journald;
+
+pub use host_files::{HostFiles, HostFilesInput};
+pub use ingest::{ingest, resolve_container, IngestResult, TcpInput, POD_HEADER};
+pub use journald::{JournalEntry, JournalInput, JournalReader, JournalSource};
+
+pub use INPUTS as INS;
+
+lazy_static! {
+    pub static ref INPUTS: Arc<RwLock<Inputs>> = Arc::new(RwLock::new(Inputs::new()));
+}
+
+pub fn registry_input<T>(name: &str, t: T)
+where
+    T: IInput + Send + Sync + 'static,
+{
+    match INPUTS.write() {
+        Ok(mut ins) => ins.registry_input(name, Input::new(t)),
+        Err(e) => eprintln!("[ERROR] registry_input write lock failed: {:?}", e),
+    }
+}
+
+pub fn input_start(name: &str, sink: InputSink) {
+    match INPUTS.write() {
+        Ok(mut ins) => {
+            if let Err(e) = ins.start(name, sink) {
+                eprintln!("[ERROR] start input {:?} error: {:?}", name, e);
+            }
+        }
+        Err(e) => eprintln!("[ERROR] input_start write lock failed: {:?}", e),
+    }
+}
+
+pub fn input_stop_all() {
+    if let Ok(mut ins) = INPUTS.write() {
+        for i in ins.input_listener.values_mut() {
+            i.stop();
+        }
+    }
+}
+
+/// Record is a message read by an input, `custom` holds the metadata of where
+/// it comes from and `fields` the structured fields kept beside the message.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Record {
+    pub custom: Map<String, Value>,
+    pub fields: Map<String, Value>,
+    pub message: String,
+}
+
+impl Record {
+    pub fn new(source: &str, message: &str) -> Self {
+        let mut custom = Map::new();
+        custom.insert("source".to_string(), Value::String(source.to_string()));
+        custom.insert(
+            "version".to_string(),
+            Value::String(RECORD_VERSION.to_string()),
+        );
+        Self {
+            custom,
+            fields: Map::new(),
+            message: message.to_string(),
+        }
+    }
+
+    pub fn with_custom(mut self, key: &str, value: Value) -> Self {
+        self.custom.insert(key.to_string(), value);
+        self
+    }
+
+    pub fn with_field(mut self, key: &str, value: Value) -> Self {
+        self.fields.insert(key.to_string(), value);
+        self
+    }
+}
+
+/// InputSink passes the records of an input through its filters and the
+/// pipeline of its task config to the output, in the envelope schema of the
+/// task or of the output.
+#[derive(Clone)]
+pub struct InputSink {
+    output: String,
+    filters: Vec<Arc<dyn Filter + Send + Sync>>,
+    pipeline: Option<Arc<Mutex<RecordPipeline>>>,
+    encoder: Arc<RecordEncoder>,
+}
+
+impl InputSink {
+    pub fn new(output: &str) -> Self {
+        Self {
+            output: output.to_string(),
+            filters: vec![],
+            pipeline: None,
+            encoder: Arc::new(RecordEncoder::new(&Container::default(), output)),
+        }
+    }
+
+    // with_pipeline processes the messages with the task config of the
+    // container, such as its redaction, before they are written in the
+    // envelope of the task
+    pub fn with_pipeline(mut self, container: &Container) -> Self {
+        self.pipeline = Some(Arc::new(Mutex::new(RecordPipeline::new(container))));
+        self.encoder = Arc::new(RecordEncoder::new(container, &self.output));
+        self
+    }
+
+    pub fn with_filter<F>(mut self, filter: F) -> Self
+    where
+        F: Filter + Send + Sync + 'static,
+    {
+        self.filters.push(Arc::new(filter));
+        self
+    }
+
+    pub fn output(&self) -> &str {
+        &self.output
+    }
+
+    pub fn emit(&self, record: &Record) {
+        if record.message.is_empty() || !self.filters.iter().all(|f| f.pass(&record.message)) {
+            return;
+        }
+        let pipeline = match &self.pipeline {
+            Some(pipeline) => pipeline,
+            None => return self.write(record, &record.message, record.fields.clone()),
+        };
+        let messages = match pipeline.lock() {
+            Ok(mut pipeline) => pipeline.process(&record.message),
+            Err(e) => {
+                eprintln!("[ERROR] input pipeline lock failed: {:?}", e);
+                return;
+            }
+        };
+        for (message, keys) in messages {
+            let mut fields = record.fields.clone();
+            fields.extend(keys);
+            self.write(record, &message, fields);
+        }
+    }
+
+    fn write(&self, record: &Record, message: &str, fields: Map<String, Value>) {
+        let encoded = self.encoder.encode(&record.custom, message, fields);
+        output_write(&self.output, &encoded);
+    }
+}
+
+pub struct Inputs {
+    input_listener: HashMap<String, Box<dyn IInput>>,
+}
+
+impl Inputs {
+    pub fn new() -> Self {
+        Self {
+            input_listener: HashMap::new(),
+        }
+    }
+
+    pub fn contains_input(&self, name: &str) -> bool {
+        self.input_listener.contains_key(name)
+    }
+
+    pub fn registry_input<T>(&mut self, name: &str, t: T)
+    where
+        T: IInput + Send + Sync + 'static,
+    {
+        if self.input_listener.contains_key(name) {
+            return;
+        }
+        self.input_listener.insert(name.to_string(), Box::new(t));
+    }
+
+    pub fn start(&mut self, name: &str, sink: InputSink) -> Result<()> {
+        match self.input_listener.get_mut(name) {
+            Some(i) => {
+                println!(
+                    "[INFO] start input {:?} to output {:?}",
+                    name,
+                    sink.output()
+                );
+                i.start(sink)
+            }
+            None => Err(format!("input not found {:?}", name).into()),
+        }
+    }
+
+    pub fn stop(&mut self, name: &str) {
+        if let Some(i) = self.input_listener.get_mut(name) {
+            i.stop();
+        }
+    }
+}
+
+impl Default for Inputs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// IInput is a source of records, the counterpart of `output::IOutput`.
+/// `start` reads in the background and writes every record to the sink
+/// until `stop` is called.
+pub trait IInput: Send + Sync + 'static {
+    fn start(&mut self, sink: InputSink) -> Result<()>;
+    fn stop(&mut self);
+}
+
+#[derive(Debug)]
+pub struct Input<T: ?Sized + IInput> {
+    i: T,
+}
+
+impl<T: IInput> Input<T> {
+    pub fn new(i: T) -> Self {
+        Self { i }
+    }
+}
+
+impl<T: IInput> IInput for Input<T> {
+    fn start(&mut self, sink: InputSink) -> Result<()> {
+        self.i.start(sink)
+    }
+
+    fn stop(&mut self) {
+        self.i.stop()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::Item;
+    use output::{IOutput, Output, OUTPUTS};
+    use serde_json::json;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Mutex;
+    use std::thread;
+    use std::time::Duration;
+
+    // GeneratorInput emits `count` numbered copies of a message
+    struct GeneratorInput {
+        message: String,
+        count: usize,
+        interval: Duration,
+        stopped: Arc<AtomicBool>,
+    }
+
+    impl GeneratorInput {
+        fn new(message: &str, count: usize, interval: Duration) -> Self {
+            Self {
+                message: message.to_string(),
+                count,
+                interval,
+                stopped: Arc::new(AtomicBool::new(false)),
+            }
+        }
+    }
+
+    impl IInput for GeneratorInput {
+        fn start(&mut self, sink: InputSink) -> Result<()> {
+            self.stopped.store(false, Ordering::SeqCst);
+            let stopped = self.stopped.clone();
+            let message = self.message.clone();
+            let count = self.count;
+            let interval = self.interval;
+            thread::spawn(move || {
+                for i in 0..count {
+                    if stopped.load(Ordering::SeqCst) {
+                        return;
+                    }
+                    sink.emit(
+                        &Record::new("generator", &format!("{} {}", message, i))
+                            .with_custom("seq", json!(i)),
+                    );
+                    thread::sleep(interval);
+                }
+            });
+            Ok(())
+        }
+
+        fn stop(&mut self) {
+            self.stopped.store(true, Ordering::SeqCst);
+        }
+    }
+
+    struct Capture(Arc<Mutex<Vec<String>>>);
+    impl IOutput for Capture {
+        fn write(&mut self, _: &str, item: Item) -> Result<()> {
+            self.0.lock().unwrap().push(item.string());
+            Ok(())
+        }
+
+        fn wait(&self, _: usize) -> bool {
+            true
+        }
+    }
+
+    struct Collector(Arc<Mutex<Vec<String>>>);
+    impl Filter for Collector {
+        fn pass(&self, message: &str) -> bool {
+            self.0.lock().unwrap().push(message.to_string());
+            !message.ends_with('1')
+        }
+    }
+
+    #[test]
+    fn it_works() {
+        let record = Record::new("stdin", "hello")
+            .with_custom("nodeName", json!("node1"))
+            .with_field("journal", json!({"PRIORITY": "6"}));
+        let encoded = RecordEncoder::new(&Container::default(), "fake_output").encode(
+            &record.custom,
+            &record.message,
+            record.fields.clone(),
+        );
+        let encoded = serde_json::from_str::<Value>(&encoded).unwrap();
+        assert_eq!(encoded["custom"]["source"], "stdin");
+        assert_eq!(encoded["custom"]["version"], RECORD_VERSION);
+        assert_eq!(encoded["custom"]["nodeName"], "node1");
+        assert_eq!(encoded["journal"]["PRIORITY"], "6");
+        assert_eq!(encoded["message"], "hello");
+    }
+
+    #[test]
+    fn envelope_it_works() {
+        let written = Arc::new(Mutex::new(vec![]));
+        OUTPUTS
+            .write()
+            .unwrap()
+            .registry_output("capture_envelope", Output::new(Capture(written.clone())));
+        file::set_output_envelopes("capture_envelope=flat").unwrap();
+        let record = Record::new("journald", "[ERROR] disk full")
+            .with_custom("nodeName", json!("node1"))
+            .with_field("journal", json!({"PRIORITY": "3"}));
+        InputSink::new("capture_envelope").emit(&record);
+
+        // the schema of the task wins over the schema of the output
+        let container = Container {
+            envelope: db::Envelope {
+                schema: Some(db::EnvelopeSchema::Otel),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        InputSink::new("capture_envelope")
+            .with_pipeline(&container)
+            .emit(&record);
+
+        let written = written.lock().unwrap();
+        let flat = serde_json::from_str::<Value>(&written[0]).unwrap();
+        assert!(flat.get("custom").is_none());
+        assert_eq!(flat["nodeName"], "node1");
+        assert_eq!(flat["journal"]["PRIORITY"], "3");
+        assert_eq!(flat["message"], "[ERROR] disk full");
+        let otel = serde_json::from_str::<Value>(&written[1]).unwrap();
+        assert_eq!(otel["Body"], "[ERROR] disk full");
+        assert_eq!(otel["SeverityText"], "ERROR");
+        assert_eq!(otel["Attributes"]["journal"]["PRIORITY"], "3");
+
+        // an output without a schema keeps the legacy `custom` block
+        let legacy = Arc::new(Mutex::new(vec![]));
+        OUTPUTS
+            .write()
+            .unwrap()
+            .registry_output("capture_legacy", Output::new(Capture(legacy.clone())));
+        InputSink::new("capture_legacy").emit(&record);
+        let legacy = legacy.lock().unwrap();
+        assert_eq!(legacy.len(), 1);
+        let legacy = serde_json::from_str::<Value>(&legacy[0]).unwrap();
+        assert_eq!(legacy["custom"]["source"], "journald");
+        assert_eq!(legacy["custom"]["nodeName"], "node1");
+        assert_eq!(legacy["custom"]["version"], RECORD_VERSION);
+        assert_eq!(legacy["journal"]["PRIORITY"], "3");
+        assert_eq!(legacy["message"], "[ERROR] disk full");
+    }
+
+    #[test]
+    fn it_works_with_inputs() {
+        let seen = Arc::new(Mutex::new(vec![]));
+        registry_input(
+            "generator",
+            GeneratorInput::new("line", 3, Duration::from_millis(1)),
+        );
+        assert!(INPUTS.read().unwrap().contains_input("generator"));
+
+        let sink = InputSink::new("fake_output").with_filter(Collector(seen.clone()));
+        input_start("generator", sink);
+        for _ in 0..100 {
+            if seen.lock().unwrap().len() == 3 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![
+                "line 0".to_string(),
+                "line 1".to_string(),
+                "line 2".to_string()
+            ]
+        );
+        input_stop_all();
+
+        assert!(INPUTS
+            .write()
+            .unwrap()
+            .start("unknown", InputSink::new("fake_output"))
+            .is_err());
+    }
+}
@@ -1,8 +1,12 @@
-use std::{collections::HashMap, fs::File, io::BufReader};
+use common::Result;
+use db::Mount;
+use std::{collections::HashMap, fs};
 
 const NAMESPACE_LABEL_NAME: &'static str = "io.kubernetes.pod.namespace";
 const PODNAME_LABEL_NAME: &'static str = "io.kubernetes.pod.name";
 const CONTAINERNAME_LABEL_NAME: &'static str = "io.kubernetes.container.name";
+const PODUID_LABEL_NAME: &str = "io.kubernetes.pod.uid";
+const RESTARTCOUNT_LABEL_NAME: &str = "io.kubernetes.container.restartCount";
 const SANBOX_POD_NAME: &'static str = "POD";
 
 #[derive(Default, Debug, Clone, PartialEq, serde_derive::Serialize, serde_derive::Deserialize)]
@@ -22,21 +26,25 @@ pub struct JSONConfig {
     pub log_path: String,
     #[serde(rename = "Name")]
     pub name: String,
+    #[serde(rename = "RestartCount", default)]
+    pub restart_count: i64,
+    #[serde(rename = "Driver", default)]
+    pub driver: String,
+    #[serde(rename = "MountPoints", default)]
+    pub mount_points: HashMap<String, MountPoint>,
 }
 
-impl From<&str> for JSONConfig {
-    fn from(path: &str) -> Self {
-        serde_json::from_reader::<_, JSONConfig>(BufReader::new(File::open(path).unwrap())).unwrap()
+impl JSONConfig {
+    // docker may be in the middle of rewriting the config, so parse errors are
+    // returned to the caller instead of panicking
+    pub fn from_path(path: &str) -> Result<Self> {
+        Self::from_slice(&fs::read(path)?)
     }
-}
 
-impl From<&[u8]> for JSONConfig {
-    fn from(bytes: &[u8]) -> Self {
-        serde_json::from_reader::<_, JSONConfig>(BufReader::new(bytes)).unwrap()
+    pub fn from_slice(bytes: &[u8]) -> Result<Self> {
+        Ok(serde_json::from_slice::<JSONConfig>(bytes)?)
     }
-}
 
-impl JSONConfig {
     pub fn get_ns(&self) -> String {
         if let Some(label) = self.config.labels.get(NAMESPACE_LABEL_NAME) {
             return label.to_string();
@@ -51,6 +59,33 @@ impl JSONConfig {
         "".to_string()
     }
 
+    pub fn get_pod_uid(&self) -> String {
+        if let Some(label) = self.config.labels.get(PODUID_LABEL_NAME) {
+            return label.to_string();
+        }
+        "".to_string()
+    }
+
+    // kubelet restarts a container by creating a new one, the count lives in a label
+    pub fn get_restart_count(&self) -> i64 {
+        let label_count = match self.config.labels.get(RESTARTCOUNT_LABEL_NAME) {
+            Some(label) => label.parse::<i64>().unwrap_or(0),
+            None => 0,
+        };
+        std::cmp::max(label_count, self.restart_count)
+    }
+
+    pub fn get_mounts(&self) -> Vec<Mount> {
+        self.mount_points
+            .values()
+            .filter(|mount_point| !mount_point.source.is_empty())
+            .map(|mount_point| Mount {
+                source: mount_point.source.clone(),
+                destination: mount_point.destination.clone(),
+            })
+            .collect()
+    }
+
     pub fn is_sanbox_pod(&self) -> bool {
         if self.get_container_name() == SANBOX_POD_NAME {
             return true;
@@ -83,12 +118,31 @@ pub struct State {
     pub dead: bool,
     #[serde(rename = "Pid")]
     pub pid: i64,
+    #[serde(rename = "ExitCode", default)]
+    pub exit_code: i64,
     #[serde(rename = "StartedAt")]
     pub started_at: String,
     #[serde(rename = "FinishedAt")]
     pub finished_at: String,
 }
 
+#[derive(Default, Debug, Clone, PartialEq, serde_derive::Serialize, serde_derive::Deserialize)]
+#[serde(default)]
+pub struct MountPoint {
+    #[serde(rename = "Source")]
+    pub source: String,
+    #[serde(rename = "Destination")]
+    pub destination: String,
+    #[serde(rename = "RW")]
+    pub rw: bool,
+    #[serde(rename = "Name")]
+    pub name: String,
+    #[serde(rename = "Driver")]
+    pub driver: String,
+    #[serde(rename = "Type")]
+    pub mount_type: String,
+}
+
 #[derive(Default, Debug, Clone, PartialEq, serde_derive::Serialize, serde_derive::Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Config {
@@ -212,10 +266,26 @@ mod tests {
             \"NoNewPrivileges\": false
         }";
 
-        let _j_s_o_n_config = JSONConfig::from(TEST_STRING.as_bytes());
+        let _j_s_o_n_config = JSONConfig::from_slice(TEST_STRING.as_bytes()).unwrap();
 
         assert_eq!(_j_s_o_n_config.get_ns(), "finance-dev");
         assert_eq!(_j_s_o_n_config.get_pod_name(), "sky-fcms-web-ui-0-b-0");
+        assert_eq!(
+            _j_s_o_n_config.get_pod_uid(),
+            "c7621e69-de2b-4a5c-b439-6e3021dba432"
+        );
         assert_eq!(_j_s_o_n_config.log_path, "/data/docker/containers/58044a726890a4cbdd054a75cfa70b7e776d73f04925685aa827f162cc9026bd/58044a726890a4cbdd054a75cfa70b7e776d73f04925685aa827f162cc9026bd-json.log");
     }
+
+    #[test]
+    fn truncated_and_malformed_it_works() {
+        let truncated =
+            r#"{"State": {"Running": true}, "ID": "58044a726890", "Config": {"Labels": {"#;
+        assert!(JSONConfig::from_slice(truncated.as_bytes()).is_err());
+
+        let malformed = r#"{"State": "running", "ID": 1}"#;
+        assert!(JSONConfig::from_slice(malformed.as_bytes()).is_err());
+
+        assert!(JSONConfig::from_path("/not/exist/config.v2.json").is_err());
+    }
 }
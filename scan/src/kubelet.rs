@@ -0,0 +1,340 @@
+use common::Result;
+use db::Container;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, Read, Write};
+use std::net::TcpStream;
+use std::sync::RwLock;
+use std::thread;
+use std::time::Duration;
+
+const OUTPUT_ANNOTATION: &str = "harvest.io/output";
+const FILTER_ANNOTATION: &str = "harvest.io/filter";
+const APP_NAME_LABEL: &str = "app.kubernetes.io/name";
+const APP_LABEL: &str = "app";
+
+lazy_static! {
+    // pod uid -> pod spec
+    static ref PODS: RwLock<HashMap<String, Pod>> = RwLock::new(HashMap::new());
+}
+
+#[derive(Default, Debug, Clone, PartialEq, serde_derive::Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct PodList {
+    pub items: Vec<Pod>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, serde_derive::Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Pod {
+    pub metadata: ObjectMeta,
+    pub status: PodStatus,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, serde_derive::Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct ObjectMeta {
+    pub name: String,
+    pub namespace: String,
+    pub uid: String,
+    pub labels: HashMap<String, String>,
+    pub annotations: HashMap<String, String>,
+    pub owner_references: Vec<OwnerReference>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, serde_derive::Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct OwnerReference {
+    pub kind: String,
+    pub name: String,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, serde_derive::Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct PodStatus {
+    #[serde(rename = "podIP")]
+    pub pod_ip: String,
+    #[serde(rename = "podIPs")]
+    pub pod_ips: Vec<PodIP>,
+    pub container_statuses: Vec<ContainerStatus>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, serde_derive::Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct ContainerStatus {
+    pub name: String,
+    // the pulled image, e.g. docker-pullable://nginx@sha256:4cf6...
+    #[serde(rename = "imageID")]
+    pub image_id: String,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, serde_derive::Deserialize)]
+#[serde(default)]
+pub struct PodIP {
+    pub ip: String,
+}
+
+impl Pod {
+    pub fn ips(&self) -> Vec<String> {
+        if !self.status.pod_ips.is_empty() {
+            return self.status.pod_ips.iter().map(|ip| ip.ip.clone()).collect();
+        }
+        if self.status.pod_ip.is_empty() {
+            return vec![];
+        }
+        vec![self.status.pod_ip.clone()]
+    }
+
+    pub fn service_name(&self) -> String {
+        let labels = &self.metadata.labels;
+        if let Some(name) = labels.get(APP_NAME_LABEL).or_else(|| labels.get(APP_LABEL)) {
+            return name.to_string();
+        }
+        match self.metadata.owner_references.first() {
+            Some(owner) => owner.name.clone(),
+            None => "".to_string(),
+        }
+    }
+
+    /// image_digest returns the registry digest of the image of a container,
+    /// empty until the kubelet reports its status.
+    pub fn image_digest(&self, container: &str) -> String {
+        self.status
+            .container_statuses
+            .iter()
+            .find(|status| status.name == container)
+            .and_then(|status| status.image_id.rsplit_once('@'))
+            .map(|(_, digest)| digest.to_string())
+            .unwrap_or_default()
+    }
+
+    pub fn owner(&self) -> String {
+        match self.metadata.owner_references.first() {
+            Some(owner) => format!("{}/{}", owner.kind, owner.name),
+            None => "".to_string(),
+        }
+    }
+}
+
+/// PodMetaSource is where pod specs are read from, the kubelet read-only
+/// `/pods` endpoint or a local file holding the same PodList json.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PodMetaSource {
+    Kubelet(String),
+    File(String),
+}
+
+impl From<&str> for PodMetaSource {
+    fn from(source: &str) -> Self {
+        if source.starts_with("http://") {
+            return PodMetaSource::Kubelet(source.to_string());
+        }
+        PodMetaSource::File(source.to_string())
+    }
+}
+
+impl PodMetaSource {
+    pub fn fetch(&self) -> Result<PodList> {
+        match self {
+            PodMetaSource::Kubelet(url) => Ok(serde_json::from_str::<PodList>(&http_get(url)?)?),
+            PodMetaSource::File(path) => Ok(serde_json::from_reader::<_, PodList>(
+                BufReader::new(File::open(path)?),
+            )?),
+        }
+    }
+}
+
+// http_get issues a HTTP/1.0 request so the kubelet answers without chunked encoding
+fn http_get(url: &str) -> Result<String> {
+    let address = url.trim_start_matches("http://");
+    let (host, path) = match address.find('/') {
+        Some(index) => (&address[..index], &address[index..]),
+        None => (address, "/"),
+    };
+
+    let mut stream = TcpStream::connect(host)?;
+    stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+    write!(
+        stream,
+        "GET {} HTTP/1.0\r\nHost: {}\r\nAccept: application/json\r\n\r\n",
+        path, host
+    )?;
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+
+    let (head, body) = match response.find("\r\n\r\n") {
+        Some(index) => (&response[..index], &response[index + 4..]),
+        None => return Err(format!("invalid http response from {}", url).into()),
+    };
+    if !head.lines().next().unwrap_or("").contains(" 200") {
+        return Err(format!("http get {} failed: {}", url, head).into());
+    }
+    Ok(body.to_string())
+}
+
+/// refresh_pod_meta replaces the pod cache with the pods of the source, the
+/// containers already known of new or changed pods get their metadata.
+pub fn refresh_pod_meta(source: &PodMetaSource) -> Result<usize> {
+    let pods = source.fetch()?;
+    let count = pods.items.len();
+    let changed = match PODS.write() {
+        Ok(mut cache) => {
+            let changed = pods
+                .items
+                .iter()
+                .filter(|pod| cache.get(&pod.metadata.uid) != Some(pod))
+                .map(|pod| pod.metadata.uid.clone())
+                .collect::<Vec<String>>();
+            cache.clear();
+            for pod in pods.items {
+                cache.insert(pod.metadata.uid.clone(), pod);
+            }
+            changed
+        }
+        Err(e) => return Err(format!("pod meta cache write failed: {:?}", e).into()),
+    };
+    // containers are usually created before the next poll sees their pod
+    for uid in changed.iter().filter(|uid| !uid.is_empty()) {
+        for (_, mut container) in db::get_container_slice_by_pod_uid(uid) {
+            apply_pod_meta(&mut container);
+            db::update_pod_meta(&container);
+        }
+    }
+    Ok(count)
+}
+
+/// start_pod_meta_provider polls the source in the background.
+pub fn start_pod_meta_provider(source: PodMetaSource, interval: Duration) {
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+        if let Err(e) = refresh_pod_meta(&source) {
+            eprintln!("[ERROR] refresh pod meta from {:?} error: {:?}", source, e);
+        }
+    });
+}
+
+pub fn get_pod_meta(uid: &str) -> Option<Pod> {
+    match PODS.read() {
+        Ok(cache) => cache.get(uid).cloned(),
+        Err(e) => {
+            eprintln!("[ERROR] pod meta cache read failed: {:?}", e);
+            None
+        }
+    }
+}
+
+/// apply_pod_meta fills the container with the cached pod spec, pods annotated
+/// with `harvest.io/output` are collected without a task.
+pub fn apply_pod_meta(container: &mut Container) {
+    let pod = match get_pod_meta(&container.pod_uid) {
+        Some(pod) => pod,
+        None => return,
+    };
+
+    container.ips = pod.ips();
+    if container.service_name.is_empty() {
+        container.service_name = pod.service_name();
+    }
+    for (k, v) in pod.metadata.labels.iter() {
+        container
+            .labels
+            .entry(k.clone())
+            .or_insert_with(|| v.clone());
+    }
+    container.annotations = pod.metadata.annotations.clone();
+    container.owner = pod.owner();
+    let image_digest = pod.image_digest(&container.container);
+    if !image_digest.is_empty() {
+        container.image_digest = image_digest;
+    }
+
+    if let Some(output) = pod.metadata.annotations.get(OUTPUT_ANNOTATION) {
+        container.output = output.clone();
+        container.upload();
+    }
+    if let Some(expr) = pod.metadata.annotations.get(FILTER_ANNOTATION) {
+        container.filter.expr = expr.clone();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{apply_pod_meta, refresh_pod_meta, PodMetaSource};
+    use db::Container;
+    use std::fs;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn it_works() {
+        // the container is known before its pod
+        let known = Container {
+            path: "/var/lib/docker/containers/harvest_kubelet_test/0-json.log".to_string(),
+            pod_uid: "c7621e69-de2b-4a5c-b439-6e3021dba432".to_string(),
+            container: "nginx".to_string(),
+            ..Default::default()
+        };
+        db::insert(&known);
+        while db::get(&known.path).is_none() {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let pods = r#"{
+            "kind": "PodList",
+            "items": [{
+                "metadata": {
+                    "name": "web-7d9f-abc",
+                    "namespace": "default",
+                    "uid": "c7621e69-de2b-4a5c-b439-6e3021dba432",
+                    "labels": {"app.kubernetes.io/name": "web"},
+                    "annotations": {"harvest.io/output": "fake_output", "harvest.io/filter": "level == \"error\""},
+                    "ownerReferences": [{"kind": "ReplicaSet", "name": "web-7d9f"}]
+                },
+                "status": {
+                    "podIP": "10.1.0.5",
+                    "podIPs": [{"ip": "10.1.0.5"}],
+                    "containerStatuses": [{"name": "nginx", "imageID": "docker-pullable://nginx@sha256:4cf620a5c813"}]
+                }
+            }]
+        }"#;
+        let path = std::env::temp_dir().join("harvest_kubelet_pods.json");
+        fs::write(&path, pods).unwrap();
+
+        let source = PodMetaSource::from(path.to_str().unwrap());
+        assert_eq!(refresh_pod_meta(&source).unwrap(), 1);
+
+        let mut container = Container {
+            pod_uid: "c7621e69-de2b-4a5c-b439-6e3021dba432".to_string(),
+            container: "nginx".to_string(),
+            ..Default::default()
+        };
+        apply_pod_meta(&mut container);
+        assert_eq!(container.ips, vec!["10.1.0.5".to_string()]);
+        assert_eq!(container.service_name, "web");
+        assert_eq!(container.owner, "ReplicaSet/web-7d9f");
+        assert_eq!(container.image_digest, "sha256:4cf620a5c813");
+        assert_eq!(container.output, "fake_output");
+        assert!(container.is_upload());
+        assert_eq!(container.filter.expr, "level == \"error\"");
+
+        let mut known = db::get(&known.path).unwrap();
+        for _ in 0..100 {
+            if known.is_upload() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+            known = db::get(&known.path).unwrap();
+        }
+        assert_eq!(known.ips, vec!["10.1.0.5".to_string()]);
+        assert_eq!(known.owner, "ReplicaSet/web-7d9f");
+        assert_eq!(known.output, "fake_output");
+        assert!(known.is_upload() && known.is_running());
+
+        assert_eq!(
+            PodMetaSource::from("http://127.0.0.1:10255/pods"),
+            PodMetaSource::Kubelet("http://127.0.0.1:10255/pods".to_string())
+        );
+        let _ = fs::remove_file(path);
+    }
+}
@@ -1,15 +1,36 @@
+#[macro_use]
+extern crate lazy_static;
 use common::Result;
-use db::Container;
+use db::{Container, Mount};
 use event::{Dispatch, Listener};
 use notify::{raw_watcher, RawEvent, RecursiveMode, Watcher};
-use std::collections::{hash_map::DefaultHasher, HashMap};
+use std::collections::{hash_map::DefaultHasher, HashMap, HashSet};
+use std::fs;
 use std::hash::{Hash, Hasher};
-use std::sync::mpsc::channel;
+use std::path::Path;
+use std::sync::mpsc::{channel, RecvTimeoutError};
 use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
 use strum::AsRefStr;
 use walkdir::WalkDir;
 mod config_v2;
+mod kubelet;
+mod lifecycle;
+mod mounts;
+mod namespace;
+mod poll;
+mod quarantine;
 use config_v2::JSONConfig;
+pub use kubelet::{
+    apply_pod_meta, get_pod_meta, refresh_pod_meta, start_pod_meta_provider, Pod, PodMetaSource,
+};
+pub use lifecycle::ContainerStateEvent;
+pub use mounts::{docker_root, merged_dir, resolve_paths};
+pub use namespace::Namespaces;
+pub use poll::{Poller, WatchBackend};
+use quarantine::{quarantine, release};
+pub use quarantine::{quarantined, QuarantinedConfig};
 
 #[derive(Debug, AsRefStr, Clone)]
 pub enum PathEvent {
@@ -19,6 +40,8 @@ pub enum PathEvent {
     Remove,
     #[strum(serialize = "write")]
     Write,
+    #[strum(serialize = "state")]
+    State,
 }
 
 pub trait GetPathEventInfo {
@@ -37,6 +60,13 @@ pub struct PathEventInfo {
     pub container_name: String,
     pub path: String,
     pub ips: Vec<String>,
+    pub labels: HashMap<String, String>,
+    pub image: String,
+    pub image_id: String,
+    pub container_id: String,
+    pub pod_uid: String,
+    pub mounts: Vec<Mount>,
+    pub state_event: Option<ContainerStateEvent>,
 }
 
 impl Default for PathEventInfo {
@@ -48,6 +78,13 @@ impl Default for PathEventInfo {
             container_name: "".to_string(),
             path: "".to_string(),
             ips: vec![],
+            labels: HashMap::new(),
+            image: "".to_string(),
+            image_id: "".to_string(),
+            container_id: "".to_string(),
+            pod_uid: "".to_string(),
+            mounts: vec![],
+            state_event: None,
         }
     }
 }
@@ -66,14 +103,22 @@ impl GetDebug for PathEventInfo {
 
 impl PathEventInfo {
     pub fn to_pod(&self) -> Container {
-        Container {
+        let mut container = Container {
             service_name: self.service_name.clone(),
             ns: self.ns.clone(),
             pod_name: self.pod_name.clone(),
             container: self.container_name.clone(),
             path: self.path.clone(),
+            labels: self.labels.clone(),
+            image: self.image.clone(),
+            image_id: self.image_id.clone(),
+            container_id: self.container_id.clone(),
+            pod_uid: self.pod_uid.clone(),
+            mounts: self.mounts.clone(),
             ..Default::default()
-        }
+        };
+        apply_pod_meta(&mut container);
+        container
     }
 }
 
@@ -97,25 +142,39 @@ fn docker_config_file_type(path: &str) -> DockerConfigFileType {
 
 type Cache = Arc<Vec<RwLock<HashMap<String, Option<JSONConfig>>>>>;
 
+type IsOpen = Box<dyn Fn(&str) -> bool + Send + Sync>;
+
+type OnFallback = Box<dyn Fn(Duration) + Send + Sync>;
+
 pub struct AutoScanner {
-    namespace: String,
+    namespaces: Namespaces,
     docker_dir: String,
     event_dispatch: Dispatch<PathEventInfo>,
     cache: Cache,
+    reconcile_interval: Option<Duration>,
+    is_open: Option<IsOpen>,
+    watch_backend: WatchBackend,
+    poll_interval: Duration,
+    on_poll_fallback: Option<OnFallback>,
 }
 
 impl AutoScanner {
-    pub fn new(namespace: String, docker_dir: String) -> Self {
+    pub fn new(namespaces: Namespaces, docker_dir: String) -> Self {
         let len = 2;
         let mut cache: Vec<RwLock<HashMap<String, Option<JSONConfig>>>> = Vec::with_capacity(len);
         for _i in 0..len {
             cache.push(RwLock::new(HashMap::new()))
         }
         Self {
-            namespace,
+            namespaces,
             docker_dir,
             event_dispatch: Dispatch::<PathEventInfo>::new(),
             cache: Arc::new(cache),
+            reconcile_interval: None,
+            is_open: None,
+            watch_backend: WatchBackend::Auto,
+            poll_interval: Duration::from_secs(1),
+            on_poll_fallback: None,
         }
     }
 
@@ -138,8 +197,13 @@ impl AutoScanner {
         }
     }
 
+    // accepts tells whether a config is collected, the cache only holds them
+    fn accepts(&self, v: &JSONConfig) -> bool {
+        self.namespaces.contains(&v.get_ns()) && !v.is_sanbox_pod()
+    }
+
     fn insert(&self, k: &str, v: JSONConfig) {
-        if v.get_ns() == "" || self.namespace != v.get_ns() || v.is_sanbox_pod() {
+        if !self.accepts(&v) {
             return;
         }
 
@@ -205,6 +269,13 @@ impl AutoScanner {
         self.event_dispatch.registry(PathEvent::Create.as_ref(), l)
     }
 
+    pub fn append_state_event_handle<L>(&mut self, l: L)
+    where
+        L: Listener<PathEventInfo> + Send + Sync + 'static,
+    {
+        self.event_dispatch.registry(PathEvent::State.as_ref(), l)
+    }
+
     fn dispatch_create_event(&mut self, pei: &PathEventInfo) {
         self.event_dispatch
             .dispatch(PathEvent::Create.as_ref(), pei)
@@ -214,36 +285,97 @@ impl AutoScanner {
         self.event_dispatch.dispatch(PathEvent::Write.as_ref(), pei)
     }
 
+    fn dispatch_state_event(&mut self, pei: &PathEventInfo) {
+        self.event_dispatch.dispatch(PathEvent::State.as_ref(), pei)
+    }
+
     fn dispatch_close_event(&mut self, pei: &PathEventInfo) {
         self.event_dispatch
             .dispatch(PathEvent::Remove.as_ref(), pei)
     }
 
-    fn config_to_pei(
-        ns: &str,
-        pod_name: &str,
-        container_name: &str,
-        log_path: &str,
-    ) -> PathEventInfo {
+    fn config_to_pei(cfg: &JSONConfig) -> PathEventInfo {
         PathEventInfo {
-            ns: ns.to_string(),
-            pod_name: pod_name.to_string(),
-            container_name: container_name.to_string(),
-            path: log_path.to_string(),
+            ns: cfg.get_ns(),
+            pod_name: cfg.get_pod_name(),
+            container_name: cfg.get_container_name(),
+            path: cfg.log_path.clone(),
+            labels: cfg.config.labels.clone(),
+            image: cfg.config.image.clone(),
+            image_id: cfg.image.clone(),
+            container_id: cfg.id.clone(),
+            pod_uid: cfg.get_pod_uid(),
+            mounts: cfg.get_mounts(),
             ..Default::default()
         }
     }
 
+    // load_config_file parses a config, an unparsable config is quarantined
+    // until a later write makes it valid
+    fn load_config_file(path: &str) -> Option<JSONConfig> {
+        match JSONConfig::from_path(path) {
+            Ok(_j_s_o_n_config) => {
+                release(path);
+                Some(_j_s_o_n_config)
+            }
+            Err(e) => {
+                quarantine(path, &e.to_string());
+                None
+            }
+        }
+    }
+
     fn insert_config_file(&self, path: &str) {
-        let _j_s_o_n_config = JSONConfig::from(path);
-        self.insert(&_j_s_o_n_config.log_path.clone(), _j_s_o_n_config);
+        if let Some(_j_s_o_n_config) = Self::load_config_file(path) {
+            self.insert(&_j_s_o_n_config.log_path.clone(), _j_s_o_n_config);
+        }
+    }
+
+    // remove_config_file forgets a deleted config, a quarantined one included
+    fn remove_config_file(&self, path: &str) {
+        release(path);
+        self.remove(path);
+    }
+
+    // update_config_file reloads a rewritten config and emits its state changes
+    fn update_config_file(&mut self, path: &str) {
+        if let Some(_j_s_o_n_config) = Self::load_config_file(path) {
+            self.update_config(_j_s_o_n_config)
+        }
+    }
+
+    fn update_config(&mut self, _j_s_o_n_config: JSONConfig) {
+        let log_path = _j_s_o_n_config.log_path.clone();
+        let previous = self.get(&log_path);
+        self.insert(&log_path, _j_s_o_n_config.clone());
+        if self.get(&log_path).is_none() {
+            return;
+        }
+
+        for event in lifecycle::diff_state(previous.as_ref(), &_j_s_o_n_config) {
+            let mut pei = Self::config_to_pei(&_j_s_o_n_config);
+            pei.state_event = Some(event);
+            self.dispatch_state_event(&pei);
+        }
     }
 
     pub fn prepare(&self) -> Result<Vec<PathEventInfo>> {
         let mut result = vec![];
         for entry in WalkDir::new(self.docker_dir.clone()) {
-            let entry = entry?;
-            let path = entry.path().to_str().unwrap();
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    eprintln!("[ERROR] prepare walk {:?} error: {:?}", self.docker_dir, e);
+                    continue;
+                }
+            };
+            let path = match entry.path().to_str() {
+                Some(path) => path,
+                None => {
+                    eprintln!("[ERROR] prepare skip non utf8 path {:?}", entry.path());
+                    continue;
+                }
+            };
             match docker_config_file_type(path) {
                 DockerConfigFileType::ConfigV2 => self.insert_config_file(path),
                 DockerConfigFileType::Log => self.insert_key(path),
@@ -256,17 +388,12 @@ impl AutoScanner {
                 Ok(hm) => {
                     for (_, v) in hm.iter() {
                         if let Some(_j_s_o_n_config) = v {
-                            result.push(Self::config_to_pei(
-                                &_j_s_o_n_config.get_ns(),
-                                &_j_s_o_n_config.get_pod_name(),
-                                &_j_s_o_n_config.get_container_name(),
-                                &_j_s_o_n_config.log_path,
-                            ));
+                            result.push(Self::config_to_pei(_j_s_o_n_config));
                         }
                     }
                 }
                 Err(e) => {
-                    panic!("error occurred prepare {:?}", e);
+                    return Err(format!("error occurred prepare {:?}", e).into());
                 }
             }
         }
@@ -274,88 +401,297 @@ impl AutoScanner {
         Ok(result)
     }
 
-    pub fn watch_start(&mut self) -> Result<()> {
-        let (tx, rx) = channel();
-        let mut watcher = raw_watcher(tx).unwrap();
-
-        watcher.watch(&self.docker_dir, RecursiveMode::Recursive)?;
-        while let Ok(RawEvent {
-            path: Some(path),
-            op: Ok(op),
-            cookie,
-        }) = rx.recv()
-        {
-            let path = path.to_str().unwrap();
-            match op {
-                notify::Op::CREATE => match docker_config_file_type(path) {
-                    DockerConfigFileType::ConfigV2 => self.insert(path, JSONConfig::from(path)),
-                    DockerConfigFileType::Log => match self.get(path) {
-                        Some(cfg) => {
-                            let pei = Self::config_to_pei(
-                                &cfg.get_ns(),
-                                &cfg.get_pod_name(),
-                                &cfg.get_container_name(),
-                                &cfg.log_path,
-                            );
-                            self.dispatch_create_event(&pei)
+    pub fn set_watch_backend(&mut self, backend: WatchBackend, poll_interval: Duration) {
+        self.watch_backend = backend;
+        self.poll_interval = poll_interval;
+    }
+
+    /// set_poll_fallback registers `f`, called with the poll interval when the
+    /// `Auto` backend falls back to polling.
+    pub fn set_poll_fallback<F>(&mut self, f: F)
+    where
+        F: Fn(Duration) + Send + Sync + 'static,
+    {
+        self.on_poll_fallback = Some(Box::new(f));
+    }
+
+    /// set_reconcile enables a periodic rescan of the docker dir, `is_open`
+    /// tells whether a file reader is open for a log path.
+    pub fn set_reconcile<F>(&mut self, interval: Duration, is_open: F)
+    where
+        F: Fn(&str) -> bool + Send + Sync + 'static,
+    {
+        self.reconcile_interval = Some(interval);
+        self.is_open = Some(Box::new(is_open));
+    }
+
+    fn log_drift(&self, path: &str) -> Option<PathEvent> {
+        let container = match db::get(path) {
+            Some(container) => container,
+            None => return Some(PathEvent::Create),
+        };
+        let is_open = (self.is_open.as_ref()?)(path);
+        if container.is_upload() && !is_open {
+            return Some(PathEvent::Create);
+        }
+        let size = match fs::metadata(path) {
+            Ok(metadata) => metadata.len() as i64,
+            Err(_) => return None,
+        };
+        if is_open && size > container.offset {
+            return Some(PathEvent::Write);
+        }
+        None
+    }
+
+    /// reconcile re-walks the docker dir, compares it with the db and the open
+    /// readers, then dispatches the events the watcher missed. It returns the
+    /// number of corrections.
+    pub fn reconcile(&mut self) -> usize {
+        let mut corrections = 0;
+        let mut logs = HashSet::new();
+        for entry in WalkDir::new(self.docker_dir.clone()) {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(_) => continue,
+            };
+            let path = match entry.path().to_str() {
+                Some(path) => path.to_string(),
+                None => continue,
+            };
+            match docker_config_file_type(&path) {
+                DockerConfigFileType::ConfigV2 => {
+                    let _j_s_o_n_config = match Self::load_config_file(&path) {
+                        Some(_j_s_o_n_config) => _j_s_o_n_config,
+                        None => continue,
+                    };
+                    if self.accepts(&_j_s_o_n_config)
+                        && self.get(&_j_s_o_n_config.log_path).as_ref() != Some(&_j_s_o_n_config)
+                    {
+                        self.update_config(_j_s_o_n_config);
+                        corrections += 1;
+                    }
+                }
+                DockerConfigFileType::Log => {
+                    logs.insert(path);
+                }
+                _ => {}
+            }
+        }
+
+        for path in logs.iter() {
+            let cfg = match self.get(path) {
+                Some(cfg) => cfg,
+                None => continue,
+            };
+            match self.log_drift(path) {
+                Some(PathEvent::Create) => {
+                    let pei = Self::config_to_pei(&cfg);
+                    self.dispatch_create_event(&pei);
+                    self.dispatch_write_event(&pei);
+                    corrections += 1;
+                }
+                Some(PathEvent::Write) => {
+                    self.dispatch_write_event(&Self::config_to_pei(&cfg));
+                    corrections += 1;
+                }
+                _ => {}
+            }
+        }
+
+        for container in db::all_to_json().0 {
+            // files inside containers are closed with their container
+            if !container.source.is_empty()
+                || !container.path.starts_with(&self.docker_dir)
+                || logs.contains(&container.path)
+            {
+                continue;
+            }
+            self.dispatch_close_event(&PathEventInfo {
+                path: container.path.clone(),
+                ..Default::default()
+            });
+            corrections += 1;
+        }
+
+        if corrections > 0 {
+            println!(
+                "[INFO] reconcile {:?} corrected {:?} drift(s)",
+                self.docker_dir, corrections
+            );
+        }
+        corrections
+    }
+
+    fn handle_raw_event(&mut self, path: &str, op: notify::Op, cookie: Option<u32>) {
+        match op {
+            notify::Op::CREATE => match docker_config_file_type(path) {
+                DockerConfigFileType::ConfigV2 => self.update_config_file(path),
+                DockerConfigFileType::Log => {
+                    if let Some(cfg) = self.get(path) {
+                        let pei = Self::config_to_pei(&cfg);
+                        self.dispatch_create_event(&pei)
+                    }
+                }
+                _ => {}
+            },
+            notify::Op::WRITE => match docker_config_file_type(path) {
+                DockerConfigFileType::ConfigV2 => self.update_config_file(path),
+                DockerConfigFileType::Log => self.dispatch_write_event(&PathEventInfo {
+                    path: path.to_string(),
+                    ..Default::default()
+                }),
+                _ => {}
+            },
+            notify::Op::REMOVE => match docker_config_file_type(path) {
+                DockerConfigFileType::ConfigV2 => self.remove_config_file(path),
+                DockerConfigFileType::Log => self.dispatch_write_event(&PathEventInfo {
+                    path: path.to_string(),
+                    ..Default::default()
+                }),
+                _ => {}
+            },
+            _ => {
+                if op == notify::Op::CREATE | notify::Op::WRITE {
+                    match docker_config_file_type(path) {
+                        DockerConfigFileType::ConfigV2 => self.update_config_file(path),
+                        DockerConfigFileType::Log => {
+                            if let Some(cfg) = self.get(path) {
+                                let pei = Self::config_to_pei(&cfg);
+                                self.dispatch_create_event(&pei);
+                                self.dispatch_write_event(&pei)
+                            }
                         }
                         _ => {}
-                    },
-                    _ => continue,
-                },
-                notify::Op::WRITE => match docker_config_file_type(path) {
-                    DockerConfigFileType::ConfigV2 => self.insert(path, JSONConfig::from(path)),
-                    DockerConfigFileType::Log => self.dispatch_write_event(&PathEventInfo {
-                        path: path.to_string(),
-                        ..Default::default()
-                    }),
-                    _ => continue,
-                },
-                notify::Op::REMOVE => match docker_config_file_type(path) {
-                    DockerConfigFileType::ConfigV2 => self.remove(path),
-                    DockerConfigFileType::Log => self.dispatch_write_event(&PathEventInfo {
+                    }
+                    return;
+                }
+
+                match docker_config_file_type(path) {
+                    // docker rewrites the config with a rename of a temp file
+                    DockerConfigFileType::ConfigV2 if Path::new(path).exists() => {
+                        self.update_config_file(path)
+                    }
+                    DockerConfigFileType::ConfigV2 => {
+                        self.remove_config_file(path);
+                    }
+                    DockerConfigFileType::Log => self.dispatch_close_event(&PathEventInfo {
                         path: path.to_string(),
                         ..Default::default()
                     }),
-                    _ => continue,
-                },
-                _ => {
-                    if op == notify::Op::CREATE | notify::Op::WRITE {
-                        match docker_config_file_type(path) {
-                            DockerConfigFileType::ConfigV2 => {
-                                self.insert(path, JSONConfig::from(path))
-                            }
-                            DockerConfigFileType::Log => match self.get(path) {
-                                Some(cfg) => {
-                                    let pei = Self::config_to_pei(
-                                        &cfg.get_ns(),
-                                        &cfg.get_pod_name(),
-                                        &cfg.get_container_name(),
-                                        &cfg.log_path,
-                                    );
-                                    self.dispatch_create_event(&pei);
-                                    self.dispatch_write_event(&pei)
-                                }
-                                _ => {}
-                            },
-                            _ => continue,
-                        }
-                        continue;
+                    _ => {
+                        println!("[INFO] event {:?} {:?} ({:?})", op, path, cookie);
                     }
-                    
-                    match docker_config_file_type(path) {
-                        DockerConfigFileType::ConfigV2 => {
-                            self.remove(path);
-                        }
-                        DockerConfigFileType::Log => self.dispatch_close_event(&PathEventInfo {
-                            path: path.to_string(),
-                            ..Default::default()
-                        }),
-                        _ => {
-                            println!("[INFO] event {:?} {:?} ({:?})", op, path, cookie);
-                        }
+                }
+            }
+        }
+    }
+
+    fn reconcile_on_deadline(&mut self, last_reconcile: &mut Instant) {
+        if let Some(interval) = self.reconcile_interval {
+            if last_reconcile.elapsed() >= interval {
+                self.reconcile();
+                *last_reconcile = Instant::now();
+            }
+        }
+    }
+
+    // poll_start watches the docker dir by comparing file stats every poll interval
+    fn poll_start(&mut self) -> Result<()> {
+        println!(
+            "[INFO] watch {:?} by polling every {:?}",
+            self.docker_dir, self.poll_interval
+        );
+        let mut poller = Poller::new();
+        let filter =
+            |path: &str| !matches!(docker_config_file_type(path), DockerConfigFileType::Unknow);
+        // the files found by prepare are already known
+        poller.walk(&self.docker_dir, filter);
+
+        let mut last_reconcile = Instant::now();
+        loop {
+            thread::sleep(self.poll_interval);
+            let mut events = poller.walk(&self.docker_dir, filter);
+            // configs first, a new log is only known once its config is cached
+            events.sort_by_key(|(path, _)| match docker_config_file_type(path) {
+                DockerConfigFileType::ConfigV2 => 0,
+                _ => 1,
+            });
+            for (path, event) in events {
+                let op = match event {
+                    PathEvent::Create => notify::Op::CREATE,
+                    PathEvent::Write => notify::Op::WRITE,
+                    _ => notify::Op::REMOVE,
+                };
+                self.handle_raw_event(&path, op, None);
+            }
+            self.reconcile_on_deadline(&mut last_reconcile);
+        }
+    }
+
+    fn fallback_poll_start(&mut self, e: notify::Error) -> Result<()> {
+        if self.watch_backend != WatchBackend::Auto {
+            return Err(Box::new(e));
+        }
+        eprintln!(
+            "[WARN] inotify watch {:?} failed: {:?}, fallback to polling",
+            self.docker_dir, e
+        );
+        if let Some(on_poll_fallback) = &self.on_poll_fallback {
+            on_poll_fallback(self.poll_interval);
+        }
+        self.poll_start()
+    }
+
+    pub fn watch_start(&mut self) -> Result<()> {
+        if self.watch_backend == WatchBackend::Poll {
+            return self.poll_start();
+        }
+
+        let (tx, rx) = channel();
+        let mut watcher = match raw_watcher(tx) {
+            Ok(watcher) => watcher,
+            Err(e) => return self.fallback_poll_start(e),
+        };
+
+        if let Err(e) = watcher.watch(&self.docker_dir, RecursiveMode::Recursive) {
+            return self.fallback_poll_start(e);
+        }
+        let mut last_reconcile = Instant::now();
+        loop {
+            let event = match self.reconcile_interval {
+                Some(interval) => {
+                    // a busy watcher never times out, so reconcile on a deadline
+                    self.reconcile_on_deadline(&mut last_reconcile);
+                    match rx.recv_timeout(interval - last_reconcile.elapsed().min(interval)) {
+                        Ok(event) => event,
+                        Err(RecvTimeoutError::Timeout) => continue,
+                        Err(RecvTimeoutError::Disconnected) => break,
                     }
                 }
+                None => match rx.recv() {
+                    Ok(event) => event,
+                    Err(_) => break,
+                },
+            };
+
+            match event {
+                RawEvent {
+                    path: Some(path),
+                    op: Ok(op),
+                    cookie,
+                } => match path.to_str() {
+                    Some(path_str) => self.handle_raw_event(path_str, op, cookie),
+                    None => eprintln!("[ERROR] watch skip non utf8 path {:?}", path),
+                },
+                // the kernel queue overflowed, events are lost
+                RawEvent { op: Ok(op), .. } if op.contains(notify::Op::RESCAN) => {
+                    self.reconcile();
+                }
+                RawEvent { op: Err(e), .. } => {
+                    eprintln!("[ERROR] watch {:?} error: {:?}", self.docker_dir, e);
+                }
+                _ => {}
             }
         }
 
@@ -365,12 +701,15 @@ impl AutoScanner {
 
 #[cfg(test)]
 mod tests {
-    use crate::{AutoScanner, GetDebug, PathEvent};
+    use crate::config_v2::JSONConfig;
+    use crate::{quarantined, AutoScanner, GetDebug, GetPathEventInfo, Namespaces, PathEvent};
+    use crossbeam_channel::{unbounded, Sender};
     use event::Listener;
+    use std::fs;
 
     #[test]
     fn it_works() {
-        let mut auto_scanner = AutoScanner::new("".into(), ".".into());
+        let mut auto_scanner = AutoScanner::new(Namespaces::new("*", ""), ".".into());
 
         struct ListenerImpl;
         impl<T> Listener<T> for ListenerImpl
@@ -390,4 +729,138 @@ mod tests {
         assert_eq!(PathEvent::Create.as_ref(), "NeedOpen");
         assert_eq!(PathEvent::Write.as_ref(), "NeedWrite");
     }
+
+    #[test]
+    fn reconcile_it_works() {
+        let dir = std::env::temp_dir().join("harvest_reconcile");
+        let container_dir = dir.join("containers").join("58044a726890");
+        fs::create_dir_all(&container_dir).unwrap();
+        let log_path = container_dir.join("58044a726890-json.log");
+        fs::write(&log_path, "{\"log\":\"hello\\n\"}\n").unwrap();
+
+        let mut cfg = JSONConfig {
+            log_path: log_path.to_str().unwrap().to_string(),
+            ..Default::default()
+        };
+        for (k, v) in [
+            ("io.kubernetes.pod.namespace", "default"),
+            ("io.kubernetes.pod.name", "web-7d9f-abc"),
+            ("io.kubernetes.container.name", "nginx"),
+        ]
+        .iter()
+        {
+            cfg.config.labels.insert(k.to_string(), v.to_string());
+        }
+        fs::write(
+            container_dir.join("config.v2.json"),
+            serde_json::to_string(&cfg).unwrap(),
+        )
+        .unwrap();
+
+        struct CreateListener(Sender<String>);
+        impl<T> Listener<T> for CreateListener
+        where
+            T: Clone + GetPathEventInfo,
+        {
+            fn handle(&self, t: T) {
+                self.0.send(t.get().path.clone()).unwrap()
+            }
+        }
+
+        let (tx, rx) = unbounded();
+        let mut auto_scanner =
+            AutoScanner::new(Namespaces::new("*", ""), dir.to_str().unwrap().into());
+        auto_scanner.append_create_event_handle(CreateListener(tx));
+
+        // the config is not cached and the log is unknown to the db
+        assert_eq!(auto_scanner.reconcile(), 2);
+        assert_eq!(rx.try_recv().unwrap(), cfg.log_path);
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn reconcile_sandbox_it_works() {
+        let dir = std::env::temp_dir().join("harvest_reconcile_sandbox");
+        let sandbox_dir = dir.join("containers").join("7a1c0e2f3b4d");
+        fs::create_dir_all(&sandbox_dir).unwrap();
+        let mut cfg = JSONConfig {
+            log_path: sandbox_dir
+                .join("7a1c0e2f3b4d-json.log")
+                .to_str()
+                .unwrap()
+                .to_string(),
+            ..Default::default()
+        };
+        for (k, v) in [
+            ("io.kubernetes.pod.namespace", "default"),
+            ("io.kubernetes.pod.name", "web-7d9f-abc"),
+            ("io.kubernetes.container.name", "POD"),
+        ]
+        .iter()
+        {
+            cfg.config.labels.insert(k.to_string(), v.to_string());
+        }
+        fs::write(
+            sandbox_dir.join("config.v2.json"),
+            serde_json::to_string(&cfg).unwrap(),
+        )
+        .unwrap();
+
+        // the sandbox config is never cached, so it is no drift
+        let mut auto_scanner =
+            AutoScanner::new(Namespaces::new("*", ""), dir.to_str().unwrap().into());
+        auto_scanner.reconcile();
+        assert_eq!(auto_scanner.reconcile(), 0);
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn quarantine_it_works() {
+        let dir = std::env::temp_dir().join("harvest_quarantine");
+        let container_dir = dir.join("containers").join("e1d2c3b4a596");
+        fs::create_dir_all(&container_dir).unwrap();
+        let config_path = container_dir.join("config.v2.json");
+        let config_path_str = config_path.to_str().unwrap().to_string();
+
+        let mut auto_scanner =
+            AutoScanner::new(Namespaces::new("*", ""), dir.to_str().unwrap().into());
+
+        // docker is caught in the middle of rewriting the config
+        fs::write(&config_path, "{\"State\": {\"Running\": tr").unwrap();
+        auto_scanner.handle_raw_event(&config_path_str, notify::Op::WRITE, None);
+        assert!(quarantined().iter().any(|q| q.path == config_path_str));
+
+        let mut cfg = JSONConfig {
+            log_path: container_dir
+                .join("e1d2c3b4a596-json.log")
+                .to_str()
+                .unwrap()
+                .to_string(),
+            ..Default::default()
+        };
+        for (k, v) in [
+            ("io.kubernetes.pod.namespace", "default"),
+            ("io.kubernetes.container.name", "nginx"),
+        ]
+        .iter()
+        {
+            cfg.config.labels.insert(k.to_string(), v.to_string());
+        }
+        fs::write(&config_path, serde_json::to_string(&cfg).unwrap()).unwrap();
+        auto_scanner.handle_raw_event(&config_path_str, notify::Op::WRITE, None);
+        assert!(!quarantined().iter().any(|q| q.path == config_path_str));
+        assert_eq!(auto_scanner.get(&cfg.log_path), Some(cfg));
+
+        // the container is removed while its config is broken
+        fs::write(&config_path, "{\"State\": {").unwrap();
+        auto_scanner.handle_raw_event(&config_path_str, notify::Op::WRITE, None);
+        assert!(quarantined().iter().any(|q| q.path == config_path_str));
+        fs::remove_file(&config_path).unwrap();
+        auto_scanner.handle_raw_event(&config_path_str, notify::Op::REMOVE, None);
+        assert!(!quarantined().iter().any(|q| q.path == config_path_str));
+
+        let _ = fs::remove_dir_all(dir);
+    }
 }
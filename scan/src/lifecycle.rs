@@ -0,0 +1,123 @@
+use super::JSONConfig;
+
+/// ContainerStateEvent is a synthetic record describing a container state
+/// change, found by diffing two versions of `config.v2.json`.
+#[derive(Debug, Clone, PartialEq, serde_derive::Serialize)]
+pub struct ContainerStateEvent {
+    pub reason: String,
+    pub message: String,
+    pub exit_code: i64,
+    pub restart_count: i64,
+    pub oom_killed: bool,
+    pub started_at: String,
+    pub finished_at: String,
+}
+
+impl ContainerStateEvent {
+    fn new(reason: &str, message: String, cfg: &JSONConfig) -> Self {
+        Self {
+            reason: reason.to_string(),
+            message,
+            exit_code: cfg.state.exit_code,
+            restart_count: cfg.get_restart_count(),
+            oom_killed: cfg.state.oomkilled,
+            started_at: cfg.state.started_at.clone(),
+            finished_at: cfg.state.finished_at.clone(),
+        }
+    }
+}
+
+/// diff_state compares the previous and the current config of a container,
+/// `previous` is None when the config is seen for the first time.
+pub fn diff_state(previous: Option<&JSONConfig>, current: &JSONConfig) -> Vec<ContainerStateEvent> {
+    let mut events = vec![];
+    let (was_running, restart_count) = match previous {
+        Some(previous) => (previous.state.running, previous.get_restart_count()),
+        None => (false, 0),
+    };
+
+    if current.state.running {
+        if !was_running && current.get_restart_count() > 0 {
+            events.push(ContainerStateEvent::new(
+                "restarted",
+                format!(
+                    "container restarted, restart count {}",
+                    current.get_restart_count()
+                ),
+                current,
+            ));
+        } else if !was_running {
+            events.push(ContainerStateEvent::new(
+                "started",
+                "container started".to_string(),
+                current,
+            ));
+        } else if current.get_restart_count() > restart_count {
+            events.push(ContainerStateEvent::new(
+                "restarted",
+                format!(
+                    "container restarted, restart count {}",
+                    current.get_restart_count()
+                ),
+                current,
+            ));
+        }
+        return events;
+    }
+
+    if was_running {
+        if current.state.oomkilled {
+            events.push(ContainerStateEvent::new(
+                "oom-killed",
+                format!(
+                    "container oom-killed, exit code {}",
+                    current.state.exit_code
+                ),
+                current,
+            ));
+        } else {
+            events.push(ContainerStateEvent::new(
+                "exited",
+                format!("container exited, exit code {}", current.state.exit_code),
+                current,
+            ));
+        }
+    }
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::diff_state;
+    use crate::config_v2::JSONConfig;
+
+    #[test]
+    fn it_works() {
+        let mut running = JSONConfig::default();
+        running.state.running = true;
+
+        let events = diff_state(None, &running);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].reason, "started");
+        assert!(diff_state(Some(&running), &running).is_empty());
+
+        let mut killed = running.clone();
+        killed.state.running = false;
+        killed.state.oomkilled = true;
+        killed.state.exit_code = 137;
+        let events = diff_state(Some(&running), &killed);
+        assert_eq!(events[0].reason, "oom-killed");
+        assert_eq!(events[0].message, "container oom-killed, exit code 137");
+
+        let mut exited = running.clone();
+        exited.state.running = false;
+        exited.state.exit_code = 1;
+        assert_eq!(diff_state(Some(&running), &exited)[0].reason, "exited");
+
+        let mut restarted = running.clone();
+        restarted.restart_count = 1;
+        let events = diff_state(Some(&exited), &restarted);
+        assert_eq!(events[0].reason, "restarted");
+        assert_eq!(events[0].restart_count, 1);
+    }
+}
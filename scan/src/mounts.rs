@@ -0,0 +1,158 @@
+use db::Mount;
+use glob::{glob, Pattern};
+use std::fs;
+use std::path::Path;
+
+const OVERLAY2_DRIVER: &str = "overlay2";
+
+/// docker_root returns the docker root dir of a `<root>/containers/<id>/<id>-json.log` path.
+pub fn docker_root(log_path: &str) -> Option<String> {
+    Path::new(log_path)
+        .ancestors()
+        .nth(3)
+        .and_then(|root| root.to_str())
+        .map(|root| root.to_string())
+}
+
+/// merged_dir returns the overlay2 merged dir of the container, docker keeps its
+/// layer id in `image/overlay2/layerdb/mounts/<id>/mount-id`.
+pub fn merged_dir(docker_root: &str, container_id: &str) -> Option<String> {
+    let root = Path::new(docker_root);
+    let mount_id = fs::read_to_string(
+        root.join("image")
+            .join(OVERLAY2_DRIVER)
+            .join("layerdb")
+            .join("mounts")
+            .join(container_id)
+            .join("mount-id"),
+    )
+    .ok()?;
+    root.join(OVERLAY2_DRIVER)
+        .join(mount_id.trim())
+        .join("merged")
+        .to_str()
+        .map(|merged| merged.to_string())
+}
+
+// host_base returns the host dir backing the in-container path, the volume
+// mounted on the longest destination prefix wins over the merged dir
+fn host_base<'a>(merged_dir: &'a str, mounts: &'a [Mount], path: &str) -> (&'a str, &'a str) {
+    let mut base = (merged_dir, "");
+    for mount in mounts {
+        let destination = mount.destination.trim_end_matches('/');
+        let under = path == destination || path.starts_with(&format!("{}/", destination));
+        if under && destination.len() >= base.1.len() {
+            base = (mount.source.as_str(), destination);
+        }
+    }
+    base
+}
+
+/// resolve_paths expands the in-container glob `patterns` of a container,
+/// it returns the (in-container path, host path) of every matching file.
+/// Matches escaping the volume or merged dir through `..` or a symlink are dropped.
+pub fn resolve_paths(
+    merged_dir: &str,
+    mounts: &[Mount],
+    patterns: &[String],
+) -> Vec<(String, String)> {
+    let mut files = vec![];
+    for pattern in patterns {
+        if !pattern.starts_with('/') || pattern.split('/').any(|item| item == "..") {
+            eprintln!("[ERROR] skip invalid container path pattern {:?}", pattern);
+            continue;
+        }
+
+        let (host, destination) = host_base(merged_dir, mounts, pattern);
+        let host = host.trim_end_matches('/');
+        if host.is_empty() {
+            continue;
+        }
+        let real_host = match fs::canonicalize(host) {
+            Ok(real_host) => real_host,
+            Err(_) => continue,
+        };
+        let host_pattern = format!("{}{}", Pattern::escape(host), &pattern[destination.len()..]);
+        let entries = match glob(&host_pattern) {
+            Ok(entries) => entries,
+            Err(e) => {
+                eprintln!(
+                    "[ERROR] container path pattern {:?} error: {:?}",
+                    pattern, e
+                );
+                continue;
+            }
+        };
+        for entry in entries.filter_map(|entry| entry.ok()) {
+            match fs::canonicalize(&entry) {
+                Ok(real) if real.is_file() && real.starts_with(&real_host) => {}
+                _ => continue,
+            }
+            if let Some(host_path) = entry.to_str() {
+                let container_path = format!("{}{}", destination, &host_path[host.len()..]);
+                files.push((container_path, host_path.to_string()));
+            }
+        }
+    }
+    files
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{docker_root, merged_dir, resolve_paths};
+    use db::Mount;
+    use std::fs;
+
+    #[test]
+    fn it_works() {
+        let root = std::env::temp_dir().join("harvest_mounts");
+        let layerdb = root.join("image/overlay2/layerdb/mounts/58044a726890");
+        let merged = root.join("overlay2/3f5e1c2b/merged");
+        let volume = root.join("volumes/logs");
+        fs::create_dir_all(&layerdb).unwrap();
+        fs::create_dir_all(merged.join("app/logs")).unwrap();
+        fs::create_dir_all(&volume).unwrap();
+        fs::write(layerdb.join("mount-id"), "3f5e1c2b").unwrap();
+        fs::write(merged.join("app/logs/app.log"), "line\n").unwrap();
+        fs::write(merged.join("app/logs/gc.txt"), "line\n").unwrap();
+        fs::write(volume.join("access.log"), "line\n").unwrap();
+
+        let root_str = root.to_str().unwrap();
+        let log_path = format!("{}/containers/58044a726890/58044a726890-json.log", root_str);
+        assert_eq!(docker_root(&log_path).unwrap(), root_str);
+
+        let merged_str = merged_dir(root_str, "58044a726890").unwrap();
+        assert_eq!(merged_str, merged.to_str().unwrap());
+        assert!(merged_dir(root_str, "unknown").is_none());
+
+        let mounts = vec![Mount {
+            source: volume.to_str().unwrap().to_string(),
+            destination: "/var/log/nginx".to_string(),
+        }];
+        let patterns = vec![
+            "/app/logs/*.log".to_string(),
+            "/var/log/nginx/*.log".to_string(),
+            "/app/../../../etc/*".to_string(),
+        ];
+        let files = resolve_paths(&merged_str, &mounts, &patterns);
+        assert_eq!(
+            files,
+            vec![
+                (
+                    "/app/logs/app.log".to_string(),
+                    merged
+                        .join("app/logs/app.log")
+                        .to_str()
+                        .unwrap()
+                        .to_string()
+                ),
+                (
+                    "/var/log/nginx/access.log".to_string(),
+                    volume.join("access.log").to_str().unwrap().to_string()
+                ),
+            ]
+        );
+
+        let _ = fs::remove_dir_all(root);
+    }
+}
@@ -0,0 +1,62 @@
+const ALL_NAMESPACES: &str = "*";
+
+/// Namespaces decides which pod namespaces the scanner collects.
+/// `include` is a comma separated list or `*` for all namespaces, empty
+/// collects none as the single `--namespace` used to; `exclude` always wins
+/// over `include`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Namespaces {
+    include: Vec<String>,
+    exclude: Vec<String>,
+}
+
+fn split_list(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(|item| item.trim())
+        .filter(|item| !item.is_empty())
+        .map(|item| item.to_string())
+        .collect()
+}
+
+impl Namespaces {
+    pub fn new(include: &str, exclude: &str) -> Self {
+        Self {
+            include: split_list(include),
+            exclude: split_list(exclude),
+        }
+    }
+
+    pub fn is_all(&self) -> bool {
+        self.include.iter().any(|ns| ns == ALL_NAMESPACES)
+    }
+
+    pub fn contains(&self, ns: &str) -> bool {
+        if ns.is_empty() || self.exclude.iter().any(|item| item == ns) {
+            return false;
+        }
+        self.is_all() || self.include.iter().any(|item| item == ns)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Namespaces;
+
+    #[test]
+    fn it_works() {
+        let namespaces = Namespaces::new("default, finance-dev", "");
+        assert!(namespaces.contains("default"));
+        assert!(namespaces.contains("finance-dev"));
+        assert!(!namespaces.contains("kube-system"));
+
+        let namespaces = Namespaces::new("*", "kube-system");
+        assert!(namespaces.is_all());
+        assert!(namespaces.contains("default"));
+        assert!(!namespaces.contains("kube-system"));
+        assert!(!namespaces.contains(""));
+
+        // no namespace collects nothing
+        assert!(!Namespaces::default().contains("default"));
+        assert!(!Namespaces::new("", "kube-system").is_all());
+    }
+}
@@ -0,0 +1,150 @@
+use super::PathEvent;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::fs;
+use std::time::SystemTime;
+use walkdir::WalkDir;
+
+/// WatchBackend selects how the scanner watches the docker dir, `Auto` uses
+/// inotify and falls back to polling when inotify can not be set up.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WatchBackend {
+    Inotify,
+    Poll,
+    Auto,
+}
+
+impl TryFrom<&str> for WatchBackend {
+    type Error = String;
+
+    fn try_from(backend: &str) -> Result<Self, Self::Error> {
+        match backend {
+            "inotify" => Ok(WatchBackend::Inotify),
+            "poll" => Ok(WatchBackend::Poll),
+            "auto" => Ok(WatchBackend::Auto),
+            _ => Err(format!(
+                "unknown watcher {:?}, expect auto, inotify or poll",
+                backend
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct FileStat {
+    len: u64,
+    modified: Option<SystemTime>,
+}
+
+/// Poller is a stat based watcher for filesystems where inotify is not
+/// available or unreliable, every call compares size and mtime of the files
+/// with the previous call.
+#[derive(Debug, Default)]
+pub struct Poller {
+    files: HashMap<String, FileStat>,
+}
+
+impl Poller {
+    pub fn new() -> Self {
+        Self {
+            files: HashMap::new(),
+        }
+    }
+
+    /// diff stats `paths` and returns the create/write events since the last
+    /// call, known files missing from `paths` are reported as removed.
+    pub fn diff<I>(&mut self, paths: I) -> Vec<(String, PathEvent)>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut events = vec![];
+        let mut seen = HashSet::new();
+        for path in paths {
+            let stat = match fs::metadata(&path) {
+                Ok(metadata) => FileStat {
+                    len: metadata.len(),
+                    modified: metadata.modified().ok(),
+                },
+                Err(_) => continue,
+            };
+            match self.files.get(&path) {
+                None => events.push((path.clone(), PathEvent::Create)),
+                Some(previous) if previous != &stat => {
+                    events.push((path.clone(), PathEvent::Write))
+                }
+                _ => {}
+            }
+            self.files.insert(path.clone(), stat);
+            seen.insert(path);
+        }
+
+        let removed = self
+            .files
+            .keys()
+            .filter(|path| !seen.contains(*path))
+            .cloned()
+            .collect::<Vec<String>>();
+        for path in removed {
+            self.files.remove(&path);
+            events.push((path, PathEvent::Remove));
+        }
+        events
+    }
+
+    /// walk diffs every file under `root` accepted by `filter`.
+    pub fn walk<F>(&mut self, root: &str, filter: F) -> Vec<(String, PathEvent)>
+    where
+        F: Fn(&str) -> bool,
+    {
+        let paths = WalkDir::new(root)
+            .into_iter()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().is_file())
+            .filter_map(|entry| entry.path().to_str().map(|path| path.to_string()))
+            .filter(|path| filter(path))
+            .collect::<Vec<String>>();
+        self.diff(paths)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Poller, WatchBackend};
+    use crate::PathEvent;
+    use std::convert::TryFrom;
+    use std::fs;
+
+    #[test]
+    fn it_works() {
+        let dir = std::env::temp_dir().join("harvest_poller");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("app.log");
+        fs::write(&path, "line 1\n").unwrap();
+        let root = dir.to_str().unwrap();
+
+        let mut poller = Poller::new();
+        let events = poller.walk(root, |path| path.ends_with(".log"));
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].1.as_ref(), PathEvent::Create.as_ref());
+        assert!(poller.walk(root, |path| path.ends_with(".log")).is_empty());
+
+        fs::write(&path, "line 1\nline 2\n").unwrap();
+        let events = poller.walk(root, |path| path.ends_with(".log"));
+        assert_eq!(events[0].1.as_ref(), PathEvent::Write.as_ref());
+
+        fs::remove_file(&path).unwrap();
+        let events = poller.walk(root, |path| path.ends_with(".log"));
+        assert_eq!(events[0].1.as_ref(), PathEvent::Remove.as_ref());
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn watch_backend_it_works() {
+        assert_eq!(WatchBackend::try_from("auto"), Ok(WatchBackend::Auto));
+        assert_eq!(WatchBackend::try_from("poll"), Ok(WatchBackend::Poll));
+        assert_eq!(WatchBackend::try_from("inotify"), Ok(WatchBackend::Inotify));
+        assert!(WatchBackend::try_from("polling").is_err());
+        assert!(WatchBackend::try_from("").is_err());
+    }
+}
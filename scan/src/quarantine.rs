@@ -0,0 +1,52 @@
+use serde_derive::Serialize;
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+lazy_static! {
+    // config path -> reason the config could not be parsed
+    static ref QUARANTINE: RwLock<HashMap<String, String>> = RwLock::new(HashMap::new());
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct QuarantinedConfig {
+    pub path: String,
+    pub reason: String,
+}
+
+/// quarantine records an unparsable config, it is parsed again on its next write.
+pub(crate) fn quarantine(path: &str, reason: &str) {
+    eprintln!("[ERROR] quarantine config {:?}, reason: {:?}", path, reason);
+    match QUARANTINE.write() {
+        Ok(mut quarantine) => {
+            quarantine.insert(path.to_string(), reason.to_string());
+        }
+        Err(e) => eprintln!("[ERROR] quarantine write lock failed: {:?}", e),
+    }
+}
+
+pub(crate) fn release(path: &str) {
+    match QUARANTINE.write() {
+        Ok(mut quarantine) => {
+            if quarantine.remove(path).is_some() {
+                println!("[INFO] release config {:?} from quarantine", path);
+            }
+        }
+        Err(e) => eprintln!("[ERROR] quarantine write lock failed: {:?}", e),
+    }
+}
+
+pub fn quarantined() -> Vec<QuarantinedConfig> {
+    match QUARANTINE.read() {
+        Ok(quarantine) => quarantine
+            .iter()
+            .map(|(path, reason)| QuarantinedConfig {
+                path: path.clone(),
+                reason: reason.clone(),
+            })
+            .collect(),
+        Err(e) => {
+            eprintln!("[ERROR] quarantine read lock failed: {:?}", e);
+            vec![]
+        }
+    }
+}
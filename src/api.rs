@@ -1,15 +1,24 @@
 use std::time::Duration;
 
-use super::{run_task, stop_task, tasks_json, Task};
-use common::retry_fn;
-use rocket::get;
-use rocket_contrib::json::JsonValue;
+use super::{ns_tasks_json, run_task, stop_task, tasks_json, Task};
+use common::{parse_rfc3339, retry_fn};
+use db::Container;
+use rocket::http::{ContentType, Status};
+use rocket::request::{self, FromRequest};
+use rocket::response::content::Content;
+use rocket::response::status;
+use rocket::{get, post, Data, Outcome};
+use rocket_contrib::json::{Json, JsonValue};
 use serde::{Deserialize, Serialize};
 use sse_client::EventSource;
+use std::io::Read;
 
 const RUN: &'static str = "run";
 const STOP: &'static str = "stop";
 const HELLO: &'static str = "hello";
+const REPLAY: &'static str = "replay";
+// bytes accepted in a `POST /ingest` body, a larger body is rejected
+const MAX_INGEST_SIZE: u64 = 8 * 1024 * 1024;
 
 pub(crate) fn recv_tasks(addr: &str, node_name: &str) {
     let uri = addr.to_string() + node_name;
@@ -49,16 +58,36 @@ pub(crate) fn recv_tasks(addr: &str, node_name: &str) {
 
                 if cmd.op == RUN {
                     println!(
-                        "[INFO] task recv run task ns:{:?}, pod:{:?}, output:{:?}, server:{:?}",
-                        &cmd.ns, &cmd.pod_name, &cmd.output, &cmd.service_name
+                        "[INFO] task recv run task ns:{:?}, pod:{:?}, selector:{:?}, output:{:?}, server:{:?}",
+                        &cmd.ns, &cmd.pod_name, &cmd.selector, &cmd.output, &cmd.service_name
                     );
-                    run_task(Task::from(cmd));
+                    let warnings = match cmd.validate() {
+                        Ok(warnings) => warnings,
+                        Err(e) => {
+                            eprintln!("[ERROR] task config invalid: {}", e);
+                            continue;
+                        }
+                    };
+                    for warning in warnings.iter() {
+                        eprintln!("[ERROR] task config warning: {}", warning);
+                    }
+                    let mut task = Task::from(cmd);
+                    task.warnings = warnings;
+                    run_task(task);
                 } else if cmd.op == STOP {
                     println!(
-                        "[INFO] task recv stop task ns:{:?}, pod:{:?}, output:{:?}, server:{:?}",
-                        &cmd.ns, &cmd.pod_name, &cmd.output, &cmd.service_name
+                        "[INFO] task recv stop task ns:{:?}, pod:{:?}, selector:{:?}, output:{:?}, server:{:?}",
+                        &cmd.ns, &cmd.pod_name, &cmd.selector, &cmd.output, &cmd.service_name
                     );
                     stop_task(Task::from(cmd));
+                } else if cmd.op == REPLAY {
+                    println!(
+                        "[INFO] task recv replay ns:{:?}, pod:{:?}, since:{:?}, until:{:?}, output:{:?}",
+                        &cmd.ns, &cmd.pod_name, &cmd.since, &cmd.until, &cmd.output
+                    );
+                    if let Err(e) = run_replay(&ReplayRequest::from(&cmd)) {
+                        eprintln!("[ERROR] replay error: {}", e);
+                    }
                 } else if cmd.op == HELLO {
                     println!("[INFO] task recv hello !");
                 } else {
@@ -76,13 +105,115 @@ pub(crate) fn recv_tasks(addr: &str, node_name: &str) {
    "op":"run",
    "ns":"default",
    "service_name":"xx_service",
-   "filter":{"max_length":1024,"expr":"[INFO]"},
+   "filter":{"max_length":1024,"expr":"\"[INFO]\" in message"},
    "output":"fake_output",
     "node":"node1",
     "pod" :"pod-12345",
     "ips":[ "127.0.0.1"],
     "offset":0
 }
+
+a selector task replaces "pod" and follows the matching containers:
+{
+   "op":"run",
+   "ns":"default",
+   "output":"fake_output",
+   "node_name":"node1",
+   "filter":{"max_length":1024,"expr":""},
+   "service_name":"xx_service",
+   "ips":[],
+   "offset":0,
+   "selector":{"namespaces":["default"],"pod_name":"web-*","labels":{"app":"web"}}
+}
+
+records can be enriched with container metadata, "image" adds the image name, its local
+"imageId" and the registry "imageDigest" once the kubelet reported the pod:
+   "enrich":{"labels":true,"label_deny":["io.kubernetes.*"],"image":true,"container_id":true,"pod_uid":true,"node_name":true,"annotations":true,"owner":true}
+
+log files written inside the container are collected by in-container glob paths,
+resolved through the container volumes or its overlay2 merged dir:
+   "paths":["/app/logs/app*.log", "/var/log/nginx/access.log"]
+
+a file opened for the first time starts at "offset" unless a start position is set,
+one of "beginning", "end", "offset:N", "last_lines:N" or "since:<RFC 3339 time>":
+   "start":"last_lines:10000"
+
+messages are masked before they leave the node by the builtin detectors "email", "phone",
+"credit_card" (luhn validated), "bearer_token" and "password", by regex rules with a
+replacement template, and by the keys of json messages:
+   "redact":{"builtins":["email","credit_card"],"rules":[{"name":"session","pattern":"session=(\\w{4})\\w+","replacement":"session=$1****"}],"fields":["password","token"]}
+
+plain-text messages are parsed into "fields" by grok patterns of the bundled library
+(NGINX_ACCESS, NGINX_ERROR, COMMONAPACHELOG, COMBINEDAPACHELOG, APACHE_ERROR, JAVA_LOG4J,
+GO_KLOG, ...) or regexes with named captures, tried in order, a message matching none is
+kept, tagged "_grokparsefailure" or dropped by "on_failure" ("keep", "tag" or "drop"):
+   "parse":{"patterns":["%{NGINX_ACCESS}","^took (?P<duration>\\d+)ms$"],"definitions":{"ORDER":"ord-\\d+"},"on_failure":"tag"}
+
+the level of every message is detected from its parsed fields, json "level"/"severity" keys,
+klog headers, logfmt "level=" pairs and "[INFO]"-style prefixes, and shipped as "level",
+"min_level" drops the messages below a level, messages without a level are kept:
+   "filter":{"max_length":1024,"expr":"","min_level":"warn"}
+
+"expr" keeps the messages matching an expression over the keys of a json message, the
+parsed fields, "message", "level", "tags" and "container" metadata, with ==, !=, <, <=, >,
+>=, =~ and !~ (regex), in (list or substring), !, && and ||:
+   "filter":{"max_length":1024,"expr":"level in [\"error\",\"warn\"] && status >= 500 && !(path =~ \"/health\")"}
+a legacy "expr" of plain text without operators or quotes, such as "[INFO]", keeps the
+messages containing it and is listed in the "warnings" of the task on GET /tasks, any other
+"expr" that does not parse rejects the task with the column of the error
+
+the volume of every container is capped by token buckets of lines and bytes per second, and
+sampled at random or by message hash to "sample_rate" of the lines, error lines are never
+sampled out, a "suppressed" record reports the dropped lines every "report_interval" seconds:
+   "limit":{"lines_per_sec":1000,"bytes_per_sec":1048576,"sample_rate":0.1,"sample_by":"hash","report_interval":60}
+
+consecutive identical lines of a container are collapsed into the first one with a
+"repeat_count", shipped when a different line is read or "window" seconds after the first
+line, "normalize_numbers" compares the lines with their numbers masked:
+   "dedup":{"window":10,"normalize_numbers":true}
+
+a Rhai "script" runs on every record as "record" (message, fields, level and tags) with the
+container metadata as "container" (ns, pod_name, container, labels, annotations, ...), it
+modifies "record", returns false to drop it or an array of records to split it, a run is
+bounded by "max_operations", "timeout_ms", "max_string_size" and "max_collection_size", and
+running readers reload the script when the task is updated:
+   "script":{"source":"if record.message.contains(\"/healthz\") { return false; } record.fields.tenant = container.ns;","timeout_ms":10}
+
+webassembly "plugins" run in order on every message after the script, "name" loads
+<name>.wasm from the --plugin-dir of the agent and "path" a module file in that directory,
+a call is metered by "fuel" and the memory capped to "max_memory" bytes, a failing plugin is
+bypassed or drops the message by "on_failure" ("bypass" or "drop"). A module has no imports
+and exports "memory", "alloc(len) -> ptr" and "filter(ptr, len) -> i32" (0 drops) and/or
+"transform(ptr, len) -> i64" (negative drops, else ptr << 32 | len of the new message):
+   "plugins":[{"name":"mask_ids","fuel":1000000,"on_failure":"drop"}]
+
+"metrics" derive counters and histograms from the records matching "expr" before they are
+filtered, "labels" name the record fields of the label values, a counter adds 1 or the
+"value" field unless negative and a histogram observes the "value" field in "buckets", the
+metrics are exposed on GET /metrics and written to "output" every "interval" seconds when it
+is set:
+   "metrics":{"rules":[{"name":"http_errors_total","expr":"status >= 500","labels":{"service":"container.service_name"}}],"output":"metrics_topic","interval":60}
+
+the "envelope" of the records is the "schema" of the task or else of its output, set by
+the --output-envelopes of the agent, and legacy unless set: "legacy" (a "custom" block of
+the container), "flat", "ecs", "otel" (the OpenTelemetry log data model), "cloudevents" or
+"template", a json "template" whose "${path}" strings are replaced by the values of
+message, level, tags, fields, repeat_count, container, custom and timestamp:
+   "envelope":{"schema":"template","template":"{\"msg\":\"${message}\",\"pod\":\"${container.pod_name}\"}"}
+
+a replay re-ships the current and rotated logs of a pod between two times to an output,
+an empty "until" replays up to now:
+{
+   "op":"replay",
+   "ns":"default",
+   "pod_name":"pod-12345",
+   "container":"nginx1",
+   "node_name":"node1",
+   "output":"fake_output",
+   "since":"2021-02-03T04:00:00Z",
+   "until":"2021-02-03T05:00:00Z",
+   ...
+}
 */
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -93,9 +224,40 @@ pub(crate) struct Cmd<'a> {
     pub(crate) filter: db::Filter,
     pub(crate) service_name: &'a str,
     pub(crate) node_name: &'a str,
+    #[serde(default)]
     pub(crate) pod_name: &'a str,
     pub(crate) ips: Vec<&'a str>,
     pub(crate) offset: u64,
+    #[serde(default)]
+    pub(crate) selector: Option<db::Selector>,
+    #[serde(default)]
+    pub(crate) enrich: db::Enrich,
+    #[serde(default)]
+    pub(crate) paths: Vec<String>,
+    #[serde(default)]
+    pub(crate) start: db::StartPosition,
+    #[serde(default)]
+    pub(crate) redact: db::Redact,
+    #[serde(default)]
+    pub(crate) parse: db::Parse,
+    #[serde(default)]
+    pub(crate) limit: db::Limit,
+    #[serde(default)]
+    pub(crate) dedup: db::Dedup,
+    #[serde(default)]
+    pub(crate) script: db::Script,
+    #[serde(default)]
+    pub(crate) plugins: Vec<db::Plugin>,
+    #[serde(default)]
+    pub(crate) metrics: db::Metrics,
+    #[serde(default)]
+    pub(crate) envelope: db::Envelope,
+    #[serde(default)]
+    pub(crate) container: &'a str,
+    #[serde(default)]
+    pub(crate) since: &'a str,
+    #[serde(default)]
+    pub(crate) until: &'a str,
 }
 
 impl<'a> Cmd<'a> {
@@ -105,6 +267,51 @@ impl<'a> Cmd<'a> {
         }
         false
     }
+
+    // validate checks the processing config of a run task, so a task is not
+    // started with rules its readers can not apply, it returns the problems
+    // the task still runs with
+    pub(crate) fn validate(&self) -> std::result::Result<Vec<String>, String> {
+        let mut warnings = vec![];
+        if let Some(selector) = &self.selector {
+            selector.validate()?;
+        }
+        filter::Redactor::new(&self.redact)?;
+        filter::GrokParser::new(&self.parse)?;
+        if self.script.is_enabled() {
+            filter::Scripter::new(&self.script, &db::Container::default())?;
+        }
+        for plugin in self.plugins.iter() {
+            filter::WasmPlugin::new(plugin)?;
+        }
+        if self.metrics.is_enabled() {
+            filter::MetricRules::new(&self.metrics)?;
+        }
+        if self.envelope.schema == Some(db::EnvelopeSchema::Template) {
+            file::parse_template(&self.envelope.template)?;
+        }
+        if self.limit.sample_rate < 0.0 || self.limit.sample_rate > 1.0 {
+            return Err(format!(
+                "sample rate {} is not within [0, 1]",
+                self.limit.sample_rate
+            ));
+        }
+        if !self.filter.expr.trim().is_empty() {
+            let expr = filter::Expr::parse_filter(&self.filter.expr).map_err(|e| e.to_string())?;
+            if expr.is_legacy() {
+                warnings.push(format!(
+                    "filter {:?} is not an expression, matched as a substring of the message",
+                    self.filter.expr
+                ));
+            }
+        }
+        if !self.filter.min_level.is_empty()
+            && filter::Level::parse(&self.filter.min_level).is_none()
+        {
+            return Err(format!("unknown min level {:?}", self.filter.min_level));
+        }
+        Ok(warnings)
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -116,14 +323,193 @@ pub(crate) struct Request {
     upload: bool,
 }
 
-#[get("/tasks")]
-pub(crate) fn query_tasks() -> JsonValue {
-    json!(tasks_json())
+#[get("/tasks?<ns>")]
+pub(crate) fn query_tasks(ns: Option<String>) -> JsonValue {
+    match ns {
+        Some(ns) => json!(ns_tasks_json(&ns)),
+        None => json!(tasks_json()),
+    }
+}
+
+#[get("/pods?<ns>")]
+pub(crate) fn query_all_pod(ns: Option<String>) -> JsonValue {
+    match ns {
+        Some(ns) => json!(db::ns_to_json(&ns)),
+        None => json!(db::pods_to_json()),
+    }
+}
+
+#[get("/redactions")]
+pub(crate) fn query_redactions() -> JsonValue {
+    json!(filter::redaction_counts())
+}
+
+#[get("/metrics")]
+pub(crate) fn query_metrics() -> Content<String> {
+    Content(
+        ContentType::new("text", "plain").with_params(("version", "0.0.4")),
+        filter::render_metrics(),
+    )
+}
+
+#[get("/quarantine")]
+pub(crate) fn query_quarantine() -> JsonValue {
+    json!(scan::quarantined())
+}
+
+/// ReplayRequest is the body of `POST /replay`, `container` empty replays
+/// every container of the pod.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct ReplayRequest {
+    ns: String,
+    pod_name: String,
+    #[serde(default)]
+    container: String,
+    since: String,
+    #[serde(default)]
+    until: String,
+    output: String,
+}
+
+impl<'a> From<&Cmd<'a>> for ReplayRequest {
+    fn from(cmd: &Cmd<'a>) -> Self {
+        Self {
+            ns: cmd.ns.to_string(),
+            pod_name: cmd.pod_name.to_string(),
+            container: cmd.container.to_string(),
+            since: cmd.since.to_string(),
+            until: cmd.until.to_string(),
+            output: cmd.output.to_string(),
+        }
+    }
+}
+
+pub(crate) fn run_replay(
+    request: &ReplayRequest,
+) -> std::result::Result<file::ReplayProgress, String> {
+    let since = match parse_rfc3339(&request.since) {
+        Some(since) => since,
+        None => return Err(format!("invalid since {:?}", request.since)),
+    };
+    let until = match request.until.as_str() {
+        "" => i64::MAX,
+        until => match parse_rfc3339(until) {
+            Some(until) => until,
+            None => return Err(format!("invalid until {:?}", request.until)),
+        },
+    };
+    if since > until {
+        return Err(format!(
+            "since {:?} is after until {:?}",
+            request.since, request.until
+        ));
+    }
+    let containers = db::get_container_slice_by_pod(&request.ns, &request.pod_name)
+        .into_iter()
+        .map(|(_, container)| container)
+        .filter(|container| {
+            request.container.is_empty() || container.container == request.container
+        })
+        .collect::<Vec<Container>>();
+    if containers.is_empty() {
+        return Err(format!(
+            "pod {:?} not found in namespace {:?}",
+            request.pod_name, request.ns
+        ));
+    }
+
+    output::registry_kafka_output(&request.output);
+    Ok(file::start_replay(
+        containers,
+        since,
+        until,
+        &request.output,
+    ))
+}
+
+#[post("/replay", format = "json", data = "<request>")]
+pub(crate) fn replay(request: Json<ReplayRequest>) -> JsonValue {
+    match run_replay(&request) {
+        Ok(progress) => json!(progress),
+        Err(e) => json!({
+            "status": "error",
+            "reason": e,
+        }),
+    }
 }
 
-#[get("/pods")]
-pub(crate) fn query_all_pod() -> JsonValue {
-    json!(db::all_to_json())
+#[get("/replay/<id>")]
+pub(crate) fn query_replay(id: String) -> JsonValue {
+    match file::replay_progress(&id) {
+        Some(progress) => json!(progress),
+        None => json!({}),
+    }
+}
+
+#[get("/replays")]
+pub(crate) fn query_replays() -> JsonValue {
+    json!(file::replays())
+}
+
+// IngestSource is the pod named by the `X-Harvest-Pod` header and the client ip
+pub(crate) struct IngestSource {
+    pod: Option<String>,
+    ip: String,
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for IngestSource {
+    type Error = ();
+
+    fn from_request(request: &'a rocket::Request<'r>) -> request::Outcome<Self, ()> {
+        Outcome::Success(IngestSource {
+            pod: request
+                .headers()
+                .get_one(input::POD_HEADER)
+                .map(|pod| pod.to_string()),
+            ip: match request.client_ip() {
+                Some(ip) => ip.to_string(),
+                None => "".to_string(),
+            },
+        })
+    }
+}
+
+#[post("/ingest", data = "<data>")]
+pub(crate) fn ingest(source: IngestSource, data: Data) -> status::Custom<JsonValue> {
+    let mut body = String::new();
+    // one byte over the limit tells a body at the limit from a larger one
+    if let Err(e) = data
+        .open()
+        .take(MAX_INGEST_SIZE + 1)
+        .read_to_string(&mut body)
+    {
+        return status::Custom(
+            Status::BadRequest,
+            json!({
+                "status": "error",
+                "reason": format!("{:?}", e),
+            }),
+        );
+    }
+    if body.len() as u64 > MAX_INGEST_SIZE {
+        return status::Custom(
+            Status::PayloadTooLarge,
+            json!({
+                "status": "error",
+                "reason": format!("body larger than {} bytes", MAX_INGEST_SIZE),
+            }),
+        );
+    }
+    status::Custom(
+        Status::Ok,
+        json!(input::ingest(
+            &body,
+            source.pod.as_deref(),
+            &source.ip,
+            &input::InputSink::new(""),
+            &mut std::collections::HashMap::new(),
+        )),
+    )
 }
 
 #[get("/pod/<name>")]
@@ -145,7 +531,7 @@ pub(crate) fn not_found() -> JsonValue {
 
 #[cfg(test)]
 mod tests {
-    use super::Cmd;
+    use super::{run_replay, Cmd, ReplayRequest, Task};
 
     #[test]
     fn cmd_it_works() {
@@ -158,4 +544,207 @@ mod tests {
             }
         };
     }
+
+    #[test]
+    fn cmd_selector_it_works() {
+        let data = r#"{"op":"run","ns":"default","service_name":"xx_service","filter":{"max_length":1024,"expr":""},"output":"fake_output","node_name":"node1","ips":[],"offset":0,"selector":{"namespaces":["default"],"pod_name":"web-*","labels":{"app":"web"}}}"#;
+
+        let cmd = match serde_json::from_str::<Cmd>(data) {
+            Ok(it) => it,
+            Err(e) => {
+                panic!("{:?}", e)
+            }
+        };
+        assert_eq!(cmd.pod_name, "");
+        assert!(cmd.validate().is_ok());
+        assert_eq!(cmd.selector.unwrap().pod_name, "web-*");
+        assert_eq!(cmd.start, db::StartPosition::Resume);
+
+        let data = data.replace(r#""pod_name":"web-*""#, r#""pod_regex":"web-(""#);
+        let cmd = serde_json::from_str::<Cmd>(&data).unwrap();
+        assert!(cmd
+            .validate()
+            .unwrap_err()
+            .starts_with("selector pod regex \"web-(\" invalid"));
+    }
+
+    #[test]
+    fn cmd_redact_it_works() {
+        let data = r#"{"op":"run","ns":"default","service_name":"","filter":{"max_length":0,"expr":""},"output":"fake_output","node_name":"node1","pod_name":"pod-12345","ips":[],"offset":0,"redact":{"builtins":["email"],"fields":["password"]}}"#;
+
+        let cmd = serde_json::from_str::<Cmd>(data).unwrap();
+        assert_eq!(cmd.redact.builtins, vec!["email".to_string()]);
+        assert!(cmd.validate().is_ok());
+        let task = Task::from(cmd);
+        assert_eq!(task.container.redact.fields, vec!["password".to_string()]);
+    }
+
+    #[test]
+    fn cmd_min_level_it_works() {
+        let data = r#"{"op":"run","ns":"default","service_name":"","filter":{"max_length":0,"expr":"","min_level":"warn"},"output":"fake_output","node_name":"node1","pod_name":"pod-12345","ips":[],"offset":0}"#;
+
+        let cmd = serde_json::from_str::<Cmd>(data).unwrap();
+        assert!(cmd.validate().is_ok());
+        assert_eq!(Task::from(cmd).container.filter.min_level, "warn");
+
+        let data = data.replace("\"warn\"", "\"loud\"");
+        let cmd = serde_json::from_str::<Cmd>(&data).unwrap();
+        assert!(cmd.validate().unwrap_err().contains("loud"));
+    }
+
+    #[test]
+    fn cmd_limit_it_works() {
+        let data = r#"{"op":"run","ns":"default","service_name":"","filter":{"max_length":0,"expr":""},"output":"fake_output","node_name":"node1","pod_name":"pod-12345","ips":[],"offset":0,"limit":{"lines_per_sec":100,"sample_rate":0.5}}"#;
+
+        let cmd = serde_json::from_str::<Cmd>(data).unwrap();
+        assert!(cmd.validate().is_ok());
+        assert_eq!(Task::from(cmd).container.limit.lines_per_sec, 100);
+
+        let data = data.replace("0.5", "1.5");
+        let cmd = serde_json::from_str::<Cmd>(&data).unwrap();
+        assert!(cmd.validate().is_err());
+    }
+
+    #[test]
+    fn cmd_dedup_it_works() {
+        let data = r#"{"op":"run","ns":"default","service_name":"","filter":{"max_length":0,"expr":""},"output":"fake_output","node_name":"node1","pod_name":"pod-12345","ips":[],"offset":0,"dedup":{"window":10,"normalize_numbers":true}}"#;
+
+        let cmd = serde_json::from_str::<Cmd>(data).unwrap();
+        assert!(cmd.validate().is_ok());
+        let task = Task::from(cmd);
+        assert!(task.container.dedup.is_enabled());
+        assert!(task.container.dedup.normalize_numbers);
+    }
+
+    #[test]
+    fn cmd_script_it_works() {
+        let data = r#"{"op":"run","ns":"default","service_name":"","filter":{"max_length":0,"expr":""},"output":"fake_output","node_name":"node1","pod_name":"pod-12345","ips":[],"offset":0,"script":{"source":"record.fields.tenant = container.ns;","timeout_ms":5}}"#;
+
+        let cmd = serde_json::from_str::<Cmd>(data).unwrap();
+        assert!(cmd.validate().is_ok());
+        assert_eq!(Task::from(cmd).container.script.timeout_ms, 5);
+
+        let data = data.replace("container.ns;", "(container.ns");
+        let cmd = serde_json::from_str::<Cmd>(&data).unwrap();
+        assert!(cmd.validate().unwrap_err().contains("script compile error"));
+    }
+
+    #[test]
+    fn cmd_plugins_it_works() {
+        let data = r#"{"op":"run","ns":"default","service_name":"","filter":{"max_length":0,"expr":""},"output":"fake_output","node_name":"node1","pod_name":"pod-12345","ips":[],"offset":0,"plugins":[{"path":"/nonexistent/mask.wasm","on_failure":"drop"}]}"#;
+
+        let cmd = serde_json::from_str::<Cmd>(data).unwrap();
+        assert_eq!(cmd.plugins[0].on_failure, db::PluginFailure::Drop);
+        assert!(cmd.validate().unwrap_err().contains("mask.wasm"));
+        assert_eq!(Task::from(cmd).container.plugins.len(), 1);
+    }
+
+    #[test]
+    fn cmd_metrics_it_works() {
+        let data = r#"{"op":"run","ns":"default","service_name":"","filter":{"max_length":0,"expr":""},"output":"fake_output","node_name":"node1","pod_name":"pod-12345","ips":[],"offset":0,"metrics":{"rules":[{"name":"http_errors_total","expr":"status >= 500","labels":{"service":"container.service_name"}}]}}"#;
+
+        let cmd = serde_json::from_str::<Cmd>(data).unwrap();
+        assert!(cmd.validate().is_ok());
+        assert_eq!(cmd.metrics.rules[0].name, "http_errors_total");
+        assert_eq!(Task::from(cmd).container.metrics.rules.len(), 1);
+
+        let data = data.replace("http_errors_total", "http-errors");
+        let cmd = serde_json::from_str::<Cmd>(&data).unwrap();
+        assert_eq!(
+            cmd.validate().unwrap_err(),
+            "invalid metric name \"http-errors\""
+        );
+    }
+
+    #[test]
+    fn cmd_envelope_it_works() {
+        let data = r#"{"op":"run","ns":"default","service_name":"","filter":{"max_length":0,"expr":""},"output":"fake_output","node_name":"node1","pod_name":"pod-12345","ips":[],"offset":0,"envelope":{"schema":"template","template":"{\"msg\":\"${message}\"}"}}"#;
+
+        let cmd = serde_json::from_str::<Cmd>(data).unwrap();
+        assert!(cmd.validate().is_ok());
+        assert_eq!(cmd.envelope.schema, Some(db::EnvelopeSchema::Template));
+        assert_eq!(
+            Task::from(cmd).container.envelope.template,
+            r#"{"msg":"${message}"}"#
+        );
+
+        let data = data.replace(r#"\"}"}}"#, r#"\""}}"#);
+        let cmd = serde_json::from_str::<Cmd>(&data).unwrap();
+        assert!(cmd
+            .validate()
+            .unwrap_err()
+            .starts_with("envelope template error"));
+    }
+
+    #[test]
+    fn cmd_expr_it_works() {
+        let data = r#"{"op":"run","ns":"default","service_name":"","filter":{"max_length":0,"expr":"status >= 500 && path =~ \"^/api\""},"output":"fake_output","node_name":"node1","pod_name":"pod-12345","ips":[],"offset":0}"#;
+
+        let cmd = serde_json::from_str::<Cmd>(data).unwrap();
+        assert_eq!(cmd.validate().unwrap(), Vec::<String>::new());
+
+        // a broken expression rejects the task with its position
+        let broken = data.replace("500 &&", "500 &&&");
+        let cmd = serde_json::from_str::<Cmd>(&broken).unwrap();
+        assert_eq!(
+            cmd.validate().unwrap_err(),
+            "expr error at column 17: unexpected character '&'"
+        );
+
+        // a legacy filter runs as a substring match, reported on the task
+        let legacy = data.replace(r#"status >= 500 && path =~ \"^/api\""#, "[INFO]");
+        let cmd = serde_json::from_str::<Cmd>(&legacy).unwrap();
+        assert_eq!(
+            cmd.validate().unwrap(),
+            vec!["filter \"[INFO]\" is not an expression, matched as a substring of the message"]
+        );
+    }
+
+    #[test]
+    fn cmd_parse_it_works() {
+        let data = r#"{"op":"run","ns":"default","service_name":"","filter":{"max_length":0,"expr":""},"output":"fake_output","node_name":"node1","pod_name":"pod-12345","ips":[],"offset":0,"parse":{"patterns":["%{NGINX_ACCESS}"],"on_failure":"drop"}}"#;
+
+        let cmd = serde_json::from_str::<Cmd>(data).unwrap();
+        assert!(cmd.validate().is_ok());
+        assert_eq!(cmd.parse.on_failure, db::ParseFailure::Drop);
+
+        let data = data.replace("NGINX_ACCESS", "UNKNOWN_PATTERN");
+        let cmd = serde_json::from_str::<Cmd>(&data).unwrap();
+        assert!(cmd.validate().unwrap_err().contains("UNKNOWN_PATTERN"));
+        assert_eq!(Task::from(cmd).container.parse.patterns.len(), 1);
+    }
+
+    #[test]
+    fn cmd_replay_it_works() {
+        let data = r#"{"op":"replay","ns":"default","service_name":"","filter":{"max_length":0,"expr":""},"output":"fake_output","node_name":"node1","pod_name":"pod-12345","ips":[],"offset":0,"since":"2021-02-03T04:00:00Z"}"#;
+
+        let cmd = serde_json::from_str::<Cmd>(data).unwrap();
+        let request = ReplayRequest::from(&cmd);
+        assert_eq!(request.since, "2021-02-03T04:00:00Z");
+        assert_eq!(request.until, "");
+
+        let request = ReplayRequest {
+            since: "yesterday".to_string(),
+            ..request
+        };
+        assert!(run_replay(&request).unwrap_err().contains("invalid since"));
+
+        let request = ReplayRequest {
+            since: "2021-02-03T04:00:00Z".to_string(),
+            until: "2021-02-03T03:00:00Z".to_string(),
+            ..request
+        };
+        assert!(run_replay(&request).unwrap_err().contains("is after until"));
+    }
+
+    #[test]
+    fn cmd_start_it_works() {
+        let data = r#"{"op":"run","ns":"default","service_name":"xx_service","filter":{"max_length":1024,"expr":""},"output":"fake_output","node_name":"node1","pod_name":"pod-12345","ips":[],"offset":0,"start":"last_lines:10000"}"#;
+
+        let cmd = serde_json::from_str::<Cmd>(data).unwrap();
+        assert_eq!(cmd.start, db::StartPosition::LastLines(10000));
+
+        let data = data.replace("last_lines:10000", "since:yesterday");
+        assert!(serde_json::from_str::<Cmd>(&data).is_err());
+    }
 }
@@ -0,0 +1,80 @@
+use crate::handle::file_size;
+use db::{Container, StartPosition};
+use file::FileReaderWriter;
+use std::collections::HashSet;
+use std::thread;
+use std::time::Duration;
+
+// file_container derives the container of a file inside `container`, it
+// shares the metadata and the task config of the container
+fn file_container(container: &Container, source: String, host_path: String) -> Container {
+    // the start position of the task applies to files never read
+    // a truncated or rotated file is read again from its beginning
+    let (offset, start) = match db::get(&host_path) {
+        Some(known) if known.offset > file_size(&host_path) => (0, StartPosition::Resume),
+        Some(known) if known.offset > 0 => (known.offset, StartPosition::Resume),
+        _ => (0, container.start.clone()),
+    };
+    Container {
+        path: host_path,
+        source,
+        paths: vec![],
+        offset,
+        last_offset: 0,
+        start,
+        ..container.clone()
+    }
+}
+
+/// sync_container_files opens a reader for every file matching the task paths
+/// of a collected container, and closes the files no longer matching.
+pub(crate) fn sync_container_files(frw: &FileReaderWriter) -> usize {
+    let containers = db::all_to_json().0;
+    let mut changes = 0;
+    let mut files = HashSet::new();
+
+    for container in containers.iter().filter(|container| {
+        container.source.is_empty()
+            && !container.paths.is_empty()
+            && container.is_upload()
+            && container.is_running()
+    }) {
+        // volumes still resolve when the storage driver is not overlay2
+        let merged_dir = scan::docker_root(&container.path)
+            .and_then(|root| scan::merged_dir(&root, &container.container_id))
+            .unwrap_or_default();
+        for (source, host_path) in
+            scan::resolve_paths(&merged_dir, &container.mounts, &container.paths)
+        {
+            files.insert(host_path.clone());
+            if frw.is_open(&host_path) {
+                continue;
+            }
+            let mut file_container = file_container(container, source, host_path);
+            db::insert(&file_container);
+            frw.open_event(&mut file_container);
+            changes += 1;
+        }
+    }
+
+    for container in containers.iter().filter(|container| {
+        !container.source.is_empty()
+            && !container.container_id.is_empty()
+            && !files.contains(&container.path)
+    }) {
+        println!(
+            "[INFO] close file {:?} of pod {:?} container {:?}",
+            &container.source, &container.pod_name, &container.container
+        );
+        frw.remove_event(&container.path);
+        changes += 1;
+    }
+    changes
+}
+
+pub(crate) fn start_container_files(frw: FileReaderWriter, interval: Duration) {
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+        sync_container_files(&frw);
+    });
+}
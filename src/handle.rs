@@ -1,8 +1,16 @@
-use crate::{get_pod_task, GetTask};
-use db::GetContainer;
+use crate::{get_container_task, GetTask};
+use db::{GetContainer, StartPosition};
 use event::Listener;
 use file::FileReaderWriter;
 use scan::GetPathEventInfo;
+use std::fs;
+
+pub(crate) fn file_size(path: &str) -> i64 {
+    match fs::metadata(path) {
+        Ok(metadata) => metadata.len() as i64,
+        Err(_) => 0,
+    }
+}
 
 pub(crate) struct DBOpenEvent(pub FileReaderWriter);
 impl<T> Listener<T> for DBOpenEvent
@@ -10,7 +18,10 @@ where
     T: Clone + GetContainer,
 {
     fn handle(&self, t: T) {
-        self.0.open_event(&mut t.get().unwrap().clone())
+        let mut container = t.get().unwrap().clone();
+        // pods annotated with an output may name a kafka output
+        output::registry_kafka_output(&container.output);
+        self.0.open_event(&mut container)
     }
 }
 
@@ -31,14 +42,33 @@ where
 {
     fn handle(&self, t: T) {
         let mut container = t.get().to_pod();
+        // a container already read resumes from its offset and a truncated or
+        // rotated file from its beginning, the task start position only
+        // applies to files never read
+        let known_offset = match db::get(&container.path) {
+            Some(known) if known.offset > file_size(&container.path) => Some(0),
+            Some(known) if known.offset > 0 => Some(known.offset),
+            _ => None,
+        };
         db::insert(&container);
-        if let Some(t) = get_pod_task(&container.pod_name) {
+        if let Some(t) = get_container_task(&container) {
             if !t.container.is_upload() {
                 return;
             }
-            self.0.open_event(&mut container);
-            self.0.write_event(&container.path)
+            container.merge_with(&t.container).upload().state_running();
+        } else if container.is_upload() {
+            // collect pods annotated with an output
+            output::registry_kafka_output(&container.output);
+            container.state_running();
+        } else {
+            return;
         }
+        if let Some(offset) = known_offset {
+            container.offset = offset;
+            container.start = StartPosition::Resume;
+        }
+        self.0.open_event(&mut container);
+        self.0.write_event(&container.path)
     }
 }
 
@@ -52,6 +82,36 @@ where
     }
 }
 
+pub(crate) struct ScannerStateEvent(pub FileReaderWriter);
+impl<T> Listener<T> for ScannerStateEvent
+where
+    T: Clone + GetPathEventInfo,
+{
+    fn handle(&self, t: T) {
+        let pei = t.get();
+        let event = match &pei.state_event {
+            Some(event) => event,
+            None => return,
+        };
+        let mut container = match db::get(&pei.path) {
+            Some(container) => container,
+            None => pei.to_pod(),
+        };
+        if !container.is_upload() {
+            match get_container_task(&container) {
+                Some(t) if t.container.is_upload() => {
+                    container.merge_with(&t.container).upload();
+                }
+                _ => return,
+            }
+        }
+        match serde_json::to_value(event) {
+            Ok(record) => self.0.record_event(&container, record),
+            Err(e) => eprintln!("[ERROR] encode container state event error: {:?}", e),
+        }
+    }
+}
+
 pub(crate) struct ScannerCloseEvent();
 impl<T> Listener<T> for ScannerCloseEvent
 where
@@ -71,6 +131,17 @@ where
 {
     fn handle(&self, t: T) {
         let mut container = t.get().container.clone();
+        if self.0.is_open(&container.path) {
+            // the open reader takes the new task settings from the db and
+            // keeps reading from its own offset
+            if let Some(current) = db::get(&container.path) {
+                container.offset = current.offset;
+                container.last_offset = current.last_offset;
+            }
+            container.start = StartPosition::Resume;
+            db::update(&container);
+            return;
+        }
         self.0.open_event(&mut container);
     }
 }
@@ -8,19 +8,23 @@ extern crate rocket;
 extern crate lazy_static;
 
 mod api;
+mod files;
 mod handle;
 mod server;
+mod tail;
 
-use db::Container;
+use db::{Container, Selector};
 use event::{Dispatch, Listener};
 pub use serde_json;
 
 pub(crate) use api::*;
+pub(crate) use files::start_container_files;
+pub(crate) use tail::*;
 
 pub use common::{new_arc_rwlock, Result};
 pub(crate) use handle::{
-    DBCloseEvent, DBOpenEvent, ScannerCloseEvent, ScannerCreateEvent, ScannerWriteEvent,
-    TaskRunEvent, TaskStopEvent,
+    DBCloseEvent, DBOpenEvent, ScannerCloseEvent, ScannerCreateEvent, ScannerStateEvent,
+    ScannerWriteEvent, TaskRunEvent, TaskStopEvent,
 };
 pub use server::Harvest;
 
@@ -52,6 +56,28 @@ pub(crate) trait GetTask {
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub(crate) struct Task {
     pub(crate) container: Container,
+    #[serde(default)]
+    pub(crate) selector: Option<Selector>,
+    // the config problems the task runs with, reported on /tasks
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) warnings: Vec<String>,
+}
+
+impl Task {
+    // selector tasks are stored by selector key, pod tasks by pod name
+    pub(crate) fn key(&self) -> String {
+        match &self.selector {
+            Some(selector) => selector.key(),
+            None => self.container.pod_name.clone(),
+        }
+    }
+
+    pub(crate) fn containers(&self) -> Vec<(String, Container)> {
+        match &self.selector {
+            Some(selector) => db::get_container_slice_by_selector(selector),
+            None => db::get_container_slice_by_pod(&self.container.ns, &self.container.pod_name),
+        }
+    }
 }
 
 impl GetTask for Task {
@@ -80,8 +106,21 @@ impl<'a> From<Cmd<'a>> for Task {
                 ips: to_string_slice(&cmd.ips),
                 output: cmd.output.to_string(),
                 filter: cmd.filter.clone(),
+                enrich: cmd.enrich.clone(),
+                paths: cmd.paths.clone(),
+                start: cmd.start.clone(),
+                redact: cmd.redact.clone(),
+                parse: cmd.parse.clone(),
+                limit: cmd.limit.clone(),
+                dedup: cmd.dedup.clone(),
+                script: cmd.script.clone(),
+                plugins: cmd.plugins.clone(),
+                metrics: cmd.metrics.clone(),
+                envelope: cmd.envelope.clone(),
                 ..Default::default()
             },
+            selector: cmd.selector.clone(),
+            warnings: vec![],
         }
     }
 }
@@ -90,6 +129,8 @@ impl Default for Task {
     fn default() -> Self {
         Self {
             container: Container::default(),
+            selector: None,
+            warnings: vec![],
         }
     }
 }
@@ -177,21 +218,22 @@ impl TaskStorage {
                             }
                         };
 
-                        let pod_slice = db::get_container_slice_by_pod(
-                            &task.container.ns,
-                            &task.container.pod_name,
-                        );
+                        let template = task.container.clone();
+                        let containers = task.containers();
+                        if task.selector.is_some() {
+                            // keep the selector so containers created later are collected
+                            task.container.upload().state_running();
+                            tasks.insert(task.key(), task.clone());
+                        }
 
-                        for (_, mut container) in pod_slice {
-                            container
-                                .merge_with(&task.container)
-                                .upload()
-                                .state_running();
+                        for (_, mut container) in containers {
+                            container.merge_with(&template).upload().state_running();
 
                             task.container = container;
-                            tasks
-                                .entry(task.container.pod_name.clone())
-                                .or_insert(task.clone());
+                            if task.selector.is_none() {
+                                // a rerun replaces the settings of the running task
+                                tasks.insert(task.container.pod_name.clone(), task.clone());
+                            }
                             match t_dispatchers.write() {
                                 Ok(mut dispatch) => dispatch.dispatch_run_event(&task),
                                 Err(e) => eprintln!("{}", e),
@@ -206,15 +248,26 @@ impl TaskStorage {
                                 continue;
                             }
                         };
-                        for (_, mut container) in db::get_container_slice_by_pod(
-                            &task.container.ns,
-                            &task.container.pod_name,
-                        ) {
+
+                        if let Some(selector) = &task.selector {
+                            tasks.remove(&task.key());
+                            let in_use = tasks.values().any(|item| match &item.selector {
+                                Some(other) => other.pod_regex == selector.pod_regex,
+                                None => false,
+                            });
+                            if !selector.pod_regex.is_empty() && !in_use {
+                                db::evict_pod_regex(&selector.pod_regex);
+                            }
+                        }
+
+                        for (_, mut container) in task.containers() {
                             container.un_upload().state_stop();
                             task.container = container;
-                            tasks
-                                .entry(task.container.pod_name.clone())
-                                .or_insert(task.clone());
+                            if task.selector.is_none() {
+                                tasks
+                                    .entry(task.container.pod_name.clone())
+                                    .or_insert(task.clone());
+                            }
                             match t_dispatchers.write() {
                                 Ok(mut dispatch) => dispatch.dispatch_stop_event(&task),
                                 Err(e) => eprintln!("{}", e),
@@ -255,6 +308,18 @@ pub(crate) fn tasks_json() -> TaskListMarshaller {
     TaskListMarshaller(tasks())
 }
 
+pub(crate) fn ns_tasks_json(ns: &str) -> TaskListMarshaller {
+    TaskListMarshaller(
+        tasks()
+            .into_iter()
+            .filter(|task| match &task.selector {
+                Some(selector) => selector.matches_namespace(ns),
+                None => task.container.ns == ns,
+            })
+            .collect(),
+    )
+}
+
 pub(crate) fn tasks() -> TaskList {
     if let Ok(tasks) = TASKS.data.read() {
         return tasks.iter().map(|(_, v)| v.clone()).collect::<Vec<Task>>();
@@ -282,11 +347,19 @@ where
     }
 }
 
-pub(crate) fn get_pod_task(pod_name: &str) -> Option<Task> {
+// get_container_task returns the pod task of the container, or the first
+// selector task matching it
+pub(crate) fn get_container_task(container: &Container) -> Option<Task> {
     match TASKS.data.read() {
-        Ok(db) => match db.get(pod_name) {
+        Ok(db) => match db.get(&container.pod_name) {
             Some(task) => Some(task.clone()),
-            None => None,
+            None => db
+                .values()
+                .find(|task| match &task.selector {
+                    Some(selector) => selector.matches(container),
+                    None => false,
+                })
+                .cloned(),
         },
         Err(e) => {
             eprintln!("{}", e);
@@ -297,8 +370,83 @@ pub(crate) fn get_pod_task(pod_name: &str) -> Option<Task> {
 
 #[cfg(test)]
 mod tests {
+    use super::{new_arc_rwlock, Task, TaskMessage, TaskStorage, TaskStorageEventDispatcher};
+    use db::Container;
+    use event::Listener;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    struct Recorder(Arc<Mutex<Vec<Task>>>);
+    impl Listener<Task> for Recorder {
+        fn handle(&self, t: Task) {
+            self.0.lock().unwrap().push(t);
+        }
+    }
+
     #[test]
     fn it_works() {
         assert_eq!(2 + 2, 4);
     }
+
+    #[test]
+    fn run_replaces_task() {
+        let container = Container {
+            ns: "default".to_string(),
+            pod_name: "web-rerun-abcde".to_string(),
+            container: "nginx".to_string(),
+            path: "/var/lib/docker/containers/rerun/rerun-json.log".to_string(),
+            ..Default::default()
+        };
+        db::insert(&container);
+        for _ in 0..100 {
+            if db::get(&container.path).is_some() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let runs = Arc::new(Mutex::new(vec![]));
+        let dispatchers = new_arc_rwlock(TaskStorageEventDispatcher::new());
+        dispatchers
+            .write()
+            .unwrap()
+            .registry_run_event_listener(Recorder(runs.clone()));
+        let storage = TaskStorage::new(dispatchers);
+
+        let mut task = Task {
+            container: Container {
+                ns: container.ns.clone(),
+                pod_name: container.pod_name.clone(),
+                output: "fake_output".to_string(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        storage.tx.send(TaskMessage::Run(task.clone())).unwrap();
+        task.container.filter.expr = "\"[ERROR]\" in message".to_string();
+        storage.tx.send(TaskMessage::Run(task)).unwrap();
+        for _ in 0..100 {
+            if runs.lock().unwrap().len() == 2 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        // the second run replaces the stored task and reaches the readers
+        let runs = runs.lock().unwrap();
+        assert_eq!(runs.len(), 2);
+        assert_eq!(runs[1].container.path, container.path);
+        assert_eq!(runs[1].container.filter.expr, "\"[ERROR]\" in message");
+        assert_eq!(
+            storage.data.read().unwrap()[&container.pod_name]
+                .container
+                .filter
+                .expr,
+            "\"[ERROR]\" in message"
+        );
+
+        storage.tx.send(TaskMessage::Close).unwrap();
+        db::delete(&container.path);
+    }
 }
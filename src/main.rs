@@ -5,9 +5,14 @@ use structopt::StructOpt;
 #[derive(Debug, StructOpt)]
 pub struct ServerOptions {
     // short and long flags (-n, --namespace) will be deduced from the field's name
+    // comma separated namespace list, `*` collects all namespaces and empty none
     #[structopt(short, env = "NAMESPACE", default_value = "", long)]
     namespace: String,
 
+    // short and long flags (-e, --exclude-namespace) will be deduced from the field's name
+    #[structopt(short, env = "EXCLUDE_NAMESPACE", default_value = "", long)]
+    exclude_namespace: String,
+
     // // short and long flags (-s, --api-server) will be deduced from the field's name
     #[structopt(short = "s", env = "API_SERVER", default_value = "", long)]
     api_server: String,
@@ -23,6 +28,66 @@ pub struct ServerOptions {
     // short and long flags (-b, --buffer_size) will be deduced from the field's name
     #[structopt(short = "b", env = "BUFFER_SIZE", default_value = "100000", long)]
     buffer_size: usize,
+
+    // short and long flags (-k, --kubelet-pods) will be deduced from the field's name
+    // kubelet read-only endpoint (http://127.0.0.1:10255/pods) or a PodList json file
+    #[structopt(short, env = "KUBELET_PODS", default_value = "", long)]
+    kubelet_pods: String,
+
+    // long flags (--kubelet-interval) will be deduced from the field's name
+    #[structopt(env = "KUBELET_INTERVAL", default_value = "10", long)]
+    kubelet_interval: u64,
+
+    // long flags (--reconcile-interval) will be deduced from the field's name
+    // seconds between two rescans of the docker dir, 0 disables the reconciler
+    #[structopt(env = "RECONCILE_INTERVAL", default_value = "60", long)]
+    reconcile_interval: u64,
+
+    // short and long flags (-w, --watcher) will be deduced from the field's name
+    // auto, inotify or poll; auto falls back to polling when inotify fails
+    #[structopt(short, env = "WATCHER", default_value = "auto", long)]
+    watcher: String,
+
+    // long flags (--poll-interval-ms) will be deduced from the field's name
+    #[structopt(env = "POLL_INTERVAL_MS", default_value = "1000", long)]
+    poll_interval_ms: u64,
+
+    // long flags (--host-paths) will be deduced from the field's name
+    // comma separated host glob paths, e.g. /var/log/*.log,/var/log/containerd/*.log
+    #[structopt(env = "HOST_PATHS", default_value = "", long)]
+    host_paths: String,
+
+    // long flags (--journal) will be deduced from the field's name
+    // `journalctl` follows `journalctl -o export`, any other value is an export file
+    #[structopt(env = "JOURNAL", default_value = "", long)]
+    journal: String,
+
+    // long flags (--host-output) will be deduced from the field's name
+    // output of the host paths and journal records, empty disables host inputs
+    #[structopt(env = "HOST_OUTPUT", default_value = "", long)]
+    host_output: String,
+
+    // long flags (--host-redact) will be deduced from the field's name
+    // comma separated builtin redactions of the host records, e.g. email,phone
+    #[structopt(env = "HOST_REDACT", default_value = "", long)]
+    host_redact: String,
+
+    // long flags (--ingest-addr) will be deduced from the field's name
+    // tcp address accepting newline delimited json, empty disables the listener
+    #[structopt(env = "INGEST_ADDR", default_value = "", long)]
+    ingest_addr: String,
+
+    // long flags (--plugin-dir) will be deduced from the field's name
+    // directory of the webassembly plugins tasks load by name, empty disables them
+    #[structopt(env = "PLUGIN_DIR", default_value = "", long)]
+    plugin_dir: String,
+
+    // long flags (--output-envelopes) will be deduced from the field's name
+    // record schema of outputs, e.g. kafka:logs=ecs,kafka:events=cloudevents,
+    // legacy, flat, ecs, otel or cloudevents, tasks may set their own, the
+    // ingest, host file and journal records follow it too
+    #[structopt(env = "OUTPUT_ENVELOPES", default_value = "", long)]
+    output_envelopes: String,
 }
 // cargo run -- --namespace default --docker_dir /var/log/container --api-server http://localhost:9999/ --host node1
 
@@ -32,5 +97,20 @@ fn main() -> Result<()> {
     unsafe {
         GLOBAL_BUFFER_SIZE = opt.buffer_size;
     }
-    Harvest::new(&opt.namespace, &opt.docker_dir, &opt.api_server, &opt.host).start()
+    Harvest::new(
+        &opt.namespace,
+        &opt.exclude_namespace,
+        &opt.docker_dir,
+        &opt.api_server,
+        &opt.host,
+    )
+    .kubelet_pods(&opt.kubelet_pods, opt.kubelet_interval)
+    .reconcile_interval(opt.reconcile_interval)
+    .watcher(&opt.watcher, opt.poll_interval_ms)
+    .host_inputs(&opt.host_paths, &opt.journal, &opt.host_output)
+    .host_redact(&opt.host_redact)
+    .ingest_addr(&opt.ingest_addr)
+    .plugin_dir(&opt.plugin_dir)
+    .output_envelopes(&opt.output_envelopes)
+    .start()
 }
@@ -2,46 +2,230 @@ use super::*;
 use async_std::task;
 use crossbeam::sync::WaitGroup;
 use file::FileReaderWriter;
+use input::{HostFilesInput, InputSink, JournalInput, JournalSource, TcpInput};
 use rocket::config::{Config, Environment};
 use rocket::routes;
-use scan::AutoScanner;
+use scan::{AutoScanner, Namespaces, PodMetaSource, WatchBackend};
+use std::convert::TryFrom;
+use std::time::Duration;
+
+// seconds between two resolutions of the in-container task paths
+const CONTAINER_FILES_INTERVAL: u64 = 5;
+
+const HOST_FILES_INPUT: &str = "host_files";
+const JOURNAL_INPUT: &str = "journal";
+const TCP_INPUT: &str = "tcp";
 
 pub struct Harvest<'a> {
     node_name: &'a str,
     namespace: &'a str,
+    exclude_namespace: &'a str,
     docker_dir: &'a str,
     api_server_addr: &'a str,
+    kubelet_pods: &'a str,
+    kubelet_interval: u64,
+    reconcile_interval: u64,
+    watcher: &'a str,
+    poll_interval: Duration,
+    host_paths: &'a str,
+    journal: &'a str,
+    host_output: &'a str,
+    host_redact: &'a str,
+    ingest_addr: &'a str,
+    plugin_dir: &'a str,
+    output_envelopes: &'a str,
 }
 
 impl<'a> Harvest<'a> {
     pub fn new(
         namespace: &'a str,
+        exclude_namespace: &'a str,
         docker_dir: &'a str,
         api_server_addr: &'a str,
         node_name: &'a str,
     ) -> Self {
         Self {
             namespace,
+            exclude_namespace,
             docker_dir,
             node_name,
             api_server_addr,
+            kubelet_pods: "",
+            kubelet_interval: 10,
+            reconcile_interval: 60,
+            watcher: "auto",
+            poll_interval: Duration::from_secs(1),
+            host_paths: "",
+            journal: "",
+            host_output: "",
+            host_redact: "",
+            ingest_addr: "",
+            plugin_dir: "",
+            output_envelopes: "",
+        }
+    }
+
+    // kubelet_pods enables pod metadata from the kubelet `/pods` endpoint or a file
+    pub fn kubelet_pods(mut self, source: &'a str, interval: u64) -> Self {
+        self.kubelet_pods = source;
+        self.kubelet_interval = interval;
+        self
+    }
+
+    // reconcile_interval sets the seconds between two rescans, 0 disables it
+    pub fn reconcile_interval(mut self, interval: u64) -> Self {
+        self.reconcile_interval = interval;
+        self
+    }
+
+    // watcher selects the inotify or polling backend of the scanner
+    pub fn watcher(mut self, backend: &str, poll_interval_ms: u64) -> Self {
+        self.watcher = backend;
+        self.poll_interval = Duration::from_millis(poll_interval_ms);
+        self
+    }
+
+    // host_inputs collects the host files matching the comma separated glob
+    // paths and the journal (`journalctl` or an export file) to the output
+    pub fn host_inputs(mut self, paths: &'a str, journal: &'a str, output: &'a str) -> Self {
+        self.host_paths = paths;
+        self.journal = journal;
+        self.host_output = output;
+        self
+    }
+
+    // host_redact masks the comma separated builtin detectors, such as
+    // email,phone,credit_card, in the host files and journal records
+    pub fn host_redact(mut self, builtins: &'a str) -> Self {
+        self.host_redact = builtins;
+        self
+    }
+
+    // ingest_addr listens for newline delimited json pushed over tcp, such as 0.0.0.0:5170
+    pub fn ingest_addr(mut self, addr: &'a str) -> Self {
+        self.ingest_addr = addr;
+        self
+    }
+
+    // plugin_dir loads the webassembly plugins of the tasks from the directory
+    pub fn plugin_dir(mut self, dir: &'a str) -> Self {
+        self.plugin_dir = dir;
+        self
+    }
+
+    // output_envelopes sets the record schema of outputs, `<output>=<schema>`
+    // comma separated, for the tasks that set none
+    pub fn output_envelopes(mut self, envelopes: &'a str) -> Self {
+        self.output_envelopes = envelopes;
+        self
+    }
+
+    // host_container is the task config of the host inputs
+    fn host_container(&self) -> Container {
+        Container {
+            node_name: self.node_name.to_string(),
+            redact: db::Redact {
+                builtins: self
+                    .host_redact
+                    .split(',')
+                    .map(|builtin| builtin.trim())
+                    .filter(|builtin| !builtin.is_empty())
+                    .map(|builtin| builtin.to_string())
+                    .collect(),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    fn start_host_inputs(&self) {
+        if self.host_output == "" {
+            return;
+        }
+        output::registry_kafka_output(self.host_output);
+        let sink = InputSink::new(self.host_output).with_pipeline(&self.host_container());
+
+        let patterns = self
+            .host_paths
+            .split(',')
+            .map(|pattern| pattern.trim())
+            .filter(|pattern| !pattern.is_empty())
+            .map(|pattern| pattern.to_string())
+            .collect::<Vec<String>>();
+        if !patterns.is_empty() {
+            input::registry_input(
+                HOST_FILES_INPUT,
+                HostFilesInput::new(
+                    patterns,
+                    self.node_name,
+                    Duration::from_secs(CONTAINER_FILES_INTERVAL),
+                ),
+            );
+            input::input_start(HOST_FILES_INPUT, sink.clone());
+        }
+        if self.journal != "" {
+            input::registry_input(
+                JOURNAL_INPUT,
+                JournalInput::new(JournalSource::from(self.journal), self.node_name),
+            );
+            input::input_start(JOURNAL_INPUT, sink);
         }
     }
 
     pub fn start(&mut self) -> Result<()> {
+        let watch_backend = WatchBackend::try_from(self.watcher)?;
+        filter::Redactor::new(&self.host_container().redact)?;
+        if self.plugin_dir != "" {
+            match filter::load_plugin_dir(self.plugin_dir) {
+                Ok(count) => println!("[INFO] load {:?} plugins from {:?}", count, self.plugin_dir),
+                Err(e) => eprintln!("[ERROR] load plugins error: {}", e),
+            }
+        }
+        if self.output_envelopes != "" {
+            match file::set_output_envelopes(self.output_envelopes) {
+                Ok(count) => println!("[INFO] set envelopes of {:?} outputs", count),
+                Err(e) => eprintln!("[ERROR] set output envelopes error: {}", e),
+            }
+        }
+        if self.kubelet_pods != "" {
+            let source = PodMetaSource::from(self.kubelet_pods);
+            match scan::refresh_pod_meta(&source) {
+                Ok(count) => println!("[INFO] load {:?} pods metadata from {:?}", count, source),
+                Err(e) => eprintln!(
+                    "[ERROR] load pods metadata from {:?} error: {:?}",
+                    source, e
+                ),
+            }
+            scan::start_pod_meta_provider(source, Duration::from_secs(self.kubelet_interval));
+        }
+
         let scanner = new_arc_rwlock(AutoScanner::new(
-            String::from(self.namespace),
+            Namespaces::new(self.namespace, self.exclude_namespace),
             String::from(self.docker_dir),
         ));
 
         // on kubernetes the kubelet default 110 pod in every node
-        let frw = FileReaderWriter::new(110);
+        let mut frw = FileReaderWriter::new(110);
+        if watch_backend == WatchBackend::Poll {
+            frw = frw.with_poll_interval(self.poll_interval);
+        }
 
         // registry scanner event handle
         if let Ok(mut scan) = scanner.write() {
+            scan.set_watch_backend(watch_backend, self.poll_interval);
+            // readers poll as well once auto falls back to polling
+            let fallback_frw = frw.clone();
+            scan.set_poll_fallback(move |interval| fallback_frw.set_poll_interval(interval));
             scan.append_create_event_handle(ScannerCreateEvent(frw.clone()));
             scan.append_write_event_handle(ScannerWriteEvent(frw.clone()));
             scan.append_close_event_handle(ScannerCloseEvent());
+            scan.append_state_event_handle(ScannerStateEvent(frw.clone()));
+            if self.reconcile_interval > 0 {
+                let reconcile_frw = frw.clone();
+                scan.set_reconcile(Duration::from_secs(self.reconcile_interval), move |path| {
+                    reconcile_frw.is_open(path)
+                });
+            }
         }
 
         // registry db open/close events
@@ -52,10 +236,22 @@ impl<'a> Harvest<'a> {
         registry_task_run_event_listener(TaskRunEvent(frw.clone()));
         registry_task_stop_event_listener(TaskStopEvent(frw.clone()));
 
+        // collect the host files and the journal
+        self.start_host_inputs();
+
+        if self.ingest_addr != "" {
+            input::registry_input(TCP_INPUT, TcpInput::new(self.ingest_addr));
+            input::input_start(TCP_INPUT, InputSink::new(""));
+        }
+
+        // collect the log files written inside containers
+        start_container_files(frw.clone(), Duration::from_secs(CONTAINER_FILES_INTERVAL));
+
         let wg = WaitGroup::new();
         let wg1 = wg.clone();
 
         let mut tasks = vec![];
+        let scan_frw = frw.clone();
         // start auto scanner with a new async
         tasks.push(task::spawn(async move {
             let mut scan = match scanner.write() {
@@ -76,7 +272,13 @@ impl<'a> Harvest<'a> {
 
             // add to local MemDatabase
             for item in res.iter() {
-                db::insert(&item.to_pod())
+                let mut container = item.to_pod();
+                db::insert(&container);
+                // pods annotated with an output are collected without a task
+                if container.is_upload() {
+                    output::registry_kafka_output(&container.output);
+                    scan_frw.open_event(&mut container);
+                }
             }
 
             drop(wg1);
@@ -87,6 +289,7 @@ impl<'a> Harvest<'a> {
             }
         }));
 
+        let rocket_frw = frw.clone();
         tasks.push(task::spawn(async move {
             let cfg = Config::build(Environment::Production)
                 .address("0.0.0.0")
@@ -95,7 +298,23 @@ impl<'a> Harvest<'a> {
                 .unwrap();
 
             rocket::custom(cfg)
-                .mount("/", routes![query_pod, query_tasks, query_all_pod])
+                .mount(
+                    "/",
+                    routes![
+                        query_pod,
+                        query_tasks,
+                        query_all_pod,
+                        query_quarantine,
+                        query_metrics,
+                        query_redactions,
+                        ingest,
+                        replay,
+                        query_replay,
+                        query_replays,
+                        tail
+                    ],
+                )
+                .manage(rocket_frw)
                 .register(catchers![not_found])
                 .launch();
         }));
@@ -106,6 +325,7 @@ impl<'a> Harvest<'a> {
         recv_tasks(&self.api_server_addr, &self.node_name);
         for _ in tasks {}
         task_close();
+        input::input_stop_all();
 
         output::output_wait_all();
         Ok(())
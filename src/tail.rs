@@ -0,0 +1,151 @@
+use db::Container;
+use file::{FileReaderWriter, TapEvent, TapReceiver};
+use filter::ContainsFilter;
+use rocket::http::ContentType;
+use rocket::response::content::Content;
+use rocket::response::Stream;
+use rocket::State;
+use std::io::{self, Read};
+use std::sync::Arc;
+use std::time::Duration;
+
+// an idle stream sends a comment on this interval, a write to a closed
+// client fails and detaches the tail
+const TAIL_KEEPALIVE: Duration = Duration::from_secs(15);
+const TAIL_CHUNK_SIZE: u64 = 4096;
+
+/// TailStream is the `text/event-stream` body of a tail, every record is a
+/// `data:` event flushed to the client as soon as it is read.
+pub(crate) struct TailStream {
+    frw: FileReaderWriter,
+    containers: Vec<Container>,
+    receiver: TapReceiver,
+    pending: Vec<u8>,
+    // bytes written since the last flush
+    sent: usize,
+}
+
+impl TailStream {
+    fn new(frw: FileReaderWriter, containers: Vec<Container>, receiver: TapReceiver) -> Self {
+        let stream = Self {
+            frw,
+            containers,
+            receiver,
+            pending: b": tail\n\n".to_vec(),
+            sent: 0,
+        };
+        stream.open_readers();
+        stream
+    }
+
+    // the containers not collected by a task, or no longer collected, are
+    // read by a tap-only reader
+    fn open_readers(&self) {
+        for container in self.containers.iter() {
+            self.frw.tap_event(container);
+        }
+    }
+}
+
+impl Read for TailStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pending.is_empty() {
+            if self.sent > 0 {
+                // rocket flushes the chunk when the body would block, but ends
+                // the body if nothing was read in the chunk yet
+                if self.sent % TAIL_CHUNK_SIZE as usize != 0 {
+                    self.sent = 0;
+                    return Err(io::Error::from(io::ErrorKind::WouldBlock));
+                }
+                self.pending = b"\n".to_vec();
+            } else {
+                self.pending = match self.receiver.next(TAIL_KEEPALIVE) {
+                    TapEvent::Record(record) => format!("data: {}\n\n", record).into_bytes(),
+                    TapEvent::Idle => {
+                        self.open_readers();
+                        b": keepalive\n\n".to_vec()
+                    }
+                };
+            }
+        }
+        let size = self.pending.len().min(buf.len());
+        buf[..size].copy_from_slice(&self.pending[..size]);
+        self.pending.drain(..size);
+        self.sent += size;
+        Ok(size)
+    }
+}
+
+/// tail streams the records of the pod as server-sent events, `filter` keeps
+/// the lines containing it and `rate` limits the records per second.
+#[get("/tail/<ns>/<pod>?<container>&<filter>&<rate>")]
+pub(crate) fn tail(
+    frw: State<FileReaderWriter>,
+    ns: String,
+    pod: String,
+    container: Option<String>,
+    filter: Option<String>,
+    rate: Option<u64>,
+) -> Option<Content<Stream<TailStream>>> {
+    let containers = db::get_container_slice_by_pod(&ns, &pod)
+        .into_iter()
+        .map(|(_, c)| c)
+        .filter(|c| match &container {
+            Some(name) => &c.container == name,
+            None => true,
+        })
+        .collect::<Vec<Container>>();
+    if containers.is_empty() {
+        return None;
+    }
+
+    let filter = filter.map(|pattern| {
+        Arc::new(ContainsFilter::new(&pattern)) as Arc<dyn filter::Filter + Send + Sync>
+    });
+    let mut receiver = TapReceiver::new(filter, rate.unwrap_or(0));
+    for c in containers.iter() {
+        receiver.attach(&c.path);
+    }
+    println!(
+        "[INFO] tail pod {:?} in {:?}, paths {:?}",
+        pod,
+        ns,
+        receiver.paths()
+    );
+
+    Some(Content(
+        ContentType::new("text", "event-stream"),
+        Stream::chunked(
+            TailStream::new(frw.inner().clone(), containers, receiver),
+            TAIL_CHUNK_SIZE,
+        ),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TailStream, TAIL_CHUNK_SIZE};
+    use file::{FileReaderWriter, TapReceiver};
+    use std::io::{ErrorKind, Read};
+
+    #[test]
+    fn flush_it_works() {
+        let mut stream =
+            TailStream::new(FileReaderWriter::new(1), vec![], TapReceiver::new(None, 0));
+        let mut buf = vec![0; TAIL_CHUNK_SIZE as usize];
+        assert_eq!(stream.read(&mut buf).unwrap(), 8);
+        assert_eq!(&buf[..8], b": tail\n\n");
+        assert_eq!(
+            stream.read(&mut buf[8..]).unwrap_err().kind(),
+            ErrorKind::WouldBlock
+        );
+
+        // a chunk filled to the end is flushed with the next chunk
+        stream.sent = TAIL_CHUNK_SIZE as usize;
+        assert_eq!(stream.read(&mut buf).unwrap(), 1);
+        assert_eq!(
+            stream.read(&mut buf[1..]).unwrap_err().kind(),
+            ErrorKind::WouldBlock
+        );
+    }
+}